@@ -0,0 +1,60 @@
+// Compares `find` throughput between the boxed `BTree` and the arena-backed `ArenaBTree` on the
+// same 10M-key workload as `main.rs`. Run with `cargo run --release --example arena_find`.
+extern crate catdb_lib;
+
+use std::time::Instant;
+
+use catdb_lib::trees::{ArenaBTree, BTree};
+
+const NUM_KEYS: u64 = 10_000_000;
+const NUM_FINDS: u64 = 5_000_000;
+
+fn sequential(i: u64) -> u64 {
+    i
+}
+
+// a fixed pseudo-random probe order, so lookups jump around the tree instead of walking it
+fn scattered(i: u64) -> u64 {
+    i.wrapping_mul(0x9E37_79B9_7F4A_7C15) % (2 * NUM_KEYS)
+}
+
+type Probe = fn(u64) -> u64;
+
+const PROBES: [(&str, Probe); 2] = [("sequential", sequential), ("scattered", scattered)];
+
+fn time_finds<F: Fn(&u64) -> bool>(label: &str, find: F) {
+    for &(order, probe) in PROBES.iter() {
+        let start = Instant::now();
+        let hits = (0..NUM_FINDS).filter(|&i| find(&probe(i))).count();
+        let elapsed = start.elapsed();
+        println!(
+            "{:<24} {:<10} {:>6.1} M finds/s ({} hits)",
+            label,
+            order,
+            NUM_FINDS as f64 / elapsed.as_secs_f64() / 1e6,
+            hits
+        );
+    }
+}
+
+fn main() {
+    let mut boxed = BTree::<u64>::new();
+    let mut arena = ArenaBTree::<u64>::new();
+    for i in 0..NUM_KEYS {
+        boxed.insert(i);
+        arena.insert(i);
+    }
+    time_finds("BTree::find", |key| boxed.find(key));
+    time_finds("BTree::find_by", |key| {
+        boxed.find_by(|k| k.cmp(key)).is_some()
+    });
+    time_finds("ArenaBTree::find", |key| arena.find(key));
+
+    let boxed = BTree::from_sorted(0..NUM_KEYS);
+    let arena = ArenaBTree::from_sorted(0..NUM_KEYS);
+    time_finds("packed BTree::find", |key| boxed.find(key));
+    time_finds("packed BTree::find_by", |key| {
+        boxed.find_by(|k| k.cmp(key)).is_some()
+    });
+    time_finds("packed ArenaBTree::find", |key| arena.find(key));
+}
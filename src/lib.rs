@@ -1,2 +1,25 @@
+#![cfg_attr(feature = "allocator", feature(allocator_api))]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(not(any(feature = "std", test)))]
+#[macro_use]
+extern crate alloc;
+
+// Without `std`, paths starting `std::` resolve to this stand-in assembled from `core` and
+// `alloc`, so the tree modules are written the same way for both builds.
+#[cfg(not(any(feature = "std", test)))]
+mod std {
+    #[allow(unused_imports)]
+    pub use alloc::{alloc, boxed, collections, fmt, string, sync, vec};
+    pub use core::*;
+}
+
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "rand")]
+extern crate rand;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
 #[allow(dead_code)]
 pub mod trees; // pub for now, exposed for benching
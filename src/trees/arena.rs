@@ -0,0 +1,490 @@
+use std::string::String;
+use std::vec::Vec;
+
+use super::{search_keys, subtree_capacity, Fanout, Key};
+
+/// A B-tree whose nodes all live in a single arena, with children named by `u32` index into it
+/// instead of being boxed. A child reference is half the size of a pointer, and the nodes of a
+/// tree built by `from_sorted` sit in the arena in preorder, so a descent reads slots which are
+/// near each other rather than scattered across the heap.
+///
+/// Nodes split exactly as `BTree`'s do under the same `Fanout`, so the two agree on every query.
+/// Nothing is ever removed from the arena; there is no `remove` yet. Panics if the tree
+/// outgrows `u32` node indices.
+pub struct ArenaBTree<T: Key> {
+    nodes: Vec<NodeSlot<T>>,
+    root: u32,
+    num_keys: usize,
+    fanout: Fanout,
+}
+
+struct NodeSlot<T> {
+    keys: Vec<T>,
+    // empty for a leaf; otherwise one more than there are keys, `children[i]` holding the keys
+    // below `keys[i]`
+    children: Vec<u32>,
+}
+
+impl<T> NodeSlot<T> {
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+}
+
+impl<T: Key> ArenaBTree<T> {
+    pub fn new() -> ArenaBTree<T> {
+        ArenaBTree::with_fanout(Fanout::default())
+    }
+
+    pub fn with_fanout(fanout: Fanout) -> ArenaBTree<T> {
+        ArenaBTree {
+            nodes: vec![NodeSlot {
+                keys: Vec::new(),
+                children: Vec::new(),
+            }],
+            root: 0,
+            num_keys: 0,
+            fanout,
+        }
+    }
+
+    /// Builds a densely packed tree from keys which must be strictly ascending, shaped as
+    /// `BTree::from_sorted` would shape it, with every node allocated in preorder.
+    pub fn from_sorted<I: IntoIterator<Item = T>>(sorted: I) -> ArenaBTree<T> {
+        let keys = sorted.into_iter().collect::<Vec<_>>();
+        let num_keys = keys.len();
+        let fanout = Fanout::default();
+
+        let mut height = 0;
+        while subtree_capacity(height, fanout) < num_keys {
+            height += 1;
+        }
+
+        let mut nodes = Vec::new();
+        let root = build_slot(&mut nodes, &mut keys.into_iter(), num_keys, height, fanout);
+        ArenaBTree {
+            nodes,
+            root,
+            num_keys,
+            fanout,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_keys == 0
+    }
+
+    /// Number of levels, counting the leaves; a tree which is a single leaf has height 1.
+    pub fn height(&self) -> usize {
+        let mut height = 1;
+        let mut slot = &self.nodes[self.root as usize];
+        while let Some(&child) = slot.children.first() {
+            slot = &self.nodes[child as usize];
+            height += 1;
+        }
+        height
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn find(&self, key: &T) -> bool {
+        let mut slot = &self.nodes[self.root as usize];
+        loop {
+            let index = match search_keys(&slot.keys, key) {
+                Ok(_) => return true,
+                Err(index) => index,
+            };
+            match slot.children.get(index) {
+                Some(&child) => slot = &self.nodes[child as usize],
+                None => return false,
+            }
+        }
+    }
+
+    /// Inserts `key`, returning false (and leaving the tree alone) if it was already present.
+    pub fn insert(&mut self, key: T) -> bool {
+        let split = match insert_at_slot(&mut self.nodes, self.root, key, self.fanout) {
+            ArenaInsert::Rejected => return false,
+            ArenaInsert::Inserted(split) => split,
+        };
+
+        if let Some((median, right)) = split {
+            let root = NodeSlot {
+                keys: vec![median],
+                children: vec![self.root, right],
+            };
+            self.root = push_slot(&mut self.nodes, root);
+        }
+
+        self.num_keys += 1;
+        true
+    }
+
+    /// In-order iterator over every key.
+    pub fn iter(&self) -> ArenaIter<'_, T> {
+        let mut iter = ArenaIter {
+            nodes: &self.nodes,
+            stack: Vec::new(),
+        };
+        iter.descend_left(self.root);
+        iter
+    }
+
+    /// Checks ordering, node size limits, uniform leaf depth, that every slot in the arena is
+    /// reached exactly once, and the key count; describes the first violation found.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut leaf_depth = None;
+        let total = check_slot(self, self.root, None, None, 0, &mut leaf_depth, &mut seen)?;
+
+        if let Some(orphan) = seen.iter().position(|&seen| !seen) {
+            return Err(format!("arena slot {} is not in the tree", orphan));
+        }
+        if total != self.num_keys {
+            return Err(format!(
+                "tree claims {} keys but its nodes hold {}",
+                self.num_keys, total
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<T: Key> Default for ArenaBTree<T> {
+    fn default() -> ArenaBTree<T> {
+        ArenaBTree::new()
+    }
+}
+
+/// In-order iterator over the keys of an `ArenaBTree`.
+pub struct ArenaIter<'a, T: 'a + Key> {
+    nodes: &'a [NodeSlot<T>],
+    // each entry is a slot and the index of the next of its keys to yield; for an internal slot
+    // the child before that key is above it on the stack, or already done
+    stack: Vec<(u32, usize)>,
+}
+
+impl<'a, T: 'a + Key> ArenaIter<'a, T> {
+    fn descend_left(&mut self, mut slot: u32) {
+        loop {
+            self.stack.push((slot, 0));
+            match self.nodes[slot as usize].children.first() {
+                Some(&child) => slot = child,
+                None => return,
+            }
+        }
+    }
+}
+
+impl<'a, T: 'a + Key> Iterator for ArenaIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let nodes = self.nodes;
+        loop {
+            let top = self.stack.last_mut()?;
+            let node = &nodes[top.0 as usize];
+            if top.1 == node.keys.len() {
+                self.stack.pop();
+                continue;
+            }
+
+            let key = &node.keys[top.1];
+            top.1 += 1;
+            if let Some(&child) = node.children.get(top.1) {
+                self.descend_left(child);
+            }
+            return Some(key);
+        }
+    }
+}
+
+// Adds `slot` to the arena, returning its index.
+fn push_slot<T>(nodes: &mut Vec<NodeSlot<T>>, slot: NodeSlot<T>) -> u32 {
+    let index = nodes.len();
+    assert!(
+        index <= u32::MAX as usize,
+        "an arena tree can hold at most {} nodes",
+        u32::MAX as u64 + 1
+    );
+    nodes.push(slot);
+    index as u32
+}
+
+// Builds a subtree of exactly the given height from the next `num_keys` keys, spreading them as
+// `build_subtree` does; the subtree's root is allocated before its children.
+fn build_slot<T: Key, I: Iterator<Item = T>>(
+    nodes: &mut Vec<NodeSlot<T>>,
+    keys: &mut I,
+    num_keys: usize,
+    height: usize,
+    fanout: Fanout,
+) -> u32 {
+    if height == 0 {
+        let leaf = NodeSlot {
+            keys: keys.take(num_keys).collect(),
+            children: Vec::new(),
+        };
+        return push_slot(nodes, leaf);
+    }
+
+    let index = push_slot(
+        nodes,
+        NodeSlot {
+            keys: Vec::new(),
+            children: Vec::new(),
+        },
+    );
+
+    let child_capacity = subtree_capacity(height - 1, fanout);
+    let num_children = (num_keys + 1 + child_capacity) / (child_capacity + 1);
+    let child_total = num_keys + 1 - num_children;
+
+    for i in 0..num_children {
+        let child_keys =
+            child_total / num_children + if i < child_total % num_children { 1 } else { 0 };
+        let child = build_slot(nodes, keys, child_keys, height - 1, fanout);
+        nodes[index as usize].children.push(child);
+
+        if i + 1 < num_children {
+            let separator = keys.next().expect("ran out of keys building a subtree");
+            nodes[index as usize].keys.push(separator);
+        }
+    }
+
+    index
+}
+
+enum ArenaInsert<T> {
+    Rejected,
+    // if the slot had to split, the median key and the new slot to go to its right
+    Inserted(Option<(T, u32)>),
+}
+
+fn insert_at_slot<T: Key>(
+    nodes: &mut Vec<NodeSlot<T>>,
+    slot: u32,
+    key: T,
+    fanout: Fanout,
+) -> ArenaInsert<T> {
+    let index = match search_keys(&nodes[slot as usize].keys, &key) {
+        Ok(_) => return ArenaInsert::Rejected,
+        Err(index) => index,
+    };
+
+    let max_keys = if nodes[slot as usize].is_leaf() {
+        nodes[slot as usize].keys.insert(index, key);
+        fanout.leaf_max_keys()
+    } else {
+        let child = nodes[slot as usize].children[index];
+        let (median, right) = match insert_at_slot(nodes, child, key, fanout) {
+            ArenaInsert::Inserted(Some(split)) => split,
+            done => return done,
+        };
+        let node = &mut nodes[slot as usize];
+        node.keys.insert(index, median);
+        node.children.insert(index + 1, right);
+        fanout.internal_max_keys()
+    };
+
+    if nodes[slot as usize].keys.len() <= max_keys {
+        return ArenaInsert::Inserted(None);
+    }
+
+    // cut exactly where `split_node` would, so both representations keep the same shape
+    let split_index = fanout.split_index(max_keys);
+    let node = &mut nodes[slot as usize];
+    let right_keys = node.keys.split_off(split_index + 1);
+    let median = node.keys.pop().expect("an overfull node has a median");
+    let right_children = if node.is_leaf() {
+        Vec::new()
+    } else {
+        node.children.split_off(split_index + 1)
+    };
+
+    let right = push_slot(
+        nodes,
+        NodeSlot {
+            keys: right_keys,
+            children: right_children,
+        },
+    );
+    ArenaInsert::Inserted(Some((median, right)))
+}
+
+// Checks the subtree at `slot`, whose keys must lie strictly between `lower` and `upper`,
+// returning how many keys it holds.
+fn check_slot<T: Key>(
+    tree: &ArenaBTree<T>,
+    slot: u32,
+    lower: Option<&T>,
+    upper: Option<&T>,
+    depth: usize,
+    leaf_depth: &mut Option<usize>,
+    seen: &mut [bool],
+) -> Result<usize, String> {
+    let node = match tree.nodes.get(slot as usize) {
+        Some(node) => node,
+        None => return Err(format!("slot {} is past the end of the arena", slot)),
+    };
+    if seen[slot as usize] {
+        return Err(format!("slot {} is reached more than once", slot));
+    }
+    seen[slot as usize] = true;
+
+    let keys = &node.keys;
+    if !keys.windows(2).all(|pair| pair[0] < pair[1]) {
+        return Err(format!("slot {} is not strictly ascending", slot));
+    }
+    let below = match (lower, keys.first()) {
+        (Some(lower), Some(first)) => first <= lower,
+        _ => false,
+    };
+    let above = match (upper, keys.last()) {
+        (Some(upper), Some(last)) => last >= upper,
+        _ => false,
+    };
+    if below || above {
+        return Err(format!("slot {} holds keys outside its parent's", slot));
+    }
+
+    let is_root = slot == tree.root;
+    let max_keys = if node.is_leaf() {
+        tree.fanout.leaf_max_keys()
+    } else {
+        tree.fanout.internal_max_keys()
+    };
+    if keys.len() > max_keys || (!is_root && keys.is_empty()) {
+        return Err(format!(
+            "slot {} holds {} keys, outside 1..={}",
+            slot,
+            keys.len(),
+            max_keys
+        ));
+    }
+
+    if node.is_leaf() {
+        match *leaf_depth {
+            Some(expected) if expected != depth => {
+                return Err(format!(
+                    "leaf slot {} is at depth {} but others are at depth {}",
+                    slot, depth, expected
+                ));
+            }
+            _ => *leaf_depth = Some(depth),
+        }
+        return Ok(keys.len());
+    }
+
+    if node.children.len() != keys.len() + 1 {
+        return Err(format!(
+            "slot {} has {} keys but {} children",
+            slot,
+            keys.len(),
+            node.children.len()
+        ));
+    }
+
+    let mut total = keys.len();
+    for (i, &child) in node.children.iter().enumerate() {
+        let child_lower = if i == 0 { lower } else { Some(&keys[i - 1]) };
+        let child_upper = keys.get(i).or(upper);
+        total += check_slot(
+            tree,
+            child,
+            child_lower,
+            child_upper,
+            depth + 1,
+            leaf_depth,
+            seen,
+        )?;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::scattered;
+    use super::super::{BTree, Fanout, SplitPolicy};
+    use super::ArenaBTree;
+
+    fn assert_same(arena: &ArenaBTree<u64>, boxed: &BTree<u64>, probes: u64) {
+        assert_eq!(arena.check_invariants(), Ok(()));
+        assert_eq!(arena.len(), boxed.len());
+        assert_eq!(arena.height(), boxed.height());
+        assert_eq!(arena.node_count(), boxed.node_count());
+        assert!(arena.iter().eq(boxed.iter()));
+        for key in 0..probes {
+            assert_eq!(arena.find(&key), boxed.find(&key), "find({})", key);
+        }
+    }
+
+    #[test]
+    fn inserts_match_boxed_tree() {
+        for &fanout in [
+            Fanout::default(),
+            Fanout::new(4, 4),
+            Fanout::new(2, 5),
+            Fanout::new(7, 3).with_split_policy(SplitPolicy::RightBiased),
+        ]
+        .iter()
+        {
+            let mut arena = ArenaBTree::with_fanout(fanout);
+            let mut boxed = BTree::with_fanout(fanout);
+            for key in scattered(6000) {
+                let key = key * 2;
+                assert_eq!(arena.insert(key), boxed.insert(key));
+            }
+            assert!(!arena.insert(10));
+            assert!(arena.height() > 2);
+
+            assert_same(&arena, &boxed, 12_001);
+        }
+    }
+
+    #[test]
+    fn from_sorted_matches_boxed_tree() {
+        for &n in [0, 1, 30, 31, 1000, 100_000].iter() {
+            let arena = ArenaBTree::from_sorted((0..n).map(|k| 3 * k));
+            let boxed = BTree::from_sorted((0..n).map(|k| 3 * k));
+            assert_same(&arena, &boxed, 3 * n.min(5000) + 2);
+        }
+
+        // inserting into a packed tree splits it the same way too
+        let mut arena = ArenaBTree::from_sorted(0..50_000_u64);
+        let mut boxed = BTree::from_sorted(0..50_000_u64);
+        for key in scattered(20_000) {
+            assert_eq!(arena.insert(key * 5), boxed.insert(key * 5));
+        }
+        assert_same(&arena, &boxed, 100_000);
+    }
+
+    #[test]
+    fn from_sorted_lays_out_preorder() {
+        let arena = ArenaBTree::from_sorted(0..10_000_u64);
+        assert_eq!(arena.root, 0);
+
+        // the first child of every internal slot is the slot right after it
+        for (index, slot) in arena.nodes.iter().enumerate() {
+            if let Some(&first) = slot.children.first() {
+                assert_eq!(first as usize, index + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_tree() {
+        let arena = ArenaBTree::<u32>::new();
+        assert!(arena.is_empty());
+        assert_eq!(arena.height(), 1);
+        assert!(!arena.find(&0));
+        assert_eq!(arena.iter().next(), None);
+        assert_eq!(arena.check_invariants(), Ok(()));
+    }
+}
@@ -0,0 +1,163 @@
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::vec::Vec;
+
+use super::{BTree, Key};
+
+// roughly a 1% false positive rate at the expected number of keys
+const BITS_PER_KEY: usize = 10;
+const NUM_HASHES: u64 = 7;
+
+/// A Bloom filter over the keys of a tree. The hash function is captured when the filter is
+/// created (where `T: Hash` is known), so the tree itself doesn't need to carry that bound.
+pub(super) struct BloomFilter<T> {
+    bits: Vec<u64>,
+    hash: fn(&T) -> u64,
+}
+
+impl<T> Clone for BloomFilter<T> {
+    fn clone(&self) -> BloomFilter<T> {
+        BloomFilter {
+            bits: self.bits.clone(),
+            hash: self.hash,
+        }
+    }
+}
+
+impl<T> BloomFilter<T> {
+    fn new(expected_keys: usize, hash: fn(&T) -> u64) -> BloomFilter<T> {
+        let num_words = (expected_keys.max(1) * BITS_PER_KEY).div_ceil(64);
+        BloomFilter {
+            bits: vec![0; num_words],
+            hash,
+        }
+    }
+
+    pub(super) fn insert(&mut self, key: &T) {
+        let num_bits = (self.bits.len() * 64) as u64;
+        for bit in probes(key, self.hash, num_bits) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// False means the key was definitely never inserted.
+    pub(super) fn may_contain(&self, key: &T) -> bool {
+        let num_bits = (self.bits.len() * 64) as u64;
+        probes(key, self.hash, num_bits)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    pub(super) fn heap_bytes(&self) -> usize {
+        self.bits.capacity() * mem::size_of::<u64>()
+    }
+
+    pub(super) fn clear(&mut self) {
+        for word in self.bits.iter_mut() {
+            *word = 0;
+        }
+    }
+}
+
+impl<T: Hash> BloomFilter<T> {
+    pub(super) fn hashing(expected_keys: usize) -> BloomFilter<T> {
+        BloomFilter::new(expected_keys, hash_key::<T>)
+    }
+}
+
+// bit positions for a key, by double hashing
+fn probes<T>(key: &T, hash: fn(&T) -> u64, num_bits: u64) -> impl Iterator<Item = u64> {
+    let h1 = hash(key);
+    let h2 = mix(h1) | 1;
+    (0..NUM_HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+}
+
+fn hash_key<T: Hash>(key: &T) -> u64 {
+    let mut hasher = FnvHasher(0xcbf2_9ce4_8422_2325);
+    key.hash(&mut hasher);
+    mix(hasher.finish())
+}
+
+// splitmix64 finalizer, so that nearby integers land on unrelated bits
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// FNV-1a; deterministic across runs, unlike the randomly seeded std hasher
+struct FnvHasher(u64);
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+impl<T: Key + Hash> BTree<T> {
+    /// An empty tree with a Bloom filter sized for about `expected_keys` keys, so that `find`
+    /// can answer `false` for most absent keys without descending the tree. Growing well past
+    /// `expected_keys` raises the false positive rate but never causes wrong answers. Bits can't
+    /// be cleared for individual keys, so keys which are removed keep costing a descent until
+    /// the tree is next rebuilt.
+    pub fn with_bloom(expected_keys: usize) -> BTree<T> {
+        BTree {
+            bloom: Some(BloomFilter::hashing(expected_keys)),
+            ..BTree::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::BTree;
+
+    #[test]
+    fn bloom_has_no_false_negatives() {
+        let mut tree = BTree::<u64>::with_bloom(10_000);
+        for i in 0..10_000 {
+            tree.insert(i * 7);
+        }
+
+        let bloom = tree.bloom.as_ref().unwrap();
+        for i in 0..10_000 {
+            assert!(bloom.may_contain(&(i * 7)));
+            assert!(tree.find(&(i * 7)));
+        }
+    }
+
+    #[test]
+    fn bloom_rejects_absent_keys() {
+        let mut tree = BTree::<u64>::with_bloom(10_000);
+        for i in 0..10_000 {
+            tree.insert(i * 7);
+        }
+
+        let bloom = tree.bloom.as_ref().unwrap();
+        let absent = (0..100_000_u64).map(|i| i * 7 + 3);
+        let rejected = absent.clone().filter(|k| !bloom.may_contain(k)).count();
+
+        // nominally 99%; leave plenty of slack
+        assert!(rejected > 95_000, "only {} of 100000 rejected", rejected);
+        assert!(absent.take(1000).all(|k| !tree.find(&k)));
+    }
+
+    #[test]
+    fn bloom_survives_batch_rebuild() {
+        let mut tree = BTree::<u32>::with_bloom(1000);
+        tree.insert(5);
+        tree.insert_sorted_batch(&(100..1100).collect::<Vec<_>>());
+
+        for i in 100..1100 {
+            assert!(tree.find(&i));
+        }
+        assert!(tree.find(&5));
+        assert!(!tree.find(&6));
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use super::{BTree, Key};
+
+/// A `BTree` which holds at most `capacity` keys: inserting a new key into a full tree first
+/// evicts the least recently used one. Both `insert` and `find` count as using a key.
+///
+/// Recency is tracked with an access counter, kept in two ordered maps beside the tree (key to
+/// last access, and last access to key), so every key is stored three times and each access
+/// costs two extra O(log n) map updates on top of the tree operation itself.
+pub struct BoundedBTree<T: Key + Clone> {
+    tree: BTree<T>,
+    capacity: usize,
+    last_used: BTreeMap<T, u64>,
+    by_age: BTreeMap<u64, T>,
+    clock: u64,
+    evictions: u64,
+}
+
+impl<T: Key + Clone> BoundedBTree<T> {
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> BoundedBTree<T> {
+        assert!(
+            capacity > 0,
+            "a bounded tree needs room for at least one key"
+        );
+        BoundedBTree {
+            tree: BTree::new(),
+            capacity,
+            last_used: BTreeMap::new(),
+            by_age: BTreeMap::new(),
+            clock: 0,
+            evictions: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// How many keys have been evicted to make room since the tree was created.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// The keys as a plain tree, for reads which shouldn't count as uses.
+    pub fn tree(&self) -> &BTree<T> {
+        &self.tree
+    }
+
+    /// Whether `key` is present, marking it as just used if so.
+    pub fn find(&mut self, key: &T) -> bool {
+        if !self.tree.find(key) {
+            return false;
+        }
+        self.touch(key.clone());
+        true
+    }
+
+    /// Inserts `key`, returning whether it was new; either way it becomes the most recently
+    /// used key. If the tree was full, the least recently used key is evicted to make room.
+    pub fn insert(&mut self, key: T) -> bool {
+        let inserted = self.tree.insert(key.clone());
+        self.touch(key);
+
+        if self.tree.len() > self.capacity {
+            let (_, oldest) = self
+                .by_age
+                .pop_first()
+                .expect("an overfull tree has keys to evict");
+            self.last_used.remove(&oldest);
+            self.tree.remove(&oldest);
+            self.evictions += 1;
+        }
+        inserted
+    }
+
+    pub fn remove(&mut self, key: &T) -> bool {
+        if let Some(age) = self.last_used.remove(key) {
+            self.by_age.remove(&age);
+        }
+        self.tree.remove(key)
+    }
+
+    fn touch(&mut self, key: T) {
+        self.clock += 1;
+        if let Some(age) = self.last_used.insert(key.clone(), self.clock) {
+            self.by_age.remove(&age);
+        }
+        self.by_age.insert(self.clock, key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BoundedBTree;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut bounded = BoundedBTree::new(100);
+        for key in 0..100_u32 {
+            assert!(bounded.insert(key));
+        }
+        assert_eq!(bounded.evictions(), 0);
+
+        // touch the lower half, newest last, so the untouched upper half is oldest
+        for key in 0..50 {
+            assert!(bounded.find(&key));
+        }
+
+        for key in 100..150 {
+            bounded.insert(key);
+        }
+        assert_eq!(bounded.len(), 100);
+        assert_eq!(bounded.evictions(), 50);
+        let mut expected = (0..50).chain(100..150).collect::<Vec<_>>();
+        assert_eq!(bounded.tree().to_sorted_vec(), expected);
+
+        // the touched keys go next, in the order they were touched
+        for key in 150..200 {
+            bounded.insert(key);
+            assert!(!bounded.tree().find(&(key - 150)));
+            assert_eq!(bounded.len(), 100);
+        }
+        assert_eq!(bounded.len(), bounded.capacity());
+        assert_eq!(bounded.evictions(), 100);
+        expected = (100..200).collect();
+        assert_eq!(bounded.tree().to_sorted_vec(), expected);
+        assert_eq!(bounded.tree().check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn reinsert_and_remove_update_recency() {
+        let mut bounded = BoundedBTree::new(3);
+        bounded.insert(1_u32);
+        bounded.insert(2);
+        bounded.insert(3);
+
+        // re-inserting 1 makes 2 the oldest
+        assert!(!bounded.insert(1));
+        bounded.insert(4);
+        assert_eq!(bounded.tree().to_sorted_vec(), vec![1, 3, 4]);
+
+        // a removed key frees its slot without an eviction
+        assert!(bounded.remove(&3));
+        assert!(!bounded.find(&3));
+        bounded.insert(5);
+        assert_eq!(bounded.evictions(), 1);
+        assert_eq!(bounded.tree().to_sorted_vec(), vec![1, 4, 5]);
+    }
+}
@@ -0,0 +1,525 @@
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::string::String;
+use std::string::ToString;
+use std::vec::Vec;
+
+use super::{past_end, Fanout, Key};
+
+/// A B+ tree: every key lives in a leaf, and internal nodes hold copies of keys purely to route
+/// lookups. The leaves are chained left to right, so `iter` and `range` walk along that chain
+/// and never go back up through the internal nodes.
+///
+/// Leaves are kept in one arena and refer to their right-hand neighbour by index, which lets the
+/// chain exist alongside the ordinary parent-owns-child structure. An insert always descends to
+/// a leaf, even when a routing copy of the key was met on the way down.
+///
+/// Removal takes the key out of its leaf but doesn't rebalance, so heavy deletion leaves sparse
+/// or empty leaves behind; the routing copies stay valid either way.
+pub struct BPlusTree<T: Key + Clone> {
+    root: PlusNode<T>,
+    leaves: Vec<PlusLeaf<T>>,
+    num_keys: usize,
+    fanout: Fanout,
+}
+
+enum PlusNode<T: Key + Clone> {
+    // `children[i]` holds the keys from `separators[i - 1]` (inclusive) up to `separators[i]`
+    Internal {
+        separators: Vec<T>,
+        children: Vec<PlusNode<T>>,
+    },
+    // index into the leaf arena
+    Leaf(usize),
+}
+
+struct PlusLeaf<T: Key + Clone> {
+    keys: Vec<T>,
+    next: Option<usize>,
+}
+
+// the leftmost leaf keeps its slot in the arena through every split
+const FIRST_LEAF: usize = 0;
+
+impl<T: Key + Clone> BPlusTree<T> {
+    pub fn new() -> BPlusTree<T> {
+        BPlusTree::with_fanout(Fanout::default())
+    }
+
+    pub fn with_fanout(fanout: Fanout) -> BPlusTree<T> {
+        BPlusTree {
+            root: PlusNode::Leaf(FIRST_LEAF),
+            leaves: vec![PlusLeaf {
+                keys: Vec::new(),
+                next: None,
+            }],
+            num_keys: 0,
+            fanout,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_keys == 0
+    }
+
+    /// Number of levels, counting the leaves; a tree which is a single leaf has height 1.
+    pub fn height(&self) -> usize {
+        let mut height = 1;
+        let mut node = &self.root;
+        while let PlusNode::Internal { ref children, .. } = *node {
+            node = &children[0];
+            height += 1;
+        }
+        height
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn find(&self, key: &T) -> bool {
+        self.leaves[self.leaf_for(key)]
+            .keys
+            .binary_search(key)
+            .is_ok()
+    }
+
+    /// Inserts `key`, returning false (and leaving the tree alone) if it was already present.
+    pub fn insert(&mut self, key: T) -> bool {
+        let split = match insert_at(&mut self.root, &mut self.leaves, key, self.fanout) {
+            PlusInsert::Rejected => return false,
+            PlusInsert::Inserted(split) => split,
+        };
+
+        if let Some((separator, right)) = split {
+            let fanout = self.fanout;
+            let left = mem::replace(&mut self.root, PlusNode::Leaf(FIRST_LEAF));
+            let mut separators = Vec::with_capacity(fanout.internal_max_keys() + 1);
+            separators.push(separator);
+            let mut children = Vec::with_capacity(fanout.internal_max_keys() + 2);
+            children.push(left);
+            children.push(right);
+            self.root = PlusNode::Internal {
+                separators,
+                children,
+            };
+        }
+
+        self.num_keys += 1;
+        true
+    }
+
+    /// Removes `key` from its leaf, returning whether it was present. Leaves are not merged or
+    /// rebalanced afterwards.
+    pub fn remove(&mut self, key: &T) -> bool {
+        let leaf = self.leaf_for(key);
+        let keys = &mut self.leaves[leaf].keys;
+        match keys.binary_search(key) {
+            Ok(index) => {
+                keys.remove(index);
+                self.num_keys -= 1;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// In-order iterator over every key, walking the leaf chain from the leftmost leaf.
+    pub fn iter(&self) -> LeafScan<'_, T> {
+        LeafScan {
+            leaves: &self.leaves,
+            leaf: Some(FIRST_LEAF),
+            index: 0,
+            end: Bound::Unbounded,
+        }
+    }
+
+    /// In-order iterator over the keys within `range`: one descent to the leaf where it starts,
+    /// then along the leaf chain until it ends. The end bound is cloned into the iterator.
+    pub fn range<R: RangeBounds<T>>(&self, range: R) -> LeafScan<'_, T> {
+        let (leaf, index) = match range.start_bound() {
+            Bound::Unbounded => (FIRST_LEAF, 0),
+            Bound::Included(start) => {
+                let leaf = self.leaf_for(start);
+                let index = self.leaves[leaf]
+                    .keys
+                    .binary_search(start)
+                    .unwrap_or_else(|i| i);
+                (leaf, index)
+            }
+            Bound::Excluded(start) => {
+                let leaf = self.leaf_for(start);
+                let index = match self.leaves[leaf].keys.binary_search(start) {
+                    Ok(i) => i + 1,
+                    Err(i) => i,
+                };
+                (leaf, index)
+            }
+        };
+
+        LeafScan {
+            leaves: &self.leaves,
+            leaf: Some(leaf),
+            index,
+            end: range.end_bound().cloned(),
+        }
+    }
+
+    /// Checks that every leaf is at the same depth, that each subtree's keys lie within the
+    /// separators around it, that the leaf chain visits every leaf in key order, and that the key
+    /// count is right; describes the first violation found.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut in_order = Vec::new();
+        let mut leaf_depth = None;
+        check_plus_node(
+            &self.root,
+            &self.leaves,
+            None,
+            None,
+            0,
+            &mut leaf_depth,
+            &mut in_order,
+        )?;
+
+        let mut chained = Vec::new();
+        let mut leaf = Some(FIRST_LEAF);
+        while let Some(index) = leaf {
+            if chained.len() > self.leaves.len() {
+                return Err("leaf chain has a cycle".to_string());
+            }
+            chained.push(index);
+            leaf = self.leaves[index].next;
+        }
+        if chained != in_order {
+            return Err(format!(
+                "leaf chain {:?} is not the in-order leaves {:?}",
+                chained, in_order
+            ));
+        }
+
+        let total = self
+            .leaves
+            .iter()
+            .map(|leaf| leaf.keys.len())
+            .sum::<usize>();
+        if total != self.num_keys {
+            return Err(format!(
+                "tree claims {} keys but its leaves hold {}",
+                self.num_keys, total
+            ));
+        }
+        Ok(())
+    }
+
+    // the leaf whose slice of the key space contains `key`
+    fn leaf_for(&self, key: &T) -> usize {
+        let mut node = &self.root;
+        loop {
+            match *node {
+                PlusNode::Internal {
+                    ref separators,
+                    ref children,
+                } => node = &children[child_index(separators, key)],
+                PlusNode::Leaf(leaf) => return leaf,
+            }
+        }
+    }
+}
+
+impl<T: Key + Clone> Default for BPlusTree<T> {
+    fn default() -> BPlusTree<T> {
+        BPlusTree::new()
+    }
+}
+
+/// In-order iterator over the keys of a `BPlusTree`, moving from leaf to leaf by their links.
+pub struct LeafScan<'a, T: 'a + Key + Clone> {
+    leaves: &'a [PlusLeaf<T>],
+    leaf: Option<usize>,
+    index: usize,
+    end: Bound<T>,
+}
+
+impl<'a, T: 'a + Key + Clone> Iterator for LeafScan<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            let leaf = &self.leaves[self.leaf?];
+            if let Some(key) = leaf.keys.get(self.index) {
+                if past_end(key, self.end.as_ref()) {
+                    self.leaf = None;
+                    return None;
+                }
+                self.index += 1;
+                return Some(key);
+            }
+            self.leaf = leaf.next;
+            self.index = 0;
+        }
+    }
+}
+
+// equal keys go right, since a separator is a copy of the first key of the child to its right
+fn child_index<T: Key>(separators: &[T], key: &T) -> usize {
+    match separators.binary_search(key) {
+        Ok(index) => index + 1,
+        Err(index) => index,
+    }
+}
+
+enum PlusInsert<T: Key + Clone> {
+    Rejected,
+    // if the node had to split, the separator and the new node to go to its right
+    Inserted(Option<(T, PlusNode<T>)>),
+}
+
+fn insert_at<T: Key + Clone>(
+    node: &mut PlusNode<T>,
+    leaves: &mut Vec<PlusLeaf<T>>,
+    key: T,
+    fanout: Fanout,
+) -> PlusInsert<T> {
+    match *node {
+        PlusNode::Leaf(index) => {
+            let keys = &mut leaves[index].keys;
+            match keys.binary_search(&key) {
+                Ok(_) => return PlusInsert::Rejected,
+                Err(position) => keys.insert(position, key),
+            }
+            if keys.len() <= fanout.leaf_max_keys() {
+                return PlusInsert::Inserted(None);
+            }
+
+            let right_keys = keys.split_off(keys.len() / 2);
+            let separator = right_keys[0].clone();
+            let right = leaves.len();
+            let next = leaves[index].next.replace(right);
+            leaves.push(PlusLeaf {
+                keys: right_keys,
+                next,
+            });
+            PlusInsert::Inserted(Some((separator, PlusNode::Leaf(right))))
+        }
+
+        PlusNode::Internal {
+            ref mut separators,
+            ref mut children,
+        } => {
+            let index = child_index(separators, &key);
+            let (separator, right) = match insert_at(&mut children[index], leaves, key, fanout) {
+                PlusInsert::Inserted(Some(split)) => split,
+                done => return done,
+            };
+
+            separators.insert(index, separator);
+            children.insert(index + 1, right);
+            if separators.len() <= fanout.internal_max_keys() {
+                return PlusInsert::Inserted(None);
+            }
+
+            // the middle separator moves up rather than being copied
+            let middle = separators.len() / 2;
+            let right_separators = separators.split_off(middle + 1);
+            let up = separators
+                .pop()
+                .expect("an overfull node has a middle separator");
+            let right_children = children.split_off(middle + 1);
+            PlusInsert::Inserted(Some((
+                up,
+                PlusNode::Internal {
+                    separators: right_separators,
+                    children: right_children,
+                },
+            )))
+        }
+    }
+}
+
+// Checks the subtree at `node`, whose keys must lie in `[lower, upper)`, appending its leaves
+// to `in_order` as they are reached.
+fn check_plus_node<T: Key + Clone>(
+    node: &PlusNode<T>,
+    leaves: &[PlusLeaf<T>],
+    lower: Option<&T>,
+    upper: Option<&T>,
+    depth: usize,
+    leaf_depth: &mut Option<usize>,
+    in_order: &mut Vec<usize>,
+) -> Result<(), String> {
+    match *node {
+        PlusNode::Leaf(index) => {
+            match *leaf_depth {
+                Some(expected) if expected != depth => {
+                    return Err(format!(
+                        "leaf {} is at depth {} but others are at depth {}",
+                        index, depth, expected
+                    ));
+                }
+                _ => *leaf_depth = Some(depth),
+            }
+
+            let keys = &leaves[index].keys;
+            if !keys.windows(2).all(|pair| pair[0] < pair[1]) {
+                return Err(format!("leaf {} is not strictly ascending", index));
+            }
+            let below = match (lower, keys.first()) {
+                (Some(lower), Some(first)) => first < lower,
+                _ => false,
+            };
+            let above = match (upper, keys.last()) {
+                (Some(upper), Some(last)) => last >= upper,
+                _ => false,
+            };
+            if below || above {
+                return Err(format!("leaf {} holds keys outside its separators", index));
+            }
+
+            in_order.push(index);
+            Ok(())
+        }
+
+        PlusNode::Internal {
+            ref separators,
+            ref children,
+        } => {
+            if children.len() != separators.len() + 1 {
+                return Err(format!(
+                    "internal node at depth {} has {} separators but {} children",
+                    depth,
+                    separators.len(),
+                    children.len()
+                ));
+            }
+
+            for (i, child) in children.iter().enumerate() {
+                let child_lower = if i == 0 {
+                    lower
+                } else {
+                    Some(&separators[i - 1])
+                };
+                let child_upper = separators.get(i).or(upper);
+                check_plus_node(
+                    child,
+                    leaves,
+                    child_lower,
+                    child_upper,
+                    depth + 1,
+                    leaf_depth,
+                    in_order,
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::scattered;
+    use super::super::Fanout;
+    use super::{BPlusTree, PlusNode};
+
+    // how many keys the internal nodes route by; none of them are the keys themselves
+    fn separator_count<T: super::Key + Clone>(node: &PlusNode<T>) -> usize {
+        match *node {
+            PlusNode::Leaf(_) => 0,
+            PlusNode::Internal {
+                ref separators,
+                ref children,
+            } => separators.len() + children.iter().map(separator_count).sum::<usize>(),
+        }
+    }
+
+    #[test]
+    fn every_key_is_in_a_leaf_and_findable() {
+        let mut tree = BPlusTree::with_fanout(Fanout::new(4, 4));
+        for key in scattered(5000) {
+            assert!(tree.insert(key));
+        }
+        assert!(!tree.insert(17));
+
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree.len(), 5000);
+        assert!(tree.height() > 3);
+        for key in 0..5000 {
+            assert!(tree.find(&key));
+        }
+        assert!(!tree.find(&5000));
+
+        let in_leaves = tree
+            .leaves
+            .iter()
+            .map(|leaf| leaf.keys.len())
+            .sum::<usize>();
+        assert_eq!(in_leaves, 5000);
+        assert!(separator_count(&tree.root) > 0);
+    }
+
+    #[test]
+    fn scans_walk_only_the_leaf_chain() {
+        let mut tree = BPlusTree::with_fanout(Fanout::new(6, 4));
+        for key in scattered(3000) {
+            tree.insert(2 * key);
+        }
+
+        // the scan carries the leaf arena and nothing else, so it cannot visit internal nodes
+        let scan = tree.iter();
+        assert!(::std::ptr::eq(scan.leaves, &tree.leaves[..]));
+        assert_eq!(
+            scan.cloned().collect::<Vec<_>>(),
+            (0..3000).map(|k| 2 * k).collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            tree.range(101..=121).cloned().collect::<Vec<_>>(),
+            vec![102, 104, 106, 108, 110, 112, 114, 116, 118, 120]
+        );
+        assert_eq!(
+            tree.range(5990..).cloned().collect::<Vec<_>>(),
+            vec![5990, 5992, 5994, 5996, 5998]
+        );
+        assert_eq!(tree.range(..4).cloned().collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(tree.range(7000..).next(), None);
+    }
+
+    #[test]
+    fn remove_leaves_separators_routing() {
+        let mut tree = BPlusTree::with_fanout(Fanout::new(4, 4));
+        for key in 0..1000_u32 {
+            tree.insert(key);
+        }
+        for key in (0..1000).filter(|key| key % 3 != 0) {
+            assert!(tree.remove(&key));
+        }
+        assert!(!tree.remove(&1));
+
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree.len(), 334);
+        assert!(tree.find(&999));
+        assert!(!tree.find(&998));
+        assert_eq!(
+            tree.iter().cloned().collect::<Vec<_>>(),
+            (0..1000).filter(|key| key % 3 == 0).collect::<Vec<_>>()
+        );
+
+        // a removed key can come back, into the leaf its separators route it to
+        assert!(tree.insert(500));
+        assert!(tree.find(&500));
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn empty_tree() {
+        let tree = BPlusTree::<u32>::new();
+        assert!(tree.is_empty());
+        assert_eq!(tree.height(), 1);
+        assert_eq!(tree.iter().next(), None);
+        assert!(!tree.find(&0));
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+}
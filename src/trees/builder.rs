@@ -0,0 +1,136 @@
+use std::hash::Hash;
+
+use super::bloom::BloomFilter;
+use super::{BTree, Fanout, Key, Node, DEFAULT_COMPACT_THRESHOLD};
+
+/// Collects the settings for a new `BTree` in one place. Nothing is required: `build` on a
+/// fresh builder gives the same tree as `BTree::new()`.
+pub struct BTreeBuilder<T: Key> {
+    fanout: Fanout,
+    lazy_removal: bool,
+    compact_threshold: f64,
+    capacity: usize,
+    bloom: Option<BloomFilter<T>>,
+}
+
+impl<T: Key> BTree<T> {
+    pub fn builder() -> BTreeBuilder<T> {
+        BTreeBuilder::new()
+    }
+}
+
+impl<T: Key> BTreeBuilder<T> {
+    pub fn new() -> BTreeBuilder<T> {
+        BTreeBuilder {
+            fanout: Fanout::default(),
+            lazy_removal: false,
+            compact_threshold: DEFAULT_COMPACT_THRESHOLD,
+            capacity: 0,
+            bloom: None,
+        }
+    }
+
+    /// Node size limits; see `Fanout`.
+    pub fn fanout(mut self, fanout: Fanout) -> BTreeBuilder<T> {
+        self.fanout = fanout;
+        self
+    }
+
+    /// Starts the tree in lazy removal mode; see `BTree::set_lazy_removal`.
+    pub fn lazy_removal(mut self, lazy: bool) -> BTreeBuilder<T> {
+        self.lazy_removal = lazy;
+        self
+    }
+
+    /// Removals per remaining key before `maybe_compact` rebuilds; see
+    /// `BTree::set_compact_threshold`.
+    pub fn compact_threshold(mut self, threshold: f64) -> BTreeBuilder<T> {
+        self.compact_threshold = threshold;
+        self
+    }
+
+    /// Reserves room up front for `keys` keys in the root leaf, as far as one leaf can hold
+    /// before it splits; beyond that the tree grows one node at a time whatever the hint.
+    pub fn capacity(mut self, keys: usize) -> BTreeBuilder<T> {
+        self.capacity = keys;
+        self
+    }
+
+    pub fn build(self) -> BTree<T> {
+        let mut tree = BTree::with_fanout(self.fanout);
+        tree.lazy_removal = self.lazy_removal;
+        tree.set_compact_threshold(self.compact_threshold);
+        tree.bloom = self.bloom;
+
+        if let Node::Leaf(ref mut root) = tree.root {
+            root.keys
+                .reserve_exact(self.capacity.min(self.fanout.leaf_max_keys() + 1));
+        }
+        tree
+    }
+}
+
+impl<T: Key + Hash> BTreeBuilder<T> {
+    /// Adds a Bloom filter sized for about `expected_keys` keys; see `BTree::with_bloom`.
+    pub fn bloom(mut self, expected_keys: usize) -> BTreeBuilder<T> {
+        self.bloom = Some(BloomFilter::hashing(expected_keys));
+        self
+    }
+}
+
+impl<T: Key> Default for BTreeBuilder<T> {
+    fn default() -> BTreeBuilder<T> {
+        BTreeBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use super::super::{BTree, Fanout};
+
+    #[test]
+    fn default_build_matches_new() {
+        let built = BTree::<u32>::builder().build();
+        let new = BTree::<u32>::new();
+
+        assert_eq!(built.fanout(), new.fanout());
+        assert!(built.bloom.is_none());
+        assert!(!built.lazy_removal());
+        assert_eq!(built.estimated_heap_bytes(), 0);
+    }
+
+    #[test]
+    fn options_take_effect() {
+        let mut tree = BTree::<u64>::builder()
+            .bloom(1000)
+            .capacity(20)
+            .fanout(Fanout::new(8, 4))
+            .lazy_removal(true)
+            .build();
+
+        assert!(tree.bloom.is_some());
+        assert!(tree.lazy_removal());
+        assert_eq!(tree.fanout(), Fanout::new(8, 4));
+        // capped at the size at which the root leaf splits
+        let bloom_bytes = tree.bloom.as_ref().unwrap().heap_bytes();
+        assert_eq!(
+            tree.estimated_heap_bytes() - bloom_bytes,
+            9 * mem::size_of::<u64>()
+        );
+
+        for key in 0..1000 {
+            tree.insert(key);
+        }
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert!(tree.fill_stats().max_leaf_keys <= 8);
+        assert!(!tree.find(&5000));
+    }
+
+    #[test]
+    fn small_capacity_reserved_exactly() {
+        let tree = BTree::<u32>::builder().capacity(3).build();
+        assert_eq!(tree.estimated_heap_bytes(), 3 * mem::size_of::<u32>());
+    }
+}
@@ -0,0 +1,92 @@
+use super::Key;
+
+/// Keys with a fixed-width, big-endian binary encoding. This is what the on-disk format stores,
+/// so it must never change for an existing type.
+pub trait ByteKey: Key {
+    /// Number of bytes in the encoding of every key.
+    const WIDTH: usize;
+
+    /// The encoded form; always exactly `WIDTH` bytes long.
+    type Bytes: AsRef<[u8]> + AsMut<[u8]> + Default;
+
+    fn to_be_bytes(&self) -> Self::Bytes;
+
+    fn from_be_bytes(bytes: Self::Bytes) -> Self;
+
+    /// Decodes a key from a slice, which must be exactly `WIDTH` bytes long.
+    fn from_be_slice(slice: &[u8]) -> Self {
+        let mut bytes = Self::Bytes::default();
+        bytes.as_mut().copy_from_slice(slice);
+        Self::from_be_bytes(bytes)
+    }
+}
+
+macro_rules! impl_byte_key {
+    ($t:ty, $width:expr) => {
+        impl ByteKey for $t {
+            const WIDTH: usize = $width;
+
+            type Bytes = [u8; $width];
+
+            fn to_be_bytes(&self) -> [u8; $width] {
+                <$t>::to_be_bytes(*self)
+            }
+
+            fn from_be_bytes(bytes: [u8; $width]) -> $t {
+                <$t>::from_be_bytes(bytes)
+            }
+        }
+    };
+}
+
+impl_byte_key!(u32, 4);
+impl_byte_key!(u64, 8);
+impl_byte_key!(i32, 4);
+impl_byte_key!(i64, 8);
+
+#[cfg(test)]
+mod tests {
+    use super::ByteKey;
+
+    fn round_trip<T: ByteKey + Copy + ::std::fmt::Debug>(values: &[T]) {
+        for value in values {
+            let bytes = ByteKey::to_be_bytes(value);
+            assert_eq!(bytes.as_ref().len(), T::WIDTH);
+            assert_eq!(T::from_be_bytes(bytes), *value);
+            assert_eq!(
+                T::from_be_slice(ByteKey::to_be_bytes(value).as_ref()),
+                *value
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip_extremes() {
+        round_trip(&[u32::MIN, 1, 0x0102_0304, u32::MAX - 1, u32::MAX]);
+        round_trip(&[u64::MIN, 1, 0x0102_0304_0506_0708, u64::MAX - 1, u64::MAX]);
+        round_trip(&[i32::MIN, i32::MIN + 1, -1, 0, 1, i32::MAX - 1, i32::MAX]);
+        round_trip(&[i64::MIN, i64::MIN + 1, -1, 0, 1, i64::MAX - 1, i64::MAX]);
+    }
+
+    #[test]
+    fn encoding_is_big_endian() {
+        assert_eq!(ByteKey::to_be_bytes(&0x0102_0304_u32), [1, 2, 3, 4]);
+        assert_eq!(ByteKey::to_be_bytes(&-2_i32), [0xFF, 0xFF, 0xFF, 0xFE]);
+    }
+
+    #[test]
+    fn unsigned_byte_order_matches_numeric_order() {
+        let mut values = vec![0_u64, 1, 255, 256, 65_535, 1 << 40, u64::MAX - 1, u64::MAX];
+        values.extend((0..1000_u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15)));
+
+        let mut by_bytes = values.clone();
+        by_bytes.sort_by_key(ByteKey::to_be_bytes);
+        values.sort();
+
+        assert_eq!(by_bytes, values);
+
+        let mut small = (0..70_000_u32).rev().collect::<Vec<_>>();
+        small.sort_by_key(ByteKey::to_be_bytes);
+        assert_eq!(small, (0..70_000).collect::<Vec<_>>());
+    }
+}
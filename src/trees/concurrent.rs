@@ -0,0 +1,162 @@
+use std::ops::{Bound, RangeBounds};
+use std::sync::RwLock;
+
+use super::{BTree, Key};
+
+/// A set of keys which may be shared between threads, split by key range into independent
+/// shards, each a `BTree` behind its own read-write lock. Writers into different shards never
+/// wait on each other, and readers only wait on a writer in the same shard.
+///
+/// Each operation on a single key is atomic. Anything which spans shards is not: `range` and
+/// `len` lock one shard at a time, so while other threads are writing they can see some
+/// shards' writes and not others'.
+pub struct ConcurrentBTree<T: Key> {
+    // shard i holds the keys k with boundaries[i - 1] <= k < boundaries[i]
+    boundaries: Vec<T>,
+    shards: Vec<RwLock<BTree<T>>>,
+}
+
+impl<T: Key> ConcurrentBTree<T> {
+    /// One shard for each gap between the given split keys (plus one below the first and one
+    /// above the last), so `n` boundaries give `n + 1` shards. The boundaries must be strictly
+    /// ascending; they are best chosen so that concurrent writers fall in different shards.
+    pub fn with_boundaries(boundaries: Vec<T>) -> ConcurrentBTree<T> {
+        assert!(
+            boundaries.windows(2).all(|pair| pair[0] < pair[1]),
+            "shard boundaries must be strictly ascending"
+        );
+
+        let shards = (0..boundaries.len() + 1)
+            .map(|_| RwLock::new(BTree::new()))
+            .collect();
+        ConcurrentBTree { boundaries, shards }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn shard_of(&self, key: &T) -> usize {
+        self.boundaries.partition_point(|boundary| boundary <= key)
+    }
+
+    pub fn insert(&self, key: T) -> bool {
+        let shard = self.shard_of(&key);
+        self.shards[shard]
+            .write()
+            .expect("shard lock poisoned")
+            .insert(key)
+    }
+
+    pub fn remove(&self, key: &T) -> bool {
+        self.shards[self.shard_of(key)]
+            .write()
+            .expect("shard lock poisoned")
+            .remove(key)
+    }
+
+    pub fn find(&self, key: &T) -> bool {
+        self.shards[self.shard_of(key)]
+            .read()
+            .expect("shard lock poisoned")
+            .find(key)
+    }
+
+    /// Total keys over all shards; not a consistent snapshot while writers are active.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().expect("shard lock poisoned").len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Key + Clone> ConcurrentBTree<T> {
+    /// The keys within `range`, in ascending order. Keys are copied out because no lock is held
+    /// once this returns; shards are visited one at a time, so the result is not atomic across
+    /// shards.
+    pub fn range<R: RangeBounds<T>>(&self, range: R) -> Vec<T> {
+        let first = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => self.shard_of(start),
+            Bound::Unbounded => 0,
+        };
+        let last = match range.end_bound() {
+            Bound::Included(end) | Bound::Excluded(end) => self.shard_of(end),
+            Bound::Unbounded => self.shards.len() - 1,
+        };
+
+        let bounds = (range.start_bound(), range.end_bound());
+        let mut keys = Vec::new();
+        for shard in self.shards[first..=last.max(first)].iter() {
+            let shard = shard.read().expect("shard lock poisoned");
+            keys.extend(shard.range(bounds).cloned());
+        }
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::ConcurrentBTree;
+
+    const THREADS: u64 = 8;
+    const PER_THREAD: u64 = 20_000;
+
+    #[test]
+    fn routes_by_boundary() {
+        let tree = ConcurrentBTree::with_boundaries(vec![10, 20]);
+        assert_eq!(tree.shard_count(), 3);
+
+        for key in [0, 9, 10, 19, 20, 1000].iter() {
+            assert!(tree.insert(*key));
+        }
+        assert!(!tree.insert(10));
+        assert!(tree.find(&19));
+        assert!(!tree.find(&11));
+        assert_eq!(tree.len(), 6);
+
+        assert_eq!(tree.range(5..=19), vec![9, 10, 19]);
+        assert_eq!(tree.range(15..), vec![19, 20, 1000]);
+        assert_eq!(tree.range(..), vec![0, 9, 10, 19, 20, 1000]);
+        assert_eq!(tree.range(12..15), Vec::<u64>::new());
+
+        assert!(tree.remove(&20));
+        assert!(!tree.find(&20));
+    }
+
+    #[test]
+    fn concurrent_disjoint_inserts() {
+        let boundaries = (1..THREADS).map(|i| i * PER_THREAD).collect();
+        let tree = Arc::new(ConcurrentBTree::with_boundaries(boundaries));
+
+        let handles = (0..THREADS)
+            .map(|t| {
+                let tree = Arc::clone(&tree);
+                thread::spawn(move || {
+                    for i in 0..PER_THREAD {
+                        let key = t * PER_THREAD + (i * 7919) % PER_THREAD;
+                        assert!(tree.insert(key));
+                        assert!(tree.find(&key));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(tree.len() as u64, THREADS * PER_THREAD);
+        let all = tree.range(..);
+        assert_eq!(all, (0..THREADS * PER_THREAD).collect::<Vec<_>>());
+        for shard in tree.shards.iter() {
+            assert_eq!(shard.read().unwrap().check_invariants(), Ok(()));
+        }
+    }
+}
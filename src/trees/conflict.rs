@@ -0,0 +1,236 @@
+use std::cmp::Ordering;
+use std::vec::Vec;
+
+use super::{BTree, Key, NodeAlloc};
+
+/// What to do when combining two sets of keys turns up a key on both sides. The left side is the
+/// tree being added to (or the key already stored), the right side the one coming in.
+///
+/// As with `update_many`, there is no map variant of the tree yet, so the keys can only differ in
+/// whatever part of them their ordering ignores. The default is `KeepLeft`.
+///
+/// `F` is the type of the `Merge` function, which can be any closure. Where nothing else pins it
+/// down, `keep_left()` and `keep_right()` give the other two policies without having to name it.
+#[derive(Clone, Copy, Default)]
+pub enum ConflictPolicy<F> {
+    /// Keep the key already on the left and drop the incoming one.
+    #[default]
+    KeepLeft,
+    /// Replace the key on the left with the incoming one.
+    KeepRight,
+    /// Fold the incoming key into the one on the left. The function must not change how the
+    /// left key compares to others.
+    Merge(F),
+}
+
+impl<T> ConflictPolicy<fn(&mut T, T)> {
+    pub fn keep_left() -> ConflictPolicy<fn(&mut T, T)> {
+        ConflictPolicy::KeepLeft
+    }
+
+    pub fn keep_right() -> ConflictPolicy<fn(&mut T, T)> {
+        ConflictPolicy::KeepRight
+    }
+}
+
+impl<F> ConflictPolicy<F> {
+    // resolves `right` into `left`, returning whether `left` may have changed
+    pub(super) fn resolve<T>(&mut self, left: &mut T, right: T) -> bool
+    where
+        F: FnMut(&mut T, T),
+    {
+        match *self {
+            ConflictPolicy::KeepLeft => false,
+            ConflictPolicy::KeepRight => {
+                *left = right;
+                true
+            }
+            ConflictPolicy::Merge(ref mut merge) => {
+                merge(left, right);
+                true
+            }
+        }
+    }
+}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Moves every key of `other` into this tree, leaving `other` empty. Keys present in both are
+    /// resolved by `policy`, with this tree as the left side. Both trees are walked in order and
+    /// this one is rebuilt densely from the merged keys, in time linear in their combined size.
+    pub fn append<F: FnMut(&mut T, T)>(
+        &mut self,
+        other: &mut BTree<T, A>,
+        policy: ConflictPolicy<F>,
+    ) {
+        let left = self.take_sorted_keys();
+        let right = other.take_sorted_keys();
+        self.rebuild_from_sorted(merge_sorted(left, right, policy));
+    }
+
+    /// All the keys of both trees, as one tree with this tree's settings. Keys present in both
+    /// are resolved by `policy`, with this tree as the left side.
+    pub fn union<F: FnMut(&mut T, T)>(
+        mut self,
+        mut other: BTree<T, A>,
+        policy: ConflictPolicy<F>,
+    ) -> BTree<T, A> {
+        self.append(&mut other, policy);
+        self
+    }
+}
+
+// merges two strictly ascending runs into one
+fn merge_sorted<T: Key, F: FnMut(&mut T, T)>(
+    left: Vec<T>,
+    right: Vec<T>,
+    mut policy: ConflictPolicy<F>,
+) -> Vec<T> {
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut right = right.into_iter().peekable();
+
+    for mut key in left {
+        while let Some(incoming) = right.next_if(|incoming| *incoming < key) {
+            merged.push(incoming);
+        }
+        if let Some(incoming) = right.next_if(|incoming| incoming.cmp(&key) == Ordering::Equal) {
+            policy.resolve(&mut key, incoming);
+        }
+        merged.push(key);
+    }
+    merged.extend(right);
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::BTree;
+    use super::ConflictPolicy;
+
+    #[derive(Clone, Debug)]
+    struct Entry {
+        id: u32,
+        value: u32,
+    }
+
+    keyed_by!(Entry, id);
+
+    // ids 0, 2, 4, .. with value 1 on the left; ids 0, 3, 6, .. with value 10 on the right
+    fn sides() -> (BTree<Entry>, BTree<Entry>) {
+        let left = BTree::from_sorted((0..1000).map(|i| Entry {
+            id: 2 * i,
+            value: 1,
+        }));
+        let right = BTree::from_sorted((0..1000).map(|i| Entry {
+            id: 3 * i,
+            value: 10,
+        }));
+        (left, right)
+    }
+
+    fn add(left: &mut Entry, right: Entry) {
+        left.value += right.value;
+    }
+
+    // the value each id should end up with, given what a shared id resolves to
+    fn expected(shared: u32) -> Vec<(u32, u32)> {
+        (0..3000)
+            .filter_map(|id| match (id % 2 == 0 && id < 2000, id % 3 == 0) {
+                (true, true) => Some((id, shared)),
+                (true, false) => Some((id, 1)),
+                (false, true) => Some((id, 10)),
+                (false, false) => None,
+            })
+            .collect()
+    }
+
+    fn contents(tree: &BTree<Entry>) -> Vec<(u32, u32)> {
+        tree.iter().map(|entry| (entry.id, entry.value)).collect()
+    }
+
+    #[test]
+    fn append_with_each_policy() {
+        let policies = [
+            (ConflictPolicy::KeepLeft, 1),
+            (ConflictPolicy::KeepRight, 10),
+            (ConflictPolicy::Merge(add), 11),
+        ];
+
+        for &(policy, shared) in policies.iter() {
+            let (mut left, mut right) = sides();
+            left.append(&mut right, policy);
+
+            assert!(right.is_empty());
+            assert_eq!(left.check_invariants(), Ok(()));
+            assert_eq!(contents(&left), expected(shared));
+        }
+    }
+
+    #[test]
+    fn union_with_each_policy() {
+        let (left, right) = sides();
+        let union = left
+            .clone()
+            .union(right.clone(), ConflictPolicy::keep_left());
+        assert_eq!(contents(&union), expected(1));
+
+        let union = left
+            .clone()
+            .union(right.clone(), ConflictPolicy::keep_right());
+        assert_eq!(contents(&union), expected(10));
+
+        let union = left.union(right, ConflictPolicy::Merge(add));
+        assert_eq!(union.check_invariants(), Ok(()));
+        assert_eq!(contents(&union), expected(11));
+    }
+
+    #[test]
+    fn update_many_with_each_policy() {
+        let policies = [
+            (ConflictPolicy::KeepLeft, 1),
+            (ConflictPolicy::KeepRight, 10),
+            (ConflictPolicy::Merge(add), 11),
+        ];
+
+        for &(policy, shared) in policies.iter() {
+            let (mut left, right) = sides();
+            left.update_many_with(right, policy);
+
+            assert_eq!(left.check_invariants(), Ok(()));
+            assert_eq!(contents(&left), expected(shared));
+        }
+    }
+
+    #[test]
+    fn merge_with_capturing_closures() {
+        let (mut left, mut right) = sides();
+        let mut merges = 0;
+        left.append(
+            &mut right,
+            ConflictPolicy::Merge(|left: &mut Entry, right: Entry| {
+                merges += 1;
+                left.value += right.value;
+            }),
+        );
+        assert_eq!(merges, 334);
+        assert_eq!(contents(&left), expected(11));
+
+        let (mut left, right) = sides();
+        let bonus = 5;
+        left.update_many(right, |left, right| left.value += right.value + bonus);
+        assert_eq!(left.check_invariants(), Ok(()));
+        assert_eq!(contents(&left), expected(16));
+    }
+
+    #[test]
+    fn union_with_empty_sides() {
+        let (left, _) = sides();
+        let union = left
+            .clone()
+            .union(BTree::new(), ConflictPolicy::keep_right());
+        assert_eq!(contents(&union), contents(&left));
+
+        let union = BTree::new().union(left.clone(), ConflictPolicy::keep_left());
+        assert_eq!(contents(&union), contents(&left));
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+use super::persist::corrupt;
+use super::BTree;
+
+impl BTree<u64> {
+    /// Builds a tree from one unsigned integer column of a CSV stream. The first line is taken to
+    /// be a header and skipped; blank lines are ignored. Values are collected, sorted and loaded
+    /// with `from_sorted`, with repeated values kept once.
+    ///
+    /// A row which is missing the column or whose value doesn't parse is an `InvalidData` error
+    /// naming the (1-based) line, rather than being skipped.
+    pub fn from_csv_column<R: Read>(reader: R, column: usize) -> io::Result<BTree<u64>> {
+        let mut values = Vec::new();
+
+        for (i, line) in BufReader::new(reader).lines().enumerate().skip(1) {
+            let line = line?;
+            let line_number = i + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let field = line.split(',').nth(column).ok_or_else(|| {
+                corrupt(format!(
+                    "line {} has no column {}: {:?}",
+                    line_number, column, line
+                ))
+            })?;
+            let field = field.trim().trim_matches('"');
+
+            let value = field.parse::<u64>().map_err(|e| {
+                corrupt(format!(
+                    "line {}, column {}: {:?} is not an unsigned integer ({})",
+                    line_number, column, field, e
+                ))
+            })?;
+            values.push(value);
+        }
+
+        values.sort_unstable();
+        values.dedup();
+        Ok(BTree::from_sorted(values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::BTree;
+
+    #[test]
+    fn reads_out_of_order_column() {
+        let csv = "name,id,score\nbob,42,7\nalice,3,9\n\ncarol, 17 ,1\ndave,\"8\",2\n";
+        let tree = BTree::from_csv_column(csv.as_bytes(), 1).unwrap();
+
+        assert_eq!(tree.iter().cloned().collect::<Vec<_>>(), vec![3, 8, 17, 42]);
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn header_only_is_empty() {
+        let tree = BTree::from_csv_column("a,b\n".as_bytes(), 0).unwrap();
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn bad_value_names_the_line() {
+        let csv = "id\n1\n2\nthree\n4\n";
+        let err = BTree::from_csv_column(csv.as_bytes(), 0).unwrap_err();
+
+        assert!(err.to_string().contains("line 4"), "{}", err);
+        assert!(err.to_string().contains("three"), "{}", err);
+    }
+
+    #[test]
+    fn missing_column_names_the_line() {
+        let csv = "a,b\n1,2\n3\n";
+        let err = BTree::from_csv_column(csv.as_bytes(), 1).unwrap_err();
+
+        assert!(err.to_string().contains("line 3"), "{}", err);
+        assert!(err.to_string().contains("no column 1"), "{}", err);
+    }
+}
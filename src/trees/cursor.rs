@@ -0,0 +1,363 @@
+use std::ops::Bound;
+use std::vec::Vec;
+
+use super::{search_keys, Global, InternalNode, Key, Node, NodeAlloc, NodeRef};
+
+/// A bidirectional position within the sorted keys of a `BTree`.
+///
+/// The cursor always sits in the gap between two adjacent keys (or before the first / after the
+/// last). `next` moves forward over one key and returns it, `prev` moves backward over one key and
+/// returns it, and `peek` shows what `next` would return without moving.
+///
+/// The path is a stack of frames where each internal frame records which child we are inside of,
+/// and the top frame is usually a leaf together with the index of the key just after the gap. A
+/// gap at the far right end of a child can also be left on its parent's frame, with no frames for
+/// the child, which is where `seek` stops when it can tell the child holds nothing it is after.
+pub struct Cursor<'a, T: 'a + Key, A: 'a + NodeAlloc = Global> {
+    root: &'a Node<T, A>,
+    stack: Vec<(NodeRef<'a, T, A>, usize)>,
+}
+
+impl<'a, T: 'a + Key, A: 'a + NodeAlloc> Cursor<'a, T, A> {
+    // A cursor sought to `key`, with the number of keys before it, adding the number of nodes
+    // the descent looked at to `visited`.
+    pub(super) fn new(
+        root: &'a Node<T, A>,
+        key: &T,
+        visited: &mut usize,
+    ) -> (Cursor<'a, T, A>, usize) {
+        let mut cursor = Cursor {
+            root,
+            stack: Vec::new(),
+        };
+        let rank = cursor.seek_counting(key, visited);
+        (cursor, rank)
+    }
+
+    pub(super) fn before_first(root: &'a Node<T, A>) -> Cursor<'a, T, A> {
+        let mut cursor = Cursor {
+            root,
+            stack: Vec::new(),
+        };
+        cursor.descend_leftmost(root);
+        cursor
+    }
+
+    pub(super) fn after_last(root: &'a Node<T, A>) -> Cursor<'a, T, A> {
+        let mut cursor = Cursor {
+            root,
+            stack: Vec::new(),
+        };
+        cursor.descend_rightmost(root);
+        cursor
+    }
+
+    /// Moves the cursor to sit just before the first key which is `>= key`.
+    pub fn seek(&mut self, key: &T) {
+        let mut visited = 0;
+        self.seek_counting(key, &mut visited);
+    }
+
+    // `seek`, adding the number of nodes it looked at to `visited`, and returning the number of
+    // keys before the gap it lands in, as `rank` would count them. The descent stops above a
+    // child when the gap is known to be at its far right: when the separator after it is `key`,
+    // or when its cached bounds show every key it holds is below `key`.
+    fn seek_counting(&mut self, key: &T, visited: &mut usize) -> usize {
+        self.stack.clear();
+        let at_or_above = (Bound::Included(key), Bound::Unbounded);
+        let mut node = self.root;
+        let mut rank = 0;
+
+        loop {
+            *visited += 1;
+            match *node {
+                Node::Leaf(ref leaf) => {
+                    let index = search_keys(&leaf.keys, key).unwrap_or_else(|i| i);
+                    self.stack.push((NodeRef::Leaf(leaf), index));
+                    return rank + index;
+                }
+
+                Node::Internal(ref internal) => {
+                    let (index, stop) = match search_keys(&internal.keys, key) {
+                        Ok(index) => (index, true),
+                        Err(index) => (index, internal.children[index].misses(&at_or_above)),
+                    };
+                    self.stack.push((NodeRef::Internal(internal), index));
+                    rank += index
+                        + internal.children[..index]
+                            .iter()
+                            .map(|child| child.subtree_len())
+                            .sum::<usize>();
+                    if stop {
+                        return rank + internal.children[index].subtree_len();
+                    }
+                    node = &internal.children[index];
+                }
+            }
+        }
+    }
+
+    /// The key just after the cursor, if any.
+    pub fn peek(&self) -> Option<&'a T> {
+        if let Some(&(NodeRef::Leaf(leaf), index)) = self.stack.last() {
+            if index < leaf.num_keys {
+                return Some(&leaf.keys[index]);
+            }
+        }
+
+        self.next_ancestor()
+            .map(|(_, internal, child_index)| &internal.keys[child_index])
+    }
+
+    /// The key just before the cursor, if any.
+    pub fn peek_prev(&self) -> Option<&'a T> {
+        match self.stack.last() {
+            Some(&(NodeRef::Leaf(leaf), index)) if index > 0 => return Some(&leaf.keys[index - 1]),
+            Some(&(NodeRef::Internal(internal), index)) => {
+                if let Some(key) = last_key(&internal.children[index]) {
+                    return Some(key);
+                }
+            }
+            _ => {}
+        }
+
+        self.prev_ancestor()
+            .map(|(_, internal, child_index)| &internal.keys[child_index - 1])
+    }
+
+    /// Moves forward over one key and returns it; at the end, stays put and returns `None`.
+    #[allow(clippy::should_implement_trait)] // a cursor is not an iterator; it also goes backward
+    pub fn next(&mut self) -> Option<&'a T> {
+        if let Some(&mut (NodeRef::Leaf(leaf), ref mut index)) = self.stack.last_mut() {
+            if *index < leaf.num_keys {
+                *index += 1;
+                return Some(&leaf.keys[*index - 1]);
+            }
+        }
+
+        let (level, internal, child_index) = self.next_ancestor()?;
+        self.stack.truncate(level);
+        self.stack
+            .push((NodeRef::Internal(internal), child_index + 1));
+        self.descend_leftmost(&internal.children[child_index + 1]);
+
+        Some(&internal.keys[child_index])
+    }
+
+    /// Moves backward over one key and returns it; at the start, stays put and returns `None`.
+    pub fn prev(&mut self) -> Option<&'a T> {
+        // the keys just behind a gap left on an internal frame are in the child it stopped above
+        if let Some(&(NodeRef::Internal(internal), index)) = self.stack.last() {
+            self.descend_rightmost(&internal.children[index]);
+        }
+        if let Some(&mut (NodeRef::Leaf(leaf), ref mut index)) = self.stack.last_mut() {
+            if *index > 0 {
+                *index -= 1;
+                return Some(&leaf.keys[*index]);
+            }
+        }
+
+        let (level, internal, child_index) = self.prev_ancestor()?;
+        self.stack.truncate(level);
+        self.stack
+            .push((NodeRef::Internal(internal), child_index - 1));
+        self.descend_rightmost(&internal.children[child_index - 1]);
+
+        Some(&internal.keys[child_index - 1])
+    }
+
+    // Nearest ancestor which has a key to the right of the child we're in, as
+    // (stack level, node, index of that child).
+    fn next_ancestor(&self) -> Option<(usize, &'a InternalNode<T, A>, usize)> {
+        for level in (0..self.stack.len()).rev() {
+            if let (NodeRef::Internal(internal), child_index) = self.stack[level] {
+                if child_index < internal.num_keys {
+                    return Some((level, internal, child_index));
+                }
+            }
+        }
+        None
+    }
+
+    // Nearest ancestor which has a key to the left of the child we're in.
+    fn prev_ancestor(&self) -> Option<(usize, &'a InternalNode<T, A>, usize)> {
+        for level in (0..self.stack.len()).rev() {
+            if let (NodeRef::Internal(internal), child_index) = self.stack[level] {
+                if child_index > 0 {
+                    return Some((level, internal, child_index));
+                }
+            }
+        }
+        None
+    }
+
+    fn descend_leftmost(&mut self, mut node: &'a Node<T, A>) {
+        loop {
+            match *node {
+                Node::Internal(ref internal) => {
+                    self.stack.push((NodeRef::Internal(internal), 0));
+                    node = &internal.children[0];
+                }
+                Node::Leaf(ref leaf) => {
+                    self.stack.push((NodeRef::Leaf(leaf), 0));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn descend_rightmost(&mut self, mut node: &'a Node<T, A>) {
+        loop {
+            match *node {
+                Node::Internal(ref internal) => {
+                    self.stack
+                        .push((NodeRef::Internal(internal), internal.num_keys));
+                    node = &internal.children[internal.num_keys];
+                }
+                Node::Leaf(ref leaf) => {
+                    self.stack.push((NodeRef::Leaf(leaf), leaf.num_keys));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// The largest key in the subtree, if it holds any.
+fn last_key<T: Key, A: NodeAlloc>(node: &Node<T, A>) -> Option<&T> {
+    match *node {
+        Node::Leaf(ref leaf) => leaf.keys[..leaf.num_keys].last(),
+        Node::Internal(ref internal) => last_key(&internal.children[internal.num_keys])
+            .or_else(|| internal.keys[..internal.num_keys].last()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BTree, Fanout};
+
+    fn evens(n: u64) -> BTree<u64> {
+        let mut tree = BTree::new();
+        for i in 0..n {
+            tree.insert(2 * i);
+        }
+        tree
+    }
+
+    #[test]
+    fn cursor_walks_forward_and_back() {
+        let tree = evens(1000);
+        let mut cursor = tree.cursor_at(&500);
+
+        assert_eq!(cursor.peek(), Some(&500));
+        assert_eq!(cursor.peek_prev(), Some(&498));
+
+        for expected in 250..300 {
+            assert_eq!(cursor.next(), Some(&(2 * expected)));
+        }
+        for expected in (0..300).rev() {
+            assert_eq!(cursor.prev(), Some(&(2 * expected)));
+        }
+
+        // stepping back past the start stays at the start
+        assert_eq!(cursor.prev(), None);
+        assert_eq!(cursor.prev(), None);
+        assert_eq!(cursor.peek(), Some(&0));
+        assert_eq!(cursor.next(), Some(&0));
+        assert_eq!(cursor.next(), Some(&2));
+    }
+
+    #[test]
+    fn cursor_at_missing_key() {
+        let tree = evens(1000);
+        let mut cursor = tree.cursor_at(&31);
+
+        assert_eq!(cursor.peek(), Some(&32));
+        assert_eq!(cursor.prev(), Some(&30));
+        assert_eq!(cursor.next(), Some(&30));
+        assert_eq!(cursor.next(), Some(&32));
+    }
+
+    #[test]
+    fn cursor_reseek() {
+        let tree = evens(1000);
+        let mut cursor = tree.cursor_at(&100);
+
+        assert_eq!(cursor.next(), Some(&100));
+        assert_eq!(cursor.next(), Some(&102));
+
+        cursor.seek(&1800);
+        assert_eq!(cursor.next(), Some(&1800));
+        assert_eq!(cursor.prev(), Some(&1800));
+        assert_eq!(cursor.prev(), Some(&1798));
+
+        cursor.seek(&1997);
+        assert_eq!(cursor.next(), Some(&1998));
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.prev(), Some(&1998));
+
+        cursor.seek(&5000);
+        assert_eq!(cursor.peek(), None);
+        assert_eq!(cursor.prev(), Some(&1998));
+    }
+
+    #[test]
+    fn cursor_visits_every_key_both_ways() {
+        let tree = evens(2000);
+        let mut cursor = tree.cursor_at(&0);
+
+        let forward = (0..2000)
+            .map(|_| *cursor.next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(forward, tree.iter().cloned().collect::<Vec<_>>());
+        assert_eq!(cursor.next(), None);
+
+        let mut backward = (0..2000)
+            .map(|_| *cursor.prev().unwrap())
+            .collect::<Vec<_>>();
+        backward.reverse();
+        assert_eq!(backward, forward);
+        assert_eq!(cursor.prev(), None);
+    }
+
+    #[test]
+    fn cursor_stopped_above_a_child_steps_both_ways() {
+        // a small fanout puts plenty of separators over internal children, and the lazy removals
+        // leave empty leaves along some of their right edges
+        let mut tree = BTree::with_fanout(Fanout::new(4, 4));
+        for i in 0..3000_u64 {
+            tree.insert(2 * i);
+        }
+        tree.set_lazy_removal(true);
+        for i in (0..3000).filter(|i| i % 3 != 0) {
+            tree.remove(&(2 * i));
+        }
+        assert_eq!(tree.check_lazy_invariants(), Ok(()));
+
+        // every seek, to a separator or into the gap just below one, can stop above a child
+        for separator in tree.separators() {
+            let before = separator.checked_sub(6);
+            for key in [separator.saturating_sub(1), *separator].iter() {
+                let mut cursor = tree.cursor_at(key);
+                assert_eq!(cursor.peek(), Some(separator));
+                assert_eq!(cursor.peek_prev().cloned(), before);
+                assert_eq!(cursor.prev().cloned(), before);
+                if before.is_some() {
+                    assert_eq!(cursor.next().cloned(), before);
+                }
+                assert_eq!(cursor.next(), Some(separator));
+            }
+        }
+    }
+
+    #[test]
+    fn cursor_on_empty_tree() {
+        let tree = BTree::<u32>::new();
+        let mut cursor = tree.cursor_at(&3);
+
+        assert_eq!(cursor.peek(), None);
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.prev(), None);
+    }
+}
@@ -0,0 +1,111 @@
+use super::{BTree, Key, NodeAlloc};
+
+/// An in-order iterator which doesn't borrow the tree, for walking it in steps between which the
+/// borrow has to be given up (say, a lock released). Each step takes the tree back as an
+/// argument and resumes just after the last key it produced.
+///
+/// The tree must not change between steps: `next` panics if it has been modified since the
+/// iterator was created, rather than silently skipping or repeating keys. Only do this with the
+/// tree the iterator was made from; a different tree is not detected.
+pub struct DetachedIter<T: Key + Clone> {
+    last: Option<T>,
+    mod_count: u64,
+    done: bool,
+}
+
+impl<T: Key + Clone, A: NodeAlloc> BTree<T, A> {
+    pub fn detached_iter(&self) -> DetachedIter<T> {
+        DetachedIter {
+            last: None,
+            mod_count: self.mod_count,
+            done: false,
+        }
+    }
+}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Counts changes to the contents of the tree; it is different after any insert, removal or
+    /// in-place update which did something.
+    pub fn mod_count(&self) -> u64 {
+        self.mod_count
+    }
+}
+
+impl<T: Key + Clone> DetachedIter<T> {
+    /// The next key of `tree` in ascending order, found by a fresh descent.
+    ///
+    /// Panics if `tree` has changed since this iterator was created.
+    pub fn next<'a, A: NodeAlloc>(&mut self, tree: &'a BTree<T, A>) -> Option<&'a T> {
+        assert!(
+            tree.mod_count == self.mod_count,
+            "tree was modified during iteration"
+        );
+        if self.done {
+            return None;
+        }
+
+        let next = match self.last {
+            Some(ref last) => tree.successor(last),
+            None => tree.first(),
+        };
+        match next {
+            Some(key) => self.last = Some(key.clone()),
+            None => self.done = true,
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::BTree;
+
+    #[test]
+    fn walks_every_key() {
+        let tree = BTree::from_sorted((0..2000_u32).map(|k| 3 * k));
+        let mut iter = tree.detached_iter();
+
+        let mut seen = Vec::new();
+        while let Some(key) = iter.next(&tree) {
+            seen.push(*key);
+        }
+        assert!(seen.iter().eq(tree.iter()));
+        assert_eq!(iter.next(&tree), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "tree was modified during iteration")]
+    fn panics_after_insert() {
+        let mut tree = BTree::from_sorted(0..100_u32);
+        let mut iter = tree.detached_iter();
+
+        assert_eq!(iter.next(&tree), Some(&0));
+        tree.insert(1000);
+        iter.next(&tree);
+    }
+
+    #[test]
+    #[should_panic(expected = "tree was modified during iteration")]
+    fn panics_after_remove() {
+        let mut tree = BTree::from_sorted(0..100_u32);
+        let mut iter = tree.detached_iter();
+
+        tree.remove(&50);
+        iter.next(&tree);
+    }
+
+    #[test]
+    fn untouched_by_failed_changes() {
+        let mut tree = BTree::from_sorted(0..100_u32);
+        let before = tree.mod_count();
+        let mut iter = tree.detached_iter();
+
+        assert!(!tree.insert(5));
+        assert!(!tree.remove(&500));
+        assert_eq!(tree.mod_count(), before);
+        assert_eq!(iter.next(&tree), Some(&0));
+
+        tree.remove_range(10..20);
+        assert!(tree.mod_count() != before);
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+
+use super::{BTree, Key, Node, NodeAlloc};
+
+impl<T: Key + Display, A: NodeAlloc> BTree<T, A> {
+    /// Writes the tree as a Graphviz digraph, for `dot -Tsvg` and friends. Each node is a record
+    /// listing its keys, with a port in every gap between them; an internal node's edges leave
+    /// from those ports, so children come out drawn in key order beneath the keys they sit
+    /// between.
+    pub fn to_dot<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "digraph btree {{")?;
+        writeln!(w, "    node [shape=record];")?;
+        let mut next_id = 0;
+        write_dot_node(w, &self.root, &mut next_id)?;
+        writeln!(w, "}}")
+    }
+}
+
+// Writes `node` and everything below it, numbering nodes in preorder from `next_id`; returns the
+// number given to `node`.
+fn write_dot_node<T: Key + Display, W: Write, A: NodeAlloc>(
+    w: &mut W,
+    node: &Node<T, A>,
+    next_id: &mut usize,
+) -> io::Result<usize> {
+    let id = *next_id;
+    *next_id += 1;
+
+    let mut label = String::from("<g0> ");
+    for (i, key) in node.keys().iter().enumerate() {
+        label.push_str(&format!(
+            "|{}|<g{}> ",
+            escape_record(&key.to_string()),
+            i + 1
+        ));
+    }
+    writeln!(w, "    n{} [label=\"{}\"];", id, label)?;
+
+    if let Some(children) = node.children() {
+        for (gap, child) in children.iter().enumerate() {
+            let child_id = write_dot_node(w, child, next_id)?;
+            writeln!(w, "    n{}:g{} -> n{};", id, gap, child_id)?;
+        }
+    }
+
+    Ok(id)
+}
+
+// backslash-escapes the characters which mean something inside a record label
+fn escape_record(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "{}|<>\"\\ ".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BTree, Fanout};
+    use super::escape_record;
+
+    #[test]
+    fn dot_declares_every_node_once() {
+        let mut tree = BTree::with_fanout(Fanout::new(4, 4));
+        for i in 0..300_u32 {
+            tree.insert((i * 37) % 300);
+        }
+        assert!(tree.height() > 1);
+
+        let mut out = Vec::new();
+        tree.to_dot(&mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        let lines = dot.lines().map(str::trim).collect::<Vec<_>>();
+
+        assert_eq!(lines.first(), Some(&"digraph btree {"));
+        assert_eq!(lines.last(), Some(&"}"));
+
+        let declarations = lines
+            .iter()
+            .filter(|line| line.starts_with('n') && line.contains(" [label="))
+            .count();
+        let edges = lines.iter().filter(|line| line.contains(" -> ")).count();
+        assert_eq!(declarations, tree.node_count());
+        assert_eq!(edges, tree.node_count() - 1);
+
+        // the root's first child is numbered right after it
+        assert!(lines.contains(&"n0:g0 -> n1;"));
+    }
+
+    #[test]
+    fn dot_of_empty_tree() {
+        let mut out = Vec::new();
+        BTree::<u32>::new().to_dot(&mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+
+        assert!(dot.starts_with("digraph btree {"));
+        assert!(dot.contains("n0 [label=\"<g0> \"];"));
+        assert!(!dot.contains(" -> "));
+    }
+
+    #[test]
+    fn record_labels_are_escaped() {
+        assert_eq!(escape_record("a|b"), "a\\|b");
+        assert_eq!(escape_record("{<\"x\">}"), "\\{\\<\\\"x\\\"\\>\\}");
+        assert_eq!(escape_record("12"), "12");
+    }
+}
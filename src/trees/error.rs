@@ -0,0 +1,78 @@
+use std::error;
+use std::fmt::{self, Display};
+#[cfg(feature = "std")]
+use std::io;
+use std::string::String;
+#[cfg(feature = "std")]
+use std::string::ToString;
+
+/// Why a fallible `BTree` operation failed.
+#[derive(Debug)]
+pub enum Error {
+    /// An equal key is already in the tree.
+    DuplicateKey,
+    /// Reading or writing a file failed.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    /// Stored data failed a consistency check; the message says which one.
+    Corruption(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::DuplicateKey => write!(f, "key is already present"),
+            #[cfg(feature = "std")]
+            Error::Io(ref err) => write!(f, "i/o error: {}", err),
+            Error::Corruption(ref message) => write!(f, "corrupt data: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            #[cfg(feature = "std")]
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// The loaders report damaged files as `InvalidData`, which becomes `Corruption`; every other
+/// kind of failure stays an `Io` error.
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        if err.kind() == io::ErrorKind::InvalidData {
+            Error::Corruption(err.to_string())
+        } else {
+            Error::Io(err)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::error::Error as StdError;
+    use std::io;
+
+    use super::Error;
+
+    #[test]
+    fn io_errors_convert_by_kind() {
+        let damaged = io::Error::new(io::ErrorKind::InvalidData, "checksum mismatch");
+        match Error::from(damaged) {
+            Error::Corruption(message) => assert_eq!(message, "checksum mismatch"),
+            other => panic!("expected corruption, got {:?}", other),
+        }
+
+        let missing = Error::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        match missing {
+            Error::Io(ref err) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+            ref other => panic!("expected an io error, got {:?}", other),
+        }
+        assert!(missing.source().is_some());
+        assert_eq!(missing.to_string(), "i/o error: no such file");
+    }
+}
@@ -0,0 +1,190 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{BTree, ByteKey};
+
+// distinguishes the run files of sorts running at the same time in one process
+static NEXT_SORT_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl<T: ByteKey> BTree<T> {
+    /// Builds a tree from keys in any order (repeats allowed) by an external merge sort: the
+    /// input is cut into runs of about `mem_budget` bytes, each sorted in memory and written to a
+    /// temporary file in `tmp_dir`, and the runs are then merged back, sharing the same budget
+    /// between their read buffers. Input which fits into a single run never touches the disk.
+    ///
+    /// The budget covers the sort only; the finished tree still has to fit in memory. Temporary
+    /// files are removed before returning, whether or not the sort succeeded.
+    pub fn from_unsorted_external<I: Iterator<Item = T>>(
+        keys: I,
+        mem_budget: usize,
+        tmp_dir: &Path,
+    ) -> io::Result<BTree<T>> {
+        let run_len = (mem_budget / mem::size_of::<T>().max(1)).max(1);
+        let mut runs = RunFiles::new(tmp_dir);
+        let mut run = Vec::with_capacity(run_len);
+
+        for key in keys {
+            run.push(key);
+            if run.len() == run_len {
+                runs.write_run(&mut run)?;
+            }
+        }
+
+        if runs.paths.is_empty() {
+            run.sort_unstable();
+            run.dedup();
+            return Ok(BTree::from_sorted(run));
+        }
+        if !run.is_empty() {
+            runs.write_run(&mut run)?;
+        }
+        drop(run);
+
+        let sorted = runs.merge(mem_budget)?;
+        Ok(BTree::from_sorted(sorted))
+    }
+}
+
+// The sorted runs written so far, deleted again when this is dropped.
+struct RunFiles {
+    dir: PathBuf,
+    sort_id: usize,
+    paths: Vec<PathBuf>,
+}
+
+impl RunFiles {
+    fn new(dir: &Path) -> RunFiles {
+        RunFiles {
+            dir: dir.to_path_buf(),
+            sort_id: NEXT_SORT_ID.fetch_add(1, Ordering::Relaxed),
+            paths: Vec::new(),
+        }
+    }
+
+    // Sorts and deduplicates `run`, writes it out as one file of fixed-width keys, and empties it.
+    fn write_run<T: ByteKey>(&mut self, run: &mut Vec<T>) -> io::Result<()> {
+        run.sort_unstable();
+        run.dedup();
+
+        let path = self.dir.join(format!(
+            "catdb-run-{}-{}-{}",
+            process::id(),
+            self.sort_id,
+            self.paths.len()
+        ));
+        // recorded before creating, so that a half-written file is still cleaned up
+        self.paths.push(path.clone());
+
+        let mut out = BufWriter::new(File::create(&path)?);
+        for key in run.drain(..) {
+            out.write_all(key.to_be_bytes().as_ref())?;
+        }
+        out.flush()
+    }
+
+    // k-way merge of every run into one strictly ascending vector.
+    fn merge<T: ByteKey>(&self, mem_budget: usize) -> io::Result<Vec<T>> {
+        let buffer_len = (mem_budget / self.paths.len()).max(T::WIDTH);
+        let mut readers = Vec::with_capacity(self.paths.len());
+        for path in self.paths.iter() {
+            readers.push(BufReader::with_capacity(buffer_len, File::open(path)?));
+        }
+
+        let mut heap = BinaryHeap::with_capacity(readers.len());
+        for (run, reader) in readers.iter_mut().enumerate() {
+            if let Some(key) = read_key::<T>(reader)? {
+                heap.push(Reverse((key, run)));
+            }
+        }
+
+        let mut sorted: Vec<T> = Vec::new();
+        while let Some(Reverse((key, run))) = heap.pop() {
+            if let Some(next) = read_key(&mut readers[run])? {
+                heap.push(Reverse((next, run)));
+            }
+            // runs are deduplicated internally but may share keys with each other
+            if sorted.last() != Some(&key) {
+                sorted.push(key);
+            }
+        }
+        Ok(sorted)
+    }
+}
+
+impl Drop for RunFiles {
+    fn drop(&mut self) {
+        for path in self.paths.iter() {
+            // nothing useful to do if this fails; the file is in a temporary directory anyway
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// The next key of a run, or None at its end.
+fn read_key<T: ByteKey>(reader: &mut BufReader<File>) -> io::Result<Option<T>> {
+    let mut bytes = T::Bytes::default();
+    match reader.read_exact(bytes.as_mut()) {
+        Ok(()) => Ok(Some(T::from_be_bytes(bytes))),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::super::BTree;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("catdb-{}-{}", process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn many_runs_sorted_and_complete() {
+        let dir = temp_dir("external-many-runs");
+
+        // every key appears twice, and a budget of 100 keys forces about 200 runs
+        let n = 10_000_u64;
+        let keys = (0..2 * n).map(|i| (i * 7919) % n);
+        let tree = BTree::from_unsorted_external(keys, 800, &dir).unwrap();
+
+        assert_eq!(tree, BTree::from_sorted(0..n));
+        assert_eq!(tree.check_invariants(), Ok(()));
+
+        // the run files are gone
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn fits_in_one_run() {
+        let dir = temp_dir("external-one-run");
+
+        let keys = vec![5_i32, -3, 9, 5, 0].into_iter();
+        let tree = BTree::from_unsorted_external(keys, 1 << 20, &dir).unwrap();
+
+        assert_eq!(tree.iter().cloned().collect::<Vec<_>>(), vec![-3, 0, 5, 9]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_dir_is_an_error() {
+        let dir = env::temp_dir().join(format!("catdb-{}-external-missing", process::id()));
+        let keys = (0..1000_u32).rev();
+
+        assert!(BTree::from_unsorted_external(keys, 40, &dir).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use super::{Key, Node, NodeAlloc, BTREE_MAX_KEYS};
+
+/// Node size limits for a `BTree`, set separately for leaves and internal nodes. A node splits
+/// when an insert would take it past its maximum, and with the default `SplitPolicy::Median`
+/// every node but the root is kept at least half that full.
+///
+/// The default holds up to 30 keys in every node and splits at the median.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fanout {
+    leaf_max_keys: usize,
+    internal_max_keys: usize,
+    split_policy: SplitPolicy,
+}
+
+/// Where an overfull node is cut in two.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitPolicy {
+    /// Both halves end up about half full, which suits keys arriving in no particular order.
+    Median,
+    /// The left node ends up one key short of the maximum: of an overfull node's `max + 1` keys,
+    /// `max - 1` stay put, one moves up as the separator and the last starts the right node on
+    /// its own. With ascending (append-heavy) inserts the left nodes are never touched again, so
+    /// they stay nearly full instead of half empty. Nodes are no longer guaranteed to be half
+    /// full, so scattered inserts under this policy leave a sparser tree than `Median` would.
+    RightBiased,
+}
+
+impl Fanout {
+    /// Panics if either limit is below 2, since a node must be able to split into two non-empty
+    /// halves around a median.
+    pub fn new(leaf_max_keys: usize, internal_max_keys: usize) -> Fanout {
+        assert!(
+            leaf_max_keys >= 2 && internal_max_keys >= 2,
+            "fanout must allow at least 2 keys per node, got leaves {} / internal {}",
+            leaf_max_keys,
+            internal_max_keys
+        );
+        Fanout {
+            leaf_max_keys,
+            internal_max_keys,
+            split_policy: SplitPolicy::Median,
+        }
+    }
+
+    /// The same limits, splitting according to `policy`.
+    pub fn with_split_policy(self, policy: SplitPolicy) -> Fanout {
+        Fanout {
+            split_policy: policy,
+            ..self
+        }
+    }
+
+    pub fn leaf_max_keys(&self) -> usize {
+        self.leaf_max_keys
+    }
+
+    pub fn internal_max_keys(&self) -> usize {
+        self.internal_max_keys
+    }
+
+    pub fn split_policy(&self) -> SplitPolicy {
+        self.split_policy
+    }
+
+    pub(super) fn max_keys<T: Key, A: NodeAlloc>(&self, node: &Node<T, A>) -> usize {
+        match *node {
+            Node::Leaf(_) => self.leaf_max_keys,
+            Node::Internal(_) => self.internal_max_keys,
+        }
+    }
+
+    pub(super) fn min_keys<T: Key, A: NodeAlloc>(&self, node: &Node<T, A>) -> usize {
+        self.max_keys(node) / 2
+    }
+
+    // where an overfull node (holding one more than its maximum) splits; under `Median` the left
+    // half gets the extra key when the count is odd
+    pub(super) fn median_index<T: Key, A: NodeAlloc>(&self, node: &Node<T, A>) -> usize {
+        self.split_index(self.max_keys(node))
+    }
+
+    // the same, for a node of any representation whose maximum is `max_keys`
+    pub(super) fn split_index(&self, max_keys: usize) -> usize {
+        match self.split_policy {
+            SplitPolicy::Median => max_keys.div_ceil(2),
+            SplitPolicy::RightBiased => max_keys - 1,
+        }
+    }
+}
+
+impl Default for Fanout {
+    fn default() -> Fanout {
+        Fanout::new(BTREE_MAX_KEYS - 1, BTREE_MAX_KEYS - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::scattered;
+    use super::super::BTree;
+    use super::{Fanout, SplitPolicy};
+
+    fn check_fanout(fanout: Fanout) {
+        let mut tree = BTree::with_fanout(fanout);
+        for key in scattered(3000) {
+            tree.insert(key);
+        }
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(
+            tree.iter().cloned().collect::<Vec<_>>(),
+            (0..3000).collect::<Vec<_>>()
+        );
+
+        let stats = tree.fill_stats();
+        assert!(stats.max_leaf_keys <= fanout.leaf_max_keys());
+        assert!(stats.max_internal_keys <= fanout.internal_max_keys());
+
+        for key in scattered(3000).into_iter().filter(|k| k % 4 != 0) {
+            assert!(tree.remove(&key));
+        }
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree.len(), 750);
+
+        for n in [0, 1, 2, 50, 777, 3000].iter() {
+            let mut packed = BTree::with_fanout(fanout);
+            packed.insert_sorted_batch(&(0..*n).collect::<Vec<_>>());
+            assert_eq!(packed.check_invariants(), Ok(()));
+            assert_eq!(packed.len(), *n as usize);
+        }
+    }
+
+    #[test]
+    fn wide_leaves_narrow_internals() {
+        check_fanout(Fanout::new(64, 3));
+    }
+
+    #[test]
+    fn narrow_leaves_wide_internals() {
+        check_fanout(Fanout::new(2, 40));
+    }
+
+    #[test]
+    fn odd_and_even_limits() {
+        check_fanout(Fanout::new(5, 4));
+        check_fanout(Fanout::new(4, 5));
+        check_fanout(Fanout::new(2, 2));
+    }
+
+    #[test]
+    fn default_matches_original_limits() {
+        let mut tree = BTree::new();
+        for i in 0..31_u32 {
+            tree.insert(i);
+        }
+        let stats = tree.fill_stats();
+        assert_eq!((stats.leaf_count, stats.max_leaf_keys), (2, 15));
+    }
+
+    #[test]
+    fn right_biased_split_packs_sequential_inserts() {
+        let average_leaf_fill = |policy| {
+            let mut tree = BTree::with_fanout(Fanout::default().with_split_policy(policy));
+            for i in 0..100_000_u32 {
+                tree.insert(i);
+            }
+            assert_eq!(tree.check_invariants(), Ok(()));
+
+            tree.fill_stats().avg_leaf_keys
+        };
+
+        let median = average_leaf_fill(SplitPolicy::Median);
+        let right_biased = average_leaf_fill(SplitPolicy::RightBiased);
+
+        assert!(median < 16.0, "median fill {}", median);
+        assert!(right_biased > 28.0, "right-biased fill {}", right_biased);
+    }
+
+    #[test]
+    fn right_biased_split_handles_scattered_keys() {
+        check_fanout(Fanout::new(6, 5).with_split_policy(SplitPolicy::RightBiased));
+        check_fanout(Fanout::new(2, 2).with_split_policy(SplitPolicy::RightBiased));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 2 keys")]
+    fn tiny_fanout_rejected() {
+        Fanout::new(1, 8);
+    }
+}
@@ -0,0 +1,46 @@
+// Helpers shared between the test modules.
+
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+
+// Test keys which carry more than they are ordered by, standing in for the map variant the tree
+// doesn't have yet.
+
+// `keyed_by!(Record, id)` makes `Record` equal, ordered and a `Key` by its `id` field alone, so
+// the rest of it rides along as a value.
+macro_rules! keyed_by {
+    ($t:ident, $field:ident) => {
+        impl PartialEq for $t {
+            fn eq(&self, other: &$t) -> bool {
+                self.$field == other.$field
+            }
+        }
+
+        impl Eq for $t {}
+
+        impl PartialOrd for $t {
+            fn partial_cmp(&self, other: &$t) -> Option<::std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for $t {
+            fn cmp(&self, other: &$t) -> ::std::cmp::Ordering {
+                self.$field.cmp(&other.$field)
+            }
+        }
+
+        impl $crate::trees::Key for $t {}
+    };
+}
+
+// The keys `0..n` in a scattered order, each exactly once while `n` doesn't share the factor 7919.
+pub(super) fn scattered(n: u64) -> Vec<u64> {
+    (0..n).map(|i| (i * 7919) % n).collect()
+}
+
+// A path in the system's temporary directory to write test files to, unique to this process.
+#[cfg(feature = "std")]
+pub(super) fn temp_path(name: &str) -> PathBuf {
+    ::std::env::temp_dir().join(format!("catdb-{}-{}", ::std::process::id(), name))
+}
@@ -0,0 +1,142 @@
+use super::BTree;
+
+macro_rules! impl_first_gap {
+    ($t:ty) => {
+        impl BTree<$t> {
+            /// The smallest value `>= start` which is not in the tree, for handing out fresh
+            /// ids. Rather than probing value by value, this binary searches the keys from
+            /// `start` on for the end of the unbroken run beginning there, using `rank` and
+            /// `select`, so it takes O(log² n) however long the run is. Panics if every value
+            /// from `start` up to the type's maximum is taken.
+            pub fn first_gap_from(&self, start: $t) -> $t {
+                let first = self.rank(&start);
+
+                // the key at position `first + offset` is `start + offset` exactly while the run
+                // is unbroken, and larger from the first gap on
+                let in_run = |offset: usize| {
+                    let key = *self.select(first + offset).unwrap();
+                    key as i128 - start as i128 == offset as i128
+                };
+
+                let (mut low, mut high) = (0, self.len() - first);
+                while low < high {
+                    let mid = low + (high - low) / 2;
+                    if in_run(mid) {
+                        low = mid + 1;
+                    } else {
+                        high = mid;
+                    }
+                }
+
+                let gap = start as i128 + low as i128;
+                assert!(
+                    gap <= <$t>::MAX as i128,
+                    "no value from {} up is free",
+                    start
+                );
+                gap as $t
+            }
+
+            /// Whether every value from `start` to `end` inclusive is in the tree; says nothing
+            /// about keys outside that window. Walks the window in order and stops at the first
+            /// gap. An empty window (`start > end`) counts as contiguous.
+            pub fn is_contiguous_range(&self, start: $t, end: $t) -> bool {
+                if start > end {
+                    return true;
+                }
+
+                let mut expected = start as i128;
+                for &key in self.range(start..=end) {
+                    if key as i128 != expected {
+                        return false;
+                    }
+                    expected += 1;
+                }
+                expected == end as i128 + 1
+            }
+        }
+    };
+}
+
+impl_first_gap!(u32);
+impl_first_gap!(u64);
+impl_first_gap!(i32);
+impl_first_gap!(i64);
+
+#[cfg(test)]
+mod tests {
+    use super::super::BTree;
+
+    #[test]
+    fn gap_after_contiguous_block() {
+        let tree = BTree::from_sorted(100..1100_u64);
+
+        assert_eq!(tree.first_gap_from(100), 1100);
+        assert_eq!(tree.first_gap_from(567), 1100);
+        assert_eq!(tree.first_gap_from(1099), 1100);
+
+        // starting outside the block
+        assert_eq!(tree.first_gap_from(99), 99);
+        assert_eq!(tree.first_gap_from(0), 0);
+        assert_eq!(tree.first_gap_from(5000), 5000);
+
+        assert_eq!(BTree::<u64>::new().first_gap_from(7), 7);
+    }
+
+    #[test]
+    fn gap_finds_holes() {
+        let holes = [150, 151, 600, 1099];
+        let tree = BTree::from_sorted((100..1100_i32).filter(|k| !holes.contains(k)));
+
+        assert_eq!(tree.first_gap_from(100), 150);
+        assert_eq!(tree.first_gap_from(150), 150);
+        assert_eq!(tree.first_gap_from(151), 151);
+        assert_eq!(tree.first_gap_from(152), 600);
+        assert_eq!(tree.first_gap_from(601), 1099);
+        assert_eq!(tree.first_gap_from(1100), 1100);
+
+        let negatives = BTree::from_sorted(-50..50_i64);
+        assert_eq!(negatives.first_gap_from(-50), 50);
+        assert_eq!(negatives.first_gap_from(-51), -51);
+    }
+
+    #[test]
+    fn gap_at_the_top_of_the_range() {
+        let tree = BTree::from_sorted(u32::MAX - 10..u32::MAX);
+        assert_eq!(tree.first_gap_from(u32::MAX - 10), u32::MAX);
+    }
+
+    #[test]
+    fn contiguous_block() {
+        let tree = BTree::from_sorted(100..1100_u64);
+
+        assert!(tree.is_contiguous_range(100, 1099));
+        assert!(tree.is_contiguous_range(500, 600));
+        assert!(tree.is_contiguous_range(700, 700));
+        assert!(tree.is_contiguous_range(9, 3));
+
+        assert!(!tree.is_contiguous_range(99, 500));
+        assert!(!tree.is_contiguous_range(1000, 1100));
+        assert!(!BTree::<u64>::new().is_contiguous_range(0, 0));
+    }
+
+    #[test]
+    fn contiguous_range_with_holes() {
+        let tree = BTree::from_sorted((-500..500_i32).filter(|k| *k != 17));
+
+        assert!(!tree.is_contiguous_range(-500, 499));
+        assert!(!tree.is_contiguous_range(17, 30));
+        assert!(!tree.is_contiguous_range(0, 17));
+        assert!(tree.is_contiguous_range(-500, 16));
+        assert!(tree.is_contiguous_range(18, 499));
+
+        let top = BTree::from_sorted(u32::MAX - 5..=u32::MAX);
+        assert!(top.is_contiguous_range(u32::MAX - 5, u32::MAX));
+    }
+
+    #[test]
+    #[should_panic(expected = "is free")]
+    fn gap_panics_when_everything_above_is_taken() {
+        BTree::from_sorted(u32::MAX - 10..=u32::MAX).first_gap_from(u32::MAX - 3);
+    }
+}
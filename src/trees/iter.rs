@@ -0,0 +1,298 @@
+use std::vec;
+use std::vec::Vec;
+
+use super::{into_node, search_keys, BTree, Child, Global, Key, Node, NodeAlloc, NodeRef};
+
+/// In-order iterator over the keys of a `BTree`.
+///
+/// Holds the path from the root to the next key as a stack of `(node, index)` frames; `index` is
+/// the next key to yield from that node, and for an internal node the child to the left of that
+/// key has already been (or is being) visited.
+pub struct Iter<'a, T: 'a + Key, A: 'a + NodeAlloc = Global> {
+    stack: Vec<(NodeRef<'a, T, A>, usize)>,
+}
+
+impl<'a, T: 'a + Key, A: 'a + NodeAlloc> Iter<'a, T, A> {
+    pub(super) fn new(root: &'a Node<T, A>) -> Iter<'a, T, A> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.descend_leftmost(root);
+        iter
+    }
+
+    pub(super) fn starting_at(root: &'a Node<T, A>, start: &T) -> Iter<'a, T, A> {
+        let mut iter = Iter { stack: Vec::new() };
+        let mut node = root;
+
+        loop {
+            match *node {
+                Node::Leaf(ref leaf) => {
+                    let index = search_keys(&leaf.keys, start).unwrap_or_else(|i| i);
+                    iter.stack.push((NodeRef::Leaf(leaf), index));
+                    return iter;
+                }
+
+                Node::Internal(ref internal) => match search_keys(&internal.keys, start) {
+                    Ok(index) => {
+                        // the key itself is next; everything to its left is skipped
+                        iter.stack.push((NodeRef::Internal(internal), index));
+                        return iter;
+                    }
+                    Err(index) => {
+                        iter.stack.push((NodeRef::Internal(internal), index));
+                        node = &internal.children[index];
+                    }
+                },
+            }
+        }
+    }
+
+    fn descend_leftmost(&mut self, mut node: &'a Node<T, A>) {
+        loop {
+            self.stack.push((node.as_node_ref(), 0));
+            match *node {
+                Node::Internal(ref internal) => node = &internal.children[0],
+                Node::Leaf(_) => return,
+            }
+        }
+    }
+}
+
+impl<'a, T: 'a + Key, A: 'a + NodeAlloc> Iterator for Iter<'a, T, A> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            let (node, index) = match self.stack.last_mut() {
+                Some(&mut (node, ref mut index)) => {
+                    *index += 1;
+                    (node, *index - 1)
+                }
+                None => return None,
+            };
+
+            match node {
+                NodeRef::Leaf(leaf) => {
+                    if index < leaf.num_keys {
+                        return Some(&leaf.keys[index]);
+                    }
+                }
+                NodeRef::Internal(internal) => {
+                    if index < internal.num_keys {
+                        self.descend_leftmost(&internal.children[index + 1]);
+                        return Some(&internal.keys[index]);
+                    }
+                }
+            }
+
+            self.stack.pop();
+        }
+    }
+}
+
+/// Owning in-order iterator over the keys of a `BTree`, from `into_iter`.
+///
+/// Nodes are taken apart as the walk reaches them: keys are moved out of each leaf in turn, and a
+/// node is dropped as soon as its last key has been yielded, so the tree's memory is released as
+/// iteration goes rather than all at the end. Dropping the iterator early frees whatever is left.
+pub struct IntoIter<T: Key> {
+    // for each internal node on the path, its keys and children not yet visited; the child to the
+    // left of the next key is the one being drained
+    stack: Vec<(vec::IntoIter<T>, vec::IntoIter<Child<T>>)>,
+    leaf: vec::IntoIter<T>,
+    remaining: usize,
+}
+
+impl<T: Key> IntoIter<T> {
+    fn new(tree: BTree<T>) -> IntoIter<T> {
+        let mut iter = IntoIter {
+            stack: Vec::new(),
+            leaf: Vec::new().into_iter(),
+            remaining: tree.num_keys,
+        };
+        iter.descend_leftmost(tree.root);
+        iter
+    }
+
+    fn descend_leftmost(&mut self, mut node: Node<T>) {
+        loop {
+            match node {
+                Node::Internal(internal) => {
+                    let mut children = internal.children.into_iter();
+                    let first = children
+                        .next()
+                        .expect("internal node has at least one child");
+                    self.stack.push((internal.keys.into_iter(), children));
+                    node = into_node(first);
+                }
+                Node::Leaf(leaf) => {
+                    self.leaf = leaf.keys.into_iter();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<T: Key> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let key = match self.leaf.next() {
+            Some(key) => key,
+            None => loop {
+                let (key, next_child) = match self.stack.last_mut() {
+                    Some(&mut (ref mut keys, ref mut children)) => match keys.next() {
+                        Some(key) => (key, children.next()),
+                        None => {
+                            self.stack.pop();
+                            continue;
+                        }
+                    },
+                    None => return None,
+                };
+
+                let next_child = next_child.expect("internal node has a child after each key");
+                self.descend_leftmost(into_node(next_child));
+                break key;
+            },
+        };
+
+        self.remaining -= 1;
+        Some(key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T: Key> ExactSizeIterator for IntoIter<T> {}
+
+impl<T: Key> IntoIterator for BTree<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::super::BTree;
+
+    fn evens(n: u64) -> BTree<u64> {
+        let mut tree = BTree::new();
+        for i in 0..n {
+            tree.insert(2 * i);
+        }
+        tree
+    }
+
+    #[test]
+    fn iter_is_sorted_and_complete() {
+        let mut tree = BTree::<i32>::new();
+        for i in (0..500).rev() {
+            tree.insert((i * 37) % 500);
+        }
+
+        let keys = tree.iter().cloned().collect::<Vec<_>>();
+        assert_eq!(keys, (0..500).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn iter_empty() {
+        let tree = BTree::<u32>::new();
+        assert_eq!(tree.iter().next(), None);
+        assert_eq!(tree.iter_from(&5).next(), None);
+    }
+
+    #[test]
+    fn iter_from_present_key() {
+        let tree = evens(1000);
+
+        for start in [0, 2, 30, 998, 1000, 1998].iter() {
+            let keys = tree.iter_from(start).cloned().collect::<Vec<_>>();
+            let expected = (*start / 2..1000).map(|i| 2 * i).collect::<Vec<_>>();
+            assert_eq!(keys, expected);
+        }
+    }
+
+    #[test]
+    fn iter_from_between_keys() {
+        let tree = evens(1000);
+
+        for start in [1, 31, 999, 1997].iter() {
+            let keys = tree.iter_from(start).cloned().collect::<Vec<_>>();
+            let expected = (*start / 2 + 1..1000).map(|i| 2 * i).collect::<Vec<_>>();
+            assert_eq!(keys, expected);
+        }
+    }
+
+    #[test]
+    fn iter_from_past_max() {
+        let tree = evens(1000);
+
+        assert_eq!(tree.iter_from(&1999).next(), None);
+        assert_eq!(tree.iter_from(&u64::MAX).next(), None);
+    }
+
+    #[test]
+    fn into_iter_yields_sorted_keys() {
+        let mut tree = BTree::new();
+        for i in 0..5000_u64 {
+            tree.insert((i * 7919) % 5000);
+        }
+
+        let iter = tree.into_iter();
+        assert_eq!(iter.len(), 5000);
+        assert_eq!(iter.collect::<Vec<_>>(), (0..5000).collect::<Vec<_>>());
+
+        assert_eq!(BTree::<u32>::new().into_iter().next(), None);
+    }
+
+    // counts how many are alive, to check what the iterator has freed
+    #[derive(Clone, Debug)]
+    struct Tracked {
+        id: u32,
+        live: Rc<Cell<usize>>,
+    }
+
+    impl Tracked {
+        fn new(id: u32, live: &Rc<Cell<usize>>) -> Tracked {
+            live.set(live.get() + 1);
+            Tracked {
+                id,
+                live: live.clone(),
+            }
+        }
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.live.set(self.live.get() - 1);
+        }
+    }
+
+    keyed_by!(Tracked, id);
+
+    #[test]
+    fn into_iter_frees_as_it_goes_and_when_dropped() {
+        let live = Rc::new(Cell::new(0));
+        let tree = BTree::from_sorted((0..10_000).map(|id| Tracked::new(id, &live)));
+        assert_eq!(live.get(), 10_000);
+
+        let mut iter = tree.into_iter();
+        for expected in 0..5000 {
+            assert_eq!(iter.next().map(|key| key.id), Some(expected));
+        }
+        assert_eq!(live.get(), 5000);
+        assert_eq!(iter.len(), 5000);
+
+        drop(iter);
+        assert_eq!(live.get(), 0);
+    }
+}
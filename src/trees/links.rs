@@ -0,0 +1,297 @@
+// Next-leaf links, which let `scan_leaves_linked` hop from each leaf straight to the one after it
+// instead of climbing back through the ancestors. A link points at the successor leaf, which the
+// same tree owns, so links are only followed while the tree's `LeafChain` vouches for all of
+// them. The insert path keeps them in place as leaves split; anything else which frees or builds
+// leaves either relinks the whole tree or drops the chain until `link_leaves` is called.
+//
+// Under `cow` a leaf can be shared by several trees, each with its own idea of which leaf comes
+// next, so there are no links and the scan always takes the stack-based route.
+
+#[cfg(not(feature = "cow"))]
+use std::ptr::NonNull;
+use std::slice;
+use std::string::String;
+use std::vec::Vec;
+
+use super::{BTree, Child, Key, LeafNode, Node, NodeAlloc};
+
+#[cfg(not(feature = "cow"))]
+pub(super) struct LeafLink<T: Key, A: NodeAlloc>(Option<NonNull<LeafNode<T, A>>>);
+
+#[cfg(feature = "cow")]
+pub(super) struct LeafLink<T: Key, A: NodeAlloc>(::std::marker::PhantomData<(T, A)>);
+
+// The link only ever points into the tree which owns it, so it moves between threads along with
+// the leaf it leads to, and is only followed through a shared reference to that tree.
+#[cfg(not(feature = "cow"))]
+unsafe impl<T: Key + Send, A: NodeAlloc + Send> Send for LeafLink<T, A> {}
+#[cfg(not(feature = "cow"))]
+unsafe impl<T: Key + Sync, A: NodeAlloc + Sync> Sync for LeafLink<T, A> {}
+
+impl<T: Key, A: NodeAlloc> LeafLink<T, A> {
+    #[cfg(not(feature = "cow"))]
+    pub(super) fn none() -> LeafLink<T, A> {
+        LeafLink(None)
+    }
+
+    #[cfg(feature = "cow")]
+    pub(super) fn none() -> LeafLink<T, A> {
+        LeafLink(::std::marker::PhantomData)
+    }
+
+    #[cfg(not(feature = "cow"))]
+    fn set(&mut self, next: Option<&mut LeafNode<T, A>>) {
+        self.0 = next.map(NonNull::from);
+    }
+
+    #[cfg(feature = "cow")]
+    fn set(&mut self, _next: Option<&mut LeafNode<T, A>>) {}
+
+    // The leaf this one links to. Only meaningful while the tree's chain is linked; the caller
+    // vouches for that, and the result borrows from the tree the caller borrowed this leaf from.
+    #[cfg(not(feature = "cow"))]
+    unsafe fn follow<'a>(&self) -> Option<&'a LeafNode<T, A>> {
+        self.0.map(|next| &*next.as_ptr())
+    }
+
+    #[cfg(feature = "cow")]
+    unsafe fn follow<'a>(&self) -> Option<&'a LeafNode<T, A>> {
+        None
+    }
+
+    #[cfg(not(feature = "cow"))]
+    fn points_at(&self, leaf: Option<&LeafNode<T, A>>) -> bool {
+        self.0.map(|next| next.as_ptr() as *const _) == leaf.map(|leaf| leaf as *const _)
+    }
+
+    #[cfg(feature = "cow")]
+    fn points_at(&self, _leaf: Option<&LeafNode<T, A>>) -> bool {
+        true
+    }
+
+    pub(super) fn take(&mut self) -> LeafLink<T, A> {
+        ::std::mem::replace(self, LeafLink::none())
+    }
+}
+
+// a copied leaf belongs to some other tree, so it can't keep this one's link
+impl<T: Key, A: NodeAlloc> Clone for LeafLink<T, A> {
+    fn clone(&self) -> LeafLink<T, A> {
+        LeafLink::none()
+    }
+}
+
+// Whether every leaf of the tree links to the next one in order.
+pub(super) struct LeafChain {
+    linked: bool,
+}
+
+impl LeafChain {
+    pub(super) fn linked() -> LeafChain {
+        LeafChain {
+            linked: !cfg!(feature = "cow"),
+        }
+    }
+
+    pub(super) fn unlink(&mut self) {
+        self.linked = false;
+    }
+
+    pub(super) fn is_linked(&self) -> bool {
+        self.linked
+    }
+}
+
+// the links in a cloned tree would lead back into the original
+impl Clone for LeafChain {
+    fn clone(&self) -> LeafChain {
+        LeafChain { linked: false }
+    }
+}
+
+// After the child at `index` has split, with the new right half just put in at `index + 1`,
+// links the left half to it. The right half took over the left's old link in `split_leaf_node`.
+#[cfg(not(feature = "cow"))]
+pub(super) fn link_split_leaf<T: Key, A: NodeAlloc>(children: &mut [Child<T, A>], index: usize) {
+    let (left, right) = children.split_at_mut(index + 1);
+    let left = super::child_mut(&mut left[index]);
+    let right = super::child_mut(&mut right[0]);
+    if let (&mut Node::Leaf(ref mut left), &mut Node::Leaf(ref mut right)) = (left, right) {
+        left.next.set(Some(right));
+    }
+}
+
+#[cfg(feature = "cow")]
+pub(super) fn link_split_leaf<T: Key, A: NodeAlloc>(_children: &mut [Child<T, A>], _index: usize) {}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Links every leaf to the next one, so that `scan_leaves_linked` can hop along them. Inserts
+    /// keep the links up to date and rebuilds (such as `compact`) put them back, but a removal
+    /// drops them until the tree is relinked. Takes one walk over the nodes; does nothing under
+    /// `cow`, where leaves are shared between trees and are never linked.
+    pub fn link_leaves(&mut self) {
+        let mut previous = None;
+        link_node(&mut self.root, &mut previous);
+        self.leaf_chain = LeafChain::linked();
+    }
+
+    // With the chain linked, checks each leaf links to the one after it.
+    pub(super) fn check_links(&self) -> Result<(), String> {
+        if !self.leaf_chain.is_linked() {
+            return Ok(());
+        }
+        let mut leaves = Vec::new();
+        push_leaves(&self.root, &mut leaves);
+        for (index, leaf) in leaves.iter().enumerate() {
+            if !leaf.next.points_at(leaves.get(index + 1).copied()) {
+                return Err(format!("leaf {} does not link to the leaf after it", index));
+            }
+        }
+        Ok(())
+    }
+
+    /// The same sorted runs as `leaves`, found by following the links between leaves rather than
+    /// a stack of ancestors: each non-empty leaf's keys as one slice, with each separator key as
+    /// a one-key slice between the leaves either side of it. The separators are gathered up front
+    /// with `separators`, which only visits internal nodes. If the leaves aren't linked, say
+    /// after a removal (see `link_leaves`), this falls back to walking the tree like `leaves`.
+    pub fn scan_leaves_linked(&self) -> impl Iterator<Item = &[T]> {
+        let mut fallback = None;
+        let mut next_leaf = None;
+        let mut separators = Vec::new();
+        if self.leaf_chain.is_linked() {
+            let mut node = &self.root;
+            while let Node::Internal(ref internal) = *node {
+                node = &internal.children[0];
+            }
+            if let Node::Leaf(ref leaf) = *node {
+                next_leaf = Some(leaf);
+            }
+            separators = self.separators();
+        } else {
+            fallback = Some(self.leaves());
+        }
+
+        let mut separators = separators.into_iter();
+        let mut separator_due = false;
+        ::std::iter::from_fn(move || {
+            if let Some(ref mut leaves) = fallback {
+                return leaves.next();
+            }
+            loop {
+                if separator_due {
+                    separator_due = false;
+                    if let Some(separator) = separators.next() {
+                        return Some(slice::from_ref(separator));
+                    }
+                }
+                let leaf = next_leaf.take()?;
+                // the chain is linked, so the link leads to the next leaf of this same tree
+                next_leaf = unsafe { leaf.next.follow() };
+                separator_due = true;
+                if !leaf.keys.is_empty() {
+                    return Some(&leaf.keys[..]);
+                }
+            }
+        })
+    }
+}
+
+// Links the leaves under `node` in order, carrying on from `previous`, the leaf before them.
+#[cfg(not(feature = "cow"))]
+fn link_node<T: Key, A: NodeAlloc>(
+    node: &mut Node<T, A>,
+    previous: &mut Option<NonNull<LeafNode<T, A>>>,
+) {
+    match *node {
+        Node::Leaf(ref mut leaf) => {
+            leaf.next = LeafLink::none();
+            if let Some(previous) = *previous {
+                // the previous leaf is a different node of this tree, which nothing else borrows
+                unsafe { (*previous.as_ptr()).next.set(Some(leaf)) };
+            }
+            *previous = Some(NonNull::from(leaf));
+        }
+        Node::Internal(ref mut internal) => {
+            for child in internal.children.iter_mut() {
+                link_node(super::child_mut(child), previous);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "cow")]
+fn link_node<T: Key, A: NodeAlloc>(_node: &mut Node<T, A>, _previous: &mut Option<()>) {}
+
+fn push_leaves<'a, T: Key, A: NodeAlloc>(node: &'a Node<T, A>, out: &mut Vec<&'a LeafNode<T, A>>) {
+    match *node {
+        Node::Leaf(ref leaf) => out.push(leaf),
+        Node::Internal(ref internal) => {
+            for child in internal.children.iter() {
+                push_leaves(child, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::scattered;
+    use super::super::{BTree, Fanout};
+
+    fn joined(tree: &BTree<u64>) -> Vec<u64> {
+        tree.scan_leaves_linked()
+            .flat_map(|run| run.iter().cloned())
+            .collect()
+    }
+
+    #[test]
+    fn linked_scan_matches_stack_iterator_through_splits() {
+        let mut tree = BTree::with_fanout(Fanout::new(4, 4));
+        for (count, key) in scattered(20_000).into_iter().enumerate() {
+            tree.insert(key);
+
+            // check_invariants follows every link, so spot-check them part way through too
+            if count % 2999 == 0 {
+                assert_eq!(tree.check_invariants(), Ok(()));
+                assert_eq!(joined(&tree), tree.iter().cloned().collect::<Vec<_>>());
+            }
+        }
+        assert!(tree.height() >= 6);
+        assert_eq!(tree.leaf_chain.is_linked(), !cfg!(feature = "cow"));
+        assert_eq!(tree.check_invariants(), Ok(()));
+
+        assert_eq!(joined(&tree), (0..20_000).collect::<Vec<_>>());
+        assert!(tree.scan_leaves_linked().eq(tree.leaves()));
+    }
+
+    #[test]
+    fn removals_drop_the_links_until_relinked() {
+        let mut tree = BTree::with_fanout(Fanout::new(4, 4));
+        for key in scattered(3000) {
+            tree.insert(key);
+        }
+        tree.set_lazy_removal(true);
+        for key in (0..3000).filter(|key| key % 3 != 0) {
+            tree.remove(&key);
+        }
+        let expected = (0..3000).filter(|key| key % 3 == 0).collect::<Vec<_>>();
+        assert!(!tree.leaf_chain.is_linked());
+        assert_eq!(joined(&tree), expected);
+
+        // the lazy removals left empty leaves for the links to pass over
+        tree.link_leaves();
+        assert_eq!(tree.leaf_chain.is_linked(), !cfg!(feature = "cow"));
+        assert_eq!(tree.check_lazy_invariants(), Ok(()));
+        assert_eq!(joined(&tree), expected);
+
+        let copy = tree.clone();
+        assert!(!copy.leaf_chain.is_linked());
+        assert_eq!(joined(&copy), expected);
+
+        tree.compact();
+        assert_eq!(tree.leaf_chain.is_linked(), !cfg!(feature = "cow"));
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(joined(&tree), expected);
+    }
+}
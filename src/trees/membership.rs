@@ -0,0 +1,245 @@
+use std::vec::Vec;
+
+use super::{BTree, Key, NodeAlloc};
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Whether every key of `keys` is in the tree; stops at the first one which isn't.
+    ///
+    /// If `keys` is sorted ascending (duplicates allowed), this is answered by one walk of the
+    /// tree's iterator from the smallest query key, merged against the slice, instead of a
+    /// separate descent per key. Otherwise each key is looked up with `find`.
+    pub fn contains_all(&self, keys: &[T]) -> bool {
+        if !is_sorted(keys) {
+            return keys.iter().all(|key| self.find(key));
+        }
+
+        let mut walk = match keys.first() {
+            Some(first) => self.iter_from(first).peekable(),
+            None => return true,
+        };
+        for key in keys {
+            while walk.next_if(|k| *k < key).is_some() {}
+            match walk.peek() {
+                Some(k) if *k == key => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Whether at least one key of `keys` is in the tree; stops at the first one which is.
+    ///
+    /// Takes the same merged-walk fast path as `contains_all` when `keys` is sorted ascending.
+    pub fn contains_any(&self, keys: &[T]) -> bool {
+        if !is_sorted(keys) {
+            return keys.iter().any(|key| self.find(key));
+        }
+
+        let mut walk = match keys.first() {
+            Some(first) => self.iter_from(first).peekable(),
+            None => return false,
+        };
+        for key in keys {
+            while walk.next_if(|k| *k < key).is_some() {}
+            match walk.peek() {
+                Some(k) if *k == key => return true,
+                Some(_) => {}
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Looks up each of `sorted_keys`, which must be in ascending order, returning the stored
+    /// key for each (as `get_key_value` would) in the same order as the input. The lookups share
+    /// one walk of the tree's iterator rather than descending once per key.
+    ///
+    /// There is no map variant of the tree yet, so this returns the stored keys; a map would
+    /// return the values alongside them.
+    pub fn get_many_sorted<'a>(&'a self, sorted_keys: &[T]) -> Vec<Option<&'a T>> {
+        debug_assert!(is_sorted(sorted_keys), "get_many_sorted needs sorted input");
+
+        let mut found = Vec::with_capacity(sorted_keys.len());
+        let mut walk = match sorted_keys.first() {
+            Some(first) => self.iter_from(first).peekable(),
+            None => return found,
+        };
+        for key in sorted_keys {
+            while walk.next_if(|k| *k < key).is_some() {}
+            found.push(walk.peek().cloned().filter(|k| *k == key));
+        }
+        found
+    }
+
+    /// Whether every key of this tree is also in `other`, found by walking both in order
+    /// together. Stops at the first key `other` lacks.
+    pub fn is_subset(&self, other: &BTree<T, A>) -> bool {
+        if self.len() > other.len() {
+            return false;
+        }
+
+        let mut theirs = other.iter().peekable();
+        for key in self.iter() {
+            while theirs.next_if(|k| *k < key).is_some() {}
+            if theirs.next_if(|k| *k == key).is_none() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The keys in both trees, ascending, found by walking the two in order together in
+    /// O(n + m) and yielding references into this tree without building a result.
+    pub fn inner_join<'a>(&'a self, other: &'a BTree<T, A>) -> impl Iterator<Item = &'a T> {
+        let mut ours = self.iter();
+        let mut theirs = other.iter().peekable();
+
+        ::std::iter::from_fn(move || {
+            for key in ours.by_ref() {
+                while theirs.next_if(|k| *k < key).is_some() {}
+                match theirs.peek() {
+                    Some(k) if *k == key => {
+                        theirs.next();
+                        return Some(key);
+                    }
+                    Some(_) => {}
+                    None => return None,
+                }
+            }
+            None
+        })
+    }
+}
+
+fn is_sorted<T: Key>(keys: &[T]) -> bool {
+    keys.windows(2).all(|pair| pair[0] <= pair[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::BTree;
+
+    // multiples of 3 below 3000
+    fn threes() -> BTree<u32> {
+        BTree::from_sorted((0..1000).map(|i| i * 3))
+    }
+
+    #[test]
+    fn inner_join_matches_intersection() {
+        let left = BTree::from_sorted(0..10_000_u32);
+        let right = BTree::from_sorted((0..10_000).map(|i| 5000 + i * 3));
+
+        let joined = left.inner_join(&right).cloned().collect::<Vec<_>>();
+        let expected = (5000..10_000)
+            .filter(|k| (k - 5000) % 3 == 0)
+            .collect::<Vec<_>>();
+        assert_eq!(joined, expected);
+        assert!(right
+            .inner_join(&left)
+            .cloned()
+            .eq(expected.iter().cloned()));
+
+        assert_eq!(left.inner_join(&threes()).count(), 1000);
+        assert_eq!(left.inner_join(&BTree::new()).next(), None);
+        assert_eq!(
+            BTree::from_sorted(vec![1_u32, 2])
+                .inner_join(&BTree::from_sorted(vec![3, 4]))
+                .next(),
+            None
+        );
+    }
+
+    #[test]
+    fn subset() {
+        let tree = threes();
+        let sorted = [0, 3, 3, 300, 2997];
+        let unsorted = [2997, 0, 300, 3];
+
+        assert!(tree.contains_all(&sorted));
+        assert!(tree.contains_all(&unsorted));
+        assert!(tree.contains_any(&sorted));
+        assert!(tree.contains_any(&unsorted));
+    }
+
+    #[test]
+    fn superset() {
+        let tree = BTree::from_sorted(vec![10, 20, 30]);
+        let sorted = (0..40).collect::<Vec<u32>>();
+        let unsorted = sorted.iter().rev().cloned().collect::<Vec<_>>();
+
+        assert!(!tree.contains_all(&sorted));
+        assert!(!tree.contains_all(&unsorted));
+        assert!(tree.contains_any(&sorted));
+        assert!(tree.contains_any(&unsorted));
+    }
+
+    #[test]
+    fn disjoint() {
+        let tree = threes();
+        let sorted = [1, 2, 4, 1000, 2999, 5000];
+        let unsorted = [5000, 1, 2999, 4];
+
+        assert!(!tree.contains_all(&sorted));
+        assert!(!tree.contains_all(&unsorted));
+        assert!(!tree.contains_any(&sorted));
+        assert!(!tree.contains_any(&unsorted));
+    }
+
+    #[test]
+    fn partial_overlap() {
+        let tree = threes();
+        let sorted = [1, 2, 4, 999, 1000];
+        let unsorted = [1000, 999, 4, 2, 1];
+
+        assert!(!tree.contains_all(&sorted));
+        assert!(!tree.contains_all(&unsorted));
+        assert!(tree.contains_any(&sorted));
+        assert!(tree.contains_any(&unsorted));
+    }
+
+    #[test]
+    fn get_many_sorted_matches_get_key_value() {
+        let tree = threes();
+        let keys = [0, 1, 3, 3, 4, 299, 300, 2997, 2998, 9000];
+
+        let many = tree.get_many_sorted(&keys);
+        let single = keys
+            .iter()
+            .map(|k| tree.get_key_value(k))
+            .collect::<Vec<_>>();
+        assert_eq!(many, single);
+        assert_eq!(many[2], Some(&3));
+        assert_eq!(many[1], None);
+        assert!(tree.get_many_sorted(&[]).is_empty());
+    }
+
+    #[test]
+    fn subsets() {
+        let all = threes();
+        let some = BTree::from_sorted((0..100).map(|i| i * 30));
+        let other = BTree::from_sorted((0..100).map(|i| i * 30 + 1));
+        let empty = BTree::new();
+
+        assert!(some.is_subset(&all));
+        assert!(!all.is_subset(&some));
+        assert!(all.is_subset(&threes()));
+        assert!(!other.is_subset(&all));
+        assert!(!all.is_subset(&other));
+        assert!(empty.is_subset(&all));
+        assert!(empty.is_subset(&BTree::new()));
+        assert!(!all.is_subset(&empty));
+
+        let mut almost = threes();
+        almost.remove(&2997);
+        almost.insert(2998);
+        assert!(!almost.is_subset(&all));
+    }
+
+    #[test]
+    fn empty_query() {
+        let tree = threes();
+        assert!(tree.contains_all(&[]));
+        assert!(!tree.contains_any(&[]));
+        assert!(!BTree::new().contains_any(&[1_u32]));
+    }
+}
@@ -0,0 +1,492 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use super::persist::{copy_array, corrupt};
+use super::{BTree, ByteKey};
+
+// Layout: a sequence of `PAGE_SIZE`-byte pages, all integers big-endian.
+//
+// Page 0 is the header:
+//
+//   magic        4 bytes, `MAGIC`
+//   version      1 byte, `FORMAT_VERSION`
+//   key width    1 byte, `ByteKey::WIDTH` of the keys that were written
+//   page size    4 bytes
+//   key count    8 bytes
+//   leaf count   8 bytes
+//   page count   8 bytes, including the header page
+//   root page    8 bytes
+//   height       1 byte, counting the leaf level
+//
+// Unlike the in-memory tree, the file is laid out B+ style: every key lives in a leaf, and the
+// leaves fill pages 1 through `leaf count` in key order, so a range scan just reads consecutive
+// pages. Each internal page repeats the first key of every child but the first as separators.
+//
+//   leaf page      kind `LEAF`, 2-byte key count, the keys
+//   internal page  kind `INTERNAL`, 2-byte child count, (children - 1) separator keys, then an
+//                  8-byte page number per child
+const MAGIC: [u8; 4] = *b"CTDP";
+const FORMAT_VERSION: u8 = 1;
+const PAGE_SIZE: usize = 4096;
+const HEADER_LEN: usize = 43;
+const PAGE_HEADER_LEN: usize = 3;
+
+const LEAF: u8 = 0;
+const INTERNAL: u8 = 1;
+
+// How many keys fit in a leaf page, and how many children in an internal page, for a key width.
+fn leaf_capacity(width: usize) -> usize {
+    (PAGE_SIZE - PAGE_HEADER_LEN) / width
+}
+
+fn internal_capacity(width: usize) -> usize {
+    (PAGE_SIZE - PAGE_HEADER_LEN + width) / (width + 8)
+}
+
+impl<T: ByteKey> BTree<T> {
+    /// Writes every key to `path` in the page format read by `open_mmap`, replacing whatever was
+    /// there. Fails with `InvalidInput` if the keys are too wide for two children to share a page.
+    pub fn save_paged(&self, path: &Path) -> io::Result<()> {
+        let width = T::WIDTH;
+        if internal_capacity(width) < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}-byte keys are too wide for {}-byte pages",
+                    width, PAGE_SIZE
+                ),
+            ));
+        }
+
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&[0; PAGE_SIZE])?; // the header is filled in once the page counts are known
+
+        // (first key, page number) of every node on the level just written
+        let mut level = Vec::new();
+        let mut next_page = 1_u64;
+
+        for chunk in self.iter_chunks(leaf_capacity(width)) {
+            let mut page = vec![0; PAGE_SIZE];
+            page[0] = LEAF;
+            page[1..3].copy_from_slice(&(chunk.len() as u16).to_be_bytes());
+            for (i, key) in chunk.iter().enumerate() {
+                let start = PAGE_HEADER_LEN + i * width;
+                page[start..start + width].copy_from_slice(key.to_be_bytes().as_ref());
+            }
+            out.write_all(&page)?;
+
+            level.push((
+                page[PAGE_HEADER_LEN..PAGE_HEADER_LEN + width].to_vec(),
+                next_page,
+            ));
+            next_page += 1;
+        }
+
+        if level.is_empty() {
+            let mut page = vec![0; PAGE_SIZE];
+            page[0] = LEAF;
+            out.write_all(&page)?;
+
+            level.push((Vec::new(), next_page));
+            next_page += 1;
+        }
+
+        let leaf_count = next_page - 1;
+        let mut height = 1_u8;
+
+        while level.len() > 1 {
+            let mut parents = Vec::new();
+
+            for children in level.chunks(internal_capacity(width)) {
+                let mut page = vec![0; PAGE_SIZE];
+                page[0] = INTERNAL;
+                page[1..3].copy_from_slice(&(children.len() as u16).to_be_bytes());
+
+                let mut start = PAGE_HEADER_LEN;
+                for (first_key, _) in &children[1..] {
+                    page[start..start + width].copy_from_slice(first_key);
+                    start += width;
+                }
+                for &(_, child_page) in children {
+                    page[start..start + 8].copy_from_slice(&child_page.to_be_bytes());
+                    start += 8;
+                }
+                out.write_all(&page)?;
+
+                parents.push((children[0].0.clone(), next_page));
+                next_page += 1;
+            }
+
+            level = parents;
+            height += 1;
+        }
+
+        let mut header = [0; HEADER_LEN];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4] = FORMAT_VERSION;
+        header[5] = width as u8;
+        header[6..10].copy_from_slice(&(PAGE_SIZE as u32).to_be_bytes());
+        header[10..18].copy_from_slice(&(self.size() as u64).to_be_bytes());
+        header[18..26].copy_from_slice(&leaf_count.to_be_bytes());
+        header[26..34].copy_from_slice(&next_page.to_be_bytes());
+        header[34..42].copy_from_slice(&level[0].1.to_be_bytes());
+        header[42] = height;
+
+        out.seek(SeekFrom::Start(0))?;
+        out.write_all(&header)?;
+        out.flush()
+    }
+
+    /// Maps a file written by `save_paged` and returns a read-only view over it. Only the header
+    /// is checked up front; node pages are read straight from the map as lookups reach them, so
+    /// opening costs the same however many keys the file holds.
+    pub fn open_mmap(path: &Path) -> io::Result<MmapBTree<T>> {
+        let file = File::open(path)?;
+
+        // SAFETY: the map is only ever read, and every read goes through a bounds-checked slice.
+        // What this cannot guard against is another process truncating or rewriting the file
+        // while it is mapped, which is why `MmapBTree` documents that the file must be left alone.
+        let map = unsafe { Mmap::map(&file)? };
+
+        if map.len() < HEADER_LEN {
+            return Err(corrupt("file is too short to hold a header".to_string()));
+        }
+        if map[0..4] != MAGIC {
+            return Err(corrupt(
+                "not a paged catdb file (bad magic number)".to_string(),
+            ));
+        }
+        if map[4] != FORMAT_VERSION {
+            return Err(corrupt(format!(
+                "unsupported page format version {} (expected {})",
+                map[4], FORMAT_VERSION
+            )));
+        }
+        if map[5] as usize != T::WIDTH {
+            return Err(corrupt(format!(
+                "file holds {}-byte keys but this tree uses {}-byte keys",
+                map[5],
+                T::WIDTH
+            )));
+        }
+
+        let page_size = u32::from_be_bytes(copy_array(&map[6..10])) as usize;
+        if page_size != PAGE_SIZE {
+            return Err(corrupt(format!(
+                "file uses {}-byte pages but this build reads {}-byte pages",
+                page_size, PAGE_SIZE
+            )));
+        }
+
+        let len = u64::from_be_bytes(copy_array(&map[10..18]));
+        let leaf_count = u64::from_be_bytes(copy_array(&map[18..26]));
+        let page_count = u64::from_be_bytes(copy_array(&map[26..34]));
+        let root = u64::from_be_bytes(copy_array(&map[34..42]));
+        let height = map[42] as usize;
+
+        if (map.len() as u64) != page_count.saturating_mul(PAGE_SIZE as u64) {
+            return Err(corrupt(format!(
+                "header promises {} pages ({} bytes) but the file has {} bytes",
+                page_count,
+                page_count.saturating_mul(PAGE_SIZE as u64),
+                map.len()
+            )));
+        }
+        if leaf_count == 0 || leaf_count >= page_count || root == 0 || root >= page_count {
+            return Err(corrupt(format!(
+                "header page numbers are out of range (leaves 1..={}, root {}, {} pages)",
+                leaf_count, root, page_count
+            )));
+        }
+        if height == 0 {
+            return Err(corrupt("header says the tree has no levels".to_string()));
+        }
+
+        Ok(MmapBTree {
+            map,
+            len: len as usize,
+            leaf_count,
+            page_count,
+            root,
+            height,
+            _keys: PhantomData,
+        })
+    }
+}
+
+/// A read-only tree served straight out of a memory-mapped file written by `save_paged`.
+///
+/// Pages are decoded on demand, so only the parts of the file that lookups actually touch are
+/// ever read from disk. The file must not be modified or truncated while the view is open.
+/// Internal pages are trusted once the header checks out; a lookup that runs into a damaged page
+/// panics rather than returning a wrong answer.
+pub struct MmapBTree<T: ByteKey> {
+    map: Mmap,
+    len: usize,
+    leaf_count: u64,
+    page_count: u64,
+    root: u64,
+    height: usize,
+    _keys: PhantomData<T>,
+}
+
+impl<T: ByteKey> MmapBTree<T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of levels on disk, counting the leaves; a tree which fits one leaf page has height 1.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn find(&self, key: &T) -> bool {
+        let (page, index) = self.lower_bound(key);
+        index < key_count(self.page(page)) && key_at::<T>(self.page(page), index) == *key
+    }
+
+    /// Every key in `range`, ascending, decoded from consecutive leaf pages.
+    pub fn range<R: RangeBounds<T>>(&self, range: R) -> impl Iterator<Item = T> + '_ {
+        let (mut page, mut index) = match range.start_bound() {
+            Bound::Unbounded => (1, 0),
+            Bound::Included(start) => self.lower_bound(start),
+            Bound::Excluded(start) => {
+                let (page, index) = self.lower_bound(start);
+                if index < key_count(self.page(page))
+                    && key_at::<T>(self.page(page), index) == *start
+                {
+                    (page, index + 1)
+                } else {
+                    (page, index)
+                }
+            }
+        };
+
+        let end = match range.end_bound() {
+            Bound::Unbounded => Bound::Unbounded,
+            Bound::Included(end) => Bound::Included(copy_key(end)),
+            Bound::Excluded(end) => Bound::Excluded(copy_key(end)),
+        };
+
+        ::std::iter::from_fn(move || {
+            while page <= self.leaf_count {
+                let leaf = self.page(page);
+                if index < key_count(leaf) {
+                    let key = key_at::<T>(leaf, index);
+                    let in_range = match end {
+                        Bound::Unbounded => true,
+                        Bound::Included(ref end) => key <= *end,
+                        Bound::Excluded(ref end) => key < *end,
+                    };
+                    if !in_range {
+                        page = self.leaf_count + 1;
+                        return None;
+                    }
+                    index += 1;
+                    return Some(key);
+                }
+                page += 1;
+                index = 0;
+            }
+            None
+        })
+    }
+
+    // The leaf page and index of the first key which is `>= key`; the index may be one past the
+    // end of that leaf, in which case the key (if any) starts the next leaf page.
+    fn lower_bound(&self, key: &T) -> (u64, usize) {
+        let mut page_number = self.root;
+
+        for _ in 1..self.height {
+            let page = self.page(page_number);
+            assert_eq!(
+                page[0], INTERNAL,
+                "corrupt paged tree: expected an internal page"
+            );
+            let children = key_count(page);
+            assert!(
+                children >= 1 && children <= internal_capacity(T::WIDTH),
+                "corrupt paged tree: internal page {} has {} children",
+                page_number,
+                children
+            );
+
+            // child i holds keys from separator i-1 on, so descend into the number of separators <= key
+            let (mut low, mut high) = (0, children - 1);
+            while low < high {
+                let mid = (low + high) / 2;
+                if key_at::<T>(page, mid) <= *key {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+
+            let start = PAGE_HEADER_LEN + (children - 1) * T::WIDTH + low * 8;
+            page_number = u64::from_be_bytes(copy_array(&page[start..start + 8]));
+        }
+
+        let page = self.page(page_number);
+        assert!(
+            page[0] == LEAF && page_number <= self.leaf_count,
+            "corrupt paged tree: expected page {} to be a leaf",
+            page_number
+        );
+
+        let (mut low, mut high) = (0, key_count(page));
+        while low < high {
+            let mid = (low + high) / 2;
+            if key_at::<T>(page, mid) < *key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        (page_number, low)
+    }
+
+    fn page(&self, number: u64) -> &[u8] {
+        assert!(
+            number >= 1 && number < self.page_count,
+            "corrupt paged tree: page {} is out of range",
+            number
+        );
+        let start = number as usize * PAGE_SIZE;
+        let page = &self.map[start..start + PAGE_SIZE];
+        assert!(
+            key_count(page) <= leaf_capacity(T::WIDTH),
+            "corrupt paged tree: page {} claims {} entries",
+            number,
+            key_count(page)
+        );
+        page
+    }
+}
+
+fn key_count(page: &[u8]) -> usize {
+    u16::from_be_bytes([page[1], page[2]]) as usize
+}
+
+fn key_at<T: ByteKey>(page: &[u8], index: usize) -> T {
+    let start = PAGE_HEADER_LEN + index * T::WIDTH;
+    T::from_be_slice(&page[start..start + T::WIDTH])
+}
+
+fn copy_key<T: ByteKey>(key: &T) -> T {
+    T::from_be_bytes(key.to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+
+    use super::super::fixtures::temp_path;
+    use super::super::BTree;
+    use super::PAGE_SIZE;
+
+    #[test]
+    fn mmap_find_matches_in_memory_tree() {
+        let path = temp_path("mmap-find");
+
+        let mut tree = BTree::<u64>::new();
+        for i in 0..200_000_u64 {
+            tree.insert((i * 7919) % 1_000_003);
+        }
+        tree.save_paged(&path).unwrap();
+
+        let mapped = BTree::<u64>::open_mmap(&path).unwrap();
+        assert_eq!(mapped.len(), tree.size());
+        assert_eq!(mapped.height(), 3);
+
+        for probe in (0..1_000_003_u64).step_by(997).chain(vec![u64::MAX]) {
+            assert_eq!(mapped.find(&probe), tree.find(&probe), "probe {}", probe);
+        }
+
+        drop(mapped);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mmap_range_matches_in_memory_tree() {
+        let path = temp_path("mmap-range");
+
+        let tree = BTree::<i32>::from_sorted((-50_000..50_000).map(|i| 3 * i));
+        tree.save_paged(&path).unwrap();
+        let mapped = BTree::<i32>::open_mmap(&path).unwrap();
+
+        let whole = mapped.range(..).collect::<Vec<_>>();
+        assert_eq!(whole, tree.iter().cloned().collect::<Vec<_>>());
+
+        assert_eq!(
+            mapped.range(-10..=9).collect::<Vec<_>>(),
+            vec![-9, -6, -3, 0, 3, 6, 9]
+        );
+        assert_eq!(
+            mapped
+                .range((
+                    ::std::ops::Bound::Excluded(-9),
+                    ::std::ops::Bound::Excluded(9)
+                ))
+                .collect::<Vec<_>>(),
+            vec![-6, -3, 0, 3, 6]
+        );
+        assert_eq!(
+            mapped.range(149_990..).collect::<Vec<_>>(),
+            vec![149_991, 149_994, 149_997]
+        );
+        assert_eq!(mapped.range(150_000..).next(), None);
+
+        drop(mapped);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mmap_empty_tree() {
+        let path = temp_path("mmap-empty");
+
+        BTree::<u32>::new().save_paged(&path).unwrap();
+        let mapped = BTree::<u32>::open_mmap(&path).unwrap();
+
+        assert!(mapped.is_empty());
+        assert!(!mapped.find(&0));
+        assert_eq!(mapped.range(..).next(), None);
+
+        drop(mapped);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mmap_rejects_bad_header() {
+        let path = temp_path("mmap-bad");
+
+        BTree::<u32>::from_sorted(0..10_000)
+            .save_paged(&path)
+            .unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        assert!(BTree::<u64>::open_mmap(&path).is_err());
+
+        fs::write(&path, &bytes[..bytes.len() - PAGE_SIZE]).unwrap();
+        let err = BTree::<u32>::open_mmap(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("pages"));
+
+        let mut damaged = bytes.clone();
+        damaged[0] = b'X';
+        fs::write(&path, &damaged).unwrap();
+        let err = BTree::<u32>::open_mmap(&path).err().unwrap();
+        assert!(err.to_string().contains("magic"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
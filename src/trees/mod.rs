@@ -1,424 +1,2888 @@
 use std::cmp::Ord;
 use std::cmp::Ordering;
 
-use std::fmt::{Debug, Display};
-use std::iter;
+#[cfg(feature = "std")]
+use std::error::Error as StdError;
+use std::fmt::{self, Debug, Display};
+use std::hash::{Hash, Hasher};
 use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::string::String;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use self::bloom::BloomFilter;
+use self::links::{link_split_leaf, LeafChain, LeafLink};
+#[cfg(feature = "cow")]
+use self::node_alloc::{arc_in, NodeArc};
+#[cfg(not(feature = "cow"))]
+use self::node_alloc::{box_in, NodeBox};
+use self::node_alloc::{vec_in, NodeVec};
+
+// first, so the test modules below can use its macros
+#[cfg(test)]
+#[macro_use]
+mod fixtures;
+
+mod arena;
+mod bloom;
+mod bounded;
+mod bplus;
+mod builder;
+mod bytekey;
+#[cfg(feature = "std")]
+mod concurrent;
+mod conflict;
+#[cfg(feature = "csv")]
+mod csv;
+mod cursor;
+mod detached;
+#[cfg(feature = "std")]
+mod dot;
+mod error;
+#[cfg(feature = "std")]
+mod external;
+mod fanout;
+mod gap;
+mod iter;
+mod links;
+mod membership;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod node_alloc;
+mod nodes;
+mod ops;
+mod parallel;
+#[cfg(feature = "std")]
+mod persist;
+mod prefix;
+#[cfg(test)]
+mod properties;
+mod range;
+mod rank;
+mod remove;
+#[cfg(feature = "rand")]
+mod sample;
+mod stats;
+mod subview;
+#[cfg(feature = "std")]
+mod text;
+
+pub use self::arena::{ArenaBTree, ArenaIter};
+pub use self::bounded::BoundedBTree;
+pub use self::bplus::{BPlusTree, LeafScan};
+pub use self::builder::BTreeBuilder;
+pub use self::bytekey::ByteKey;
+#[cfg(feature = "std")]
+pub use self::concurrent::ConcurrentBTree;
+pub use self::conflict::ConflictPolicy;
+pub use self::cursor::Cursor;
+pub use self::detached::DetachedIter;
+pub use self::error::Error;
+pub use self::fanout::{Fanout, SplitPolicy};
+pub use self::iter::{IntoIter, Iter};
+#[cfg(feature = "mmap")]
+pub use self::mmap::MmapBTree;
+pub use self::node_alloc::{Global, NodeAlloc};
+pub use self::nodes::{NodeInfo, NodeIter};
+pub use self::ops::Op;
+pub use self::range::Range;
+pub use self::stats::{FillStats, TreeStats};
+pub use self::subview::SubView;
 
 const BTREE_MIN_KEYS: usize = 15; // probably too small? depends on disk model
 const BTREE_MAX_KEYS: usize = 31; // should be 2*min+1; split if we hit this number of keys in a node
 
-const BTREE_MEDIAN_INDEX: usize = BTREE_MIN_KEYS; // if we're fully loaded (i.e. at the "need to split" point) this is the index to remove and split on
+// `maybe_compact` rebuilds once there have been this many removals per remaining key
+const DEFAULT_COMPACT_THRESHOLD: f64 = 1.0;
+
+#[cfg(not(feature = "cow"))]
+pub trait Key: Sized + Ord + Eq {
+    /// A copy of the key for an internal node to keep as the smallest or largest key below it,
+    /// which lets range queries pass over subtrees with nothing in range. The default of `None`
+    /// opts out; a type should return `Some` for every key or for none.
+    fn copy_bound(&self) -> Option<Self> {
+        None
+    }
+}
+
+/// With the `cow` feature, nodes are shared between a tree and its clones and copied on the first
+/// write, which needs keys that can be cloned.
+#[cfg(feature = "cow")]
+pub trait Key: Sized + Ord + Eq + Clone {
+    /// A copy of the key for an internal node to keep as the smallest or largest key below it,
+    /// which lets range queries pass over subtrees with nothing in range. The default of `None`
+    /// opts out; a type should return `Some` for every key or for none.
+    fn copy_bound(&self) -> Option<Self> {
+        None
+    }
+}
+
+/// Opts types in to being tree keys: `impl_key!(A, B)` emits `impl Key for A {}` and the same for
+/// `B`. A tuple newtype over a fixed-width key is written `impl_key!(UserId(u64))`, which also
+/// implements `ByteKey` by encoding the wrapped value, so the newtype can be saved and mapped
+/// like the integer it holds.
+#[macro_export]
+macro_rules! impl_key {
+    ($wrapper:ident($inner:ty)) => {
+        impl $crate::trees::Key for $wrapper {}
+
+        impl $crate::trees::ByteKey for $wrapper {
+            const WIDTH: usize = <$inner as $crate::trees::ByteKey>::WIDTH;
+
+            type Bytes = <$inner as $crate::trees::ByteKey>::Bytes;
+
+            fn to_be_bytes(&self) -> Self::Bytes {
+                $crate::trees::ByteKey::to_be_bytes(&self.0)
+            }
+
+            fn from_be_bytes(bytes: Self::Bytes) -> $wrapper {
+                $wrapper(<$inner as $crate::trees::ByteKey>::from_be_bytes(bytes))
+            }
+        }
+    };
+    ($($t:ty),+ $(,)*) => {
+        $(impl $crate::trees::Key for $t {})+
+    };
+}
+
+// the built-in integer keys are cheap enough to copy that internal nodes keep bounds for them;
+// byte strings would cost an allocation per copy, so they go without
+macro_rules! impl_bounded_key {
+    ($($t:ty),+) => {
+        $(impl Key for $t {
+            fn copy_bound(&self) -> Option<$t> {
+                Some(Clone::clone(self))
+            }
+        })+
+    };
+}
+
+impl_bounded_key!(u32, u64, i32, i64);
+impl_key!(Vec<u8>);
+
+#[derive(Clone)]
+enum Node<T: Key, A: NodeAlloc = Global> {
+    Internal(InternalNode<T, A>),
+    Leaf(LeafNode<T, A>),
+}
+
+enum NodeRef<'a, T: 'a + Key, A: 'a + NodeAlloc = Global> {
+    Internal(&'a InternalNode<T, A>),
+    Leaf(&'a LeafNode<T, A>),
+}
+
+impl<'a, T: 'a + Key, A: 'a + NodeAlloc> Clone for NodeRef<'a, T, A> {
+    fn clone(&self) -> NodeRef<'a, T, A> {
+        *self
+    }
+}
+
+impl<'a, T: 'a + Key, A: 'a + NodeAlloc> Copy for NodeRef<'a, T, A> {}
 
-pub trait Key: Sized + Ord + Eq {}
+enum NodeRefMut<'a, T: 'a + Key, A: 'a + NodeAlloc = Global> {
+    Internal(&'a mut InternalNode<T, A>),
+    Leaf(&'a mut LeafNode<T, A>),
+}
+
+// Children are boxed so that shifting them around only moves pointers. With the `cow` feature
+// they are reference counted instead: cloning a tree then copies just the root, and a mutation
+// copies only the shared nodes along the path it writes to (see `child_mut`).
+#[cfg(not(feature = "cow"))]
+type Child<T, A = Global> = NodeBox<Node<T, A>, A>;
+#[cfg(feature = "cow")]
+type Child<T, A = Global> = NodeArc<Node<T, A>, A>;
+
+#[cfg(not(feature = "cow"))]
+fn new_child<T: Key, A: NodeAlloc>(node: Node<T, A>, alloc: &A) -> Child<T, A> {
+    box_in(node, alloc)
+}
+
+#[cfg(feature = "cow")]
+fn new_child<T: Key, A: NodeAlloc>(node: Node<T, A>, alloc: &A) -> Child<T, A> {
+    arc_in(node, alloc)
+}
 
-// TODO: macro for defining these
-impl Key for u32 {}
-impl Key for u64 {}
-impl Key for i32 {}
-impl Key for i64 {}
+#[cfg(not(feature = "cow"))]
+fn child_mut<T: Key, A: NodeAlloc>(child: &mut Child<T, A>) -> &mut Node<T, A> {
+    child
+}
 
-enum Node<T: Key> {
-    Internal(InternalNode<T>),
-    Leaf(LeafNode<T>),
+#[cfg(feature = "cow")]
+fn child_mut<T: Key, A: NodeAlloc>(child: &mut Child<T, A>) -> &mut Node<T, A> {
+    Arc::make_mut(child)
 }
 
-enum NodeRef<'a, T: 'a + Key> {
-    Internal(&'a InternalNode<T>),
-    Leaf(&'a LeafNode<T>),
+#[cfg(not(feature = "cow"))]
+#[allow(clippy::boxed_local)] // boxed only because the other configuration shares children
+fn into_node<T: Key, A: NodeAlloc>(child: Child<T, A>) -> Node<T, A> {
+    *child
 }
 
-enum NodeRefMut<'a, T: 'a + Key> {
-    Internal(&'a mut InternalNode<T>),
-    Leaf(&'a mut LeafNode<T>),
+#[cfg(feature = "cow")]
+fn into_node<T: Key, A: NodeAlloc>(child: Child<T, A>) -> Node<T, A> {
+    Arc::try_unwrap(child).unwrap_or_else(|shared| (*shared).clone())
 }
 
-struct InternalNode<T: Key> {
-    keys: Vec<T>,
-    children: Vec<Box<Node<T>>>,
+#[derive(Clone)]
+struct InternalNode<T: Key, A: NodeAlloc = Global> {
+    keys: NodeVec<T, A>,
+    #[allow(clippy::vec_box)]
+    children: NodeVec<Child<T, A>, A>,
     num_keys: usize,
+    // keys in this node and all of its descendants
+    subtree_keys: usize,
+    // copies of the smallest and largest keys in this subtree, `None` if it is empty or the key
+    // type has no bounds; they are only ever compared against
+    subtree_min: Option<T>,
+    subtree_max: Option<T>,
 }
 
-struct LeafNode<T: Key> {
-    keys: Vec<T>,
+#[derive(Clone)]
+struct LeafNode<T: Key, A: NodeAlloc = Global> {
+    keys: NodeVec<T, A>,
     num_keys: usize,
+    // the leaf after this one, while the tree's `leaf_chain` is linked
+    next: LeafLink<T, A>,
 }
 
-pub struct BTree<T: Key> {
+#[derive(Clone)]
+pub struct BTree<T: Key, A: NodeAlloc = Global> {
     num_keys: usize,
-    root: Node<T>,
+    root: Node<T, A>,
+    bloom: Option<BloomFilter<T>>,
+    lazy_removal: bool,
+    fanout: Fanout,
+    // bumped by every change to the contents, so that detached iterators can tell
+    mod_count: u64,
+    // successful removals since the tree was last rebuilt, and how many of them per live key
+    // make `maybe_compact` rebuild it
+    removals_since_compact: usize,
+    compact_threshold: f64,
+    leaf_chain: LeafChain,
+    alloc: A,
 }
 
-struct InsertState {
-    success: bool,
+struct InsertState<T> {
+    // the key handed back when an equal key was already present
+    rejected: Option<T>,
     must_split: bool,
+    // splits done so far on the way back up
+    splits: usize,
+}
+
+/// What a single `insert_reporting` did to the shape of the tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InsertReport {
+    /// False if an equal key was already present, in which case nothing else changed either.
+    pub inserted: bool,
+    /// Nodes split by this insert, including the root.
+    pub splits: usize,
+    /// Whether the root split, adding a level to the tree.
+    pub height_increased: bool,
 }
 
-struct SplitResult<T: Key> {
+struct SplitResult<T: Key, A: NodeAlloc> {
     median_key: T,
-    right: Node<T>,
+    right: Node<T, A>,
 }
 
 impl<T: Key> BTree<T> {
     pub fn new() -> BTree<T> {
+        BTree::with_fanout(Fanout::default())
+    }
+
+    /// An empty tree whose nodes use the given size limits.
+    pub fn with_fanout(fanout: Fanout) -> BTree<T> {
+        BTree::with_fanout_in(fanout, Global)
+    }
+}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// An empty tree whose nodes are allocated from `alloc` (see `NodeAlloc`). Lookups, ranges,
+    /// cursors, ranks, stats and updates work the same whatever the allocator, but the
+    /// constructors which aren't handed one (`new`, `from_sorted` and the like, `with_bloom`,
+    /// `BTreeBuilder`, the loaders), the parallel helpers and the binary, memory-mapped and CSV
+    /// formats stay on `Global`. To pack a tree here as `from_sorted` would, give this empty
+    /// tree the keys with `insert_sorted_batch`.
+    pub fn new_in(alloc: A) -> BTree<T, A> {
+        BTree::with_fanout_in(Fanout::default(), alloc)
+    }
+
+    /// An empty tree with the given size limits, whose nodes are allocated from `alloc`.
+    pub fn with_fanout_in(fanout: Fanout, alloc: A) -> BTree<T, A> {
         BTree {
             num_keys: 0,
-            root: Node::Leaf(LeafNode {
-                keys: Vec::with_capacity(BTREE_MAX_KEYS),
-                num_keys: 0,
-            }),
+            root: empty_leaf(&alloc),
+            bloom: None,
+            lazy_removal: false,
+            fanout,
+            mod_count: 0,
+            removals_since_compact: 0,
+            compact_threshold: DEFAULT_COMPACT_THRESHOLD,
+            leaf_chain: LeafChain::linked(),
+            alloc,
         }
     }
 
+    /// The allocator the tree's nodes come from.
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    pub fn fanout(&self) -> Fanout {
+        self.fanout
+    }
+
     // TODO: what exactly is it finding? Probably want key -> data
     pub fn find(&self, key: &T) -> bool {
-        let mut maybe_node: Option<&Node<T>> = Some(&self.root);
-
-        // recursion would be more elegant but doing this helps manage references
-        'main_loop: while let Some(current_node) = maybe_node {
-            match current_node {
-                // TODO: binary search
-                &Node::Leaf(ref node) => {
-                    for i in 0..node.num_keys {
-                        match key.cmp(&node.keys[i]) {
-                            Ordering::Less => {
-                                return false;
-                            }
-                            Ordering::Equal => {
-                                return true;
-                            }
-                            Ordering::Greater => {}
-                        }
-                    }
+        if let Some(ref bloom) = self.bloom {
+            if !bloom.may_contain(key) {
+                return false;
+            }
+        }
 
-                    return false;
-                }
+        // A nightly prefetch hint for the child, issued as soon as the binary search had picked
+        // it, made no difference beyond run-to-run noise on `examples/arena_find`, whether it
+        // named the node or the middle of its keys: finding either address already waits on the
+        // node, so the hint arrives no sooner than the read itself.
+        let mut node = &self.root;
+        loop {
+            match (search_keys(node.keys(), key), node.children()) {
+                (Ok(_), _) => return true,
+                (Err(index), Some(children)) => node = &children[index],
+                (Err(_), None) => return false,
+            }
+        }
+    }
 
-                // TODO: binary search
-                &Node::Internal(ref node) => {
-                    for i in 0..node.num_keys {
-                        match key.cmp(&node.keys[i]) {
-                            Ordering::Less => {
-                                maybe_node = Some(&node.children[i]);
-                                continue 'main_loop;
-                            }
-
-                            Ordering::Equal => {
-                                return true;
-                            }
-                            Ordering::Greater => {}
-                        }
-                    }
+    /// The key stored in the tree which compares equal to `key`, if any. This is only interesting
+    /// when equal keys can still differ, e.g. a type whose `Ord` looks at an id field alone.
+    pub fn get_key_value(&self, key: &T) -> Option<&T> {
+        if let Some(ref bloom) = self.bloom {
+            if !bloom.may_contain(key) {
+                return None;
+            }
+        }
+
+        stored(&self.root, key)
+    }
+
+    /// The stored key `cmp` picks out, found by descending as `find` does but asking `cmp` how
+    /// each stored key orders against the target, like `slice::binary_search_by`. This allows
+    /// lookups on part of a key, such as one field of a struct sorted by that field first.
+    ///
+    /// `cmp` must agree with the tree's own ordering: if it says `Less` for a key, it must say
+    /// `Less` for every smaller key too, and `Greater` likewise for larger ones. Otherwise the
+    /// result is unspecified. If `cmp` says `Equal` for several keys, any of them may be returned.
+    pub fn find_by<F: Fn(&T) -> Ordering>(&self, cmp: F) -> Option<&T> {
+        let mut node = &self.root;
+        loop {
+            let position = node.keys().binary_search_by(&cmp);
+            match (position, node.children()) {
+                (Ok(index), _) => return Some(&node.keys()[index]),
+                (Err(index), Some(children)) => node = &children[index],
+                (Err(_), None) => return None,
+            }
+        }
+    }
+
+    /// Inserts `key`, returning whether it was new. Inserting a key which is already present is
+    /// a no-op: the stored key, the node layout and the modification count are all left exactly
+    /// as they were, so replaying inserts is safe.
+    pub fn insert(&mut self, key: T) -> bool {
+        self.insert_or_get_existing(key).is_ok()
+    }
 
-                    maybe_node = Some(&node.children[node.num_keys]);
+    /// Inserts `key`, or if an equal key is already present leaves the tree alone and hands the
+    /// key back as `Err(key)`.
+    pub fn insert_or_get_existing(&mut self, key: T) -> Result<(), T> {
+        self.insert_counting_splits(key).0
+    }
+
+    /// Applies a batch of updates: each key of `updates` is inserted if no equal key is present,
+    /// and otherwise handed to `merge` along with the stored key so it can be folded in place.
+    /// An update to a key already present costs one descent; a new key costs a second one to
+    /// insert it.
+    ///
+    /// There is no map variant of the tree yet, so the "value" is whatever part of the key its
+    /// ordering ignores. `merge` must not change how the stored key compares to the others.
+    pub fn update_many<I, F>(&mut self, updates: I, merge: F)
+    where
+        I: IntoIterator<Item = T>,
+        F: FnMut(&mut T, T),
+    {
+        self.update_many_with(updates, ConflictPolicy::Merge(merge));
+    }
+
+    /// Like `update_many`, with an update to a key already present resolved by `policy`, the
+    /// stored key being the left side.
+    pub fn update_many_with<I, F>(&mut self, updates: I, mut policy: ConflictPolicy<F>)
+    where
+        I: IntoIterator<Item = T>,
+        F: FnMut(&mut T, T),
+    {
+        for update in updates {
+            match stored_mut(&mut self.root, &update) {
+                Some(stored) => {
+                    if policy.resolve(stored, update) {
+                        self.mod_count = self.mod_count.wrapping_add(1);
+                    }
+                }
+                None => {
+                    self.insert(update);
                 }
             }
         }
+    }
 
-        false
+    /// Inserts `key`, failing with `Error::DuplicateKey` if an equal key is already present.
+    pub fn try_insert(&mut self, key: T) -> Result<(), Error> {
+        self.insert_or_get_existing(key)
+            .map_err(|_| Error::DuplicateKey)
     }
 
-    pub fn insert(&mut self, key: T) -> bool {
-        let root_insert = insert_at_node(&mut self.root, key);
+    /// Inserts `key` like `insert`, reporting how many nodes had to split to make room.
+    pub fn insert_reporting(&mut self, key: T) -> InsertReport {
+        self.insert_counting_splits(key).1
+    }
+
+    fn insert_counting_splits(&mut self, key: T) -> (Result<(), T>, InsertReport) {
+        // the descent unshares every node it passes under `cow`, so rule out a duplicate first
+        if cfg!(feature = "cow") && stored(&self.root, &key).is_some() {
+            let report = InsertReport {
+                inserted: false,
+                splits: 0,
+                height_increased: false,
+            };
+            return (Err(key), report);
+        }
+
+        if let Some(ref mut bloom) = self.bloom {
+            bloom.insert(&key);
+        }
+
+        let root_insert = insert_at_node(&mut self.root, key, self.fanout, &self.alloc);
+        let mut report = InsertReport {
+            inserted: root_insert.rejected.is_none(),
+            splits: root_insert.splits,
+            height_increased: root_insert.must_split,
+        };
 
         // if self.root needs to split, do so
         if root_insert.must_split {
-            let root_split = split_node(&mut self.root);
+            report.splits += 1;
+            let root_split = split_node(&mut self.root, self.fanout);
             let new_root = InternalNode {
                 num_keys: 1,
-                keys: Vec::with_capacity(BTREE_MAX_KEYS),
-                children: Vec::with_capacity(BTREE_MAX_KEYS + 1),
+                keys: vec_in(self.fanout.internal_max_keys() + 1, &self.alloc),
+                children: vec_in(self.fanout.internal_max_keys() + 2, &self.alloc),
+                subtree_keys: self.root.subtree_len() + 1 + root_split.right.subtree_len(),
+                subtree_min: None,
+                subtree_max: None,
             };
 
             let old_root = mem::replace(&mut self.root, Node::Internal(new_root));
 
             if let Node::Internal(ref mut root) = self.root {
-                root.children.push(Box::new(old_root));
+                root.children.push(new_child(old_root, &self.alloc));
                 root.keys.push(root_split.median_key);
-                root.children.push(Box::new(root_split.right));
+                root.children.push(new_child(root_split.right, &self.alloc));
+                link_split_leaf(&mut root.children, 0);
+                refresh_bounds(root);
             }
         }
 
-        if root_insert.success {
-            self.num_keys += 1;
+        let result = match root_insert.rejected {
+            Some(key) => Err(key),
+            None => {
+                self.mod_count = self.mod_count.wrapping_add(1);
+                self.num_keys = match self.num_keys.checked_add(1) {
+                    Some(n) => n,
+                    None => panic!("BTree key count overflowed usize"),
+                };
+                debug_assert_eq!(
+                    self.num_keys,
+                    self.root.subtree_len(),
+                    "BTree key count disagrees with its contents"
+                );
+                Ok(())
+            }
+        };
+        (result, report)
+    }
+
+    /// Inserts every key of the batch, or none of them: if any key is already in the tree, or
+    /// appears twice in the batch, the tree is left untouched and that key (for a repeat, its
+    /// second copy) is handed back. Everything is checked before anything is inserted, so there
+    /// is never anything to roll back.
+    pub fn insert_all_or_none(&mut self, mut keys: Vec<T>) -> Result<(), T> {
+        if let Some(existing) = keys.iter().position(|key| self.find(key)) {
+            return Err(keys.swap_remove(existing));
         }
 
-        root_insert.success
-    }
+        let mut order = (0..keys.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| keys[a].cmp(&keys[b]));
+        if let Some(pair) = order.windows(2).find(|pair| keys[pair[0]] == keys[pair[1]]) {
+            return Err(keys.swap_remove(pair[1]));
+        }
 
-    pub fn size(&self) -> usize {
-        return self.num_keys;
+        for key in keys {
+            self.insert(key);
+        }
+        Ok(())
     }
-}
 
-impl<T: Key + Debug + Display> BTree<T> {
-    pub fn draw_tree(&self) {
-        print_node(&self.root, 0);
-    }
-}
+    // Replaces the contents of the tree with the given strictly ascending keys, packed densely.
+    fn rebuild_from_sorted(&mut self, keys: Vec<T>) {
+        let num_keys = keys.len();
 
-fn print_node<T: Key + Debug + Display>(node: &Node<T>, depth: usize) {
-    let spaces = iter::repeat(" ").take(depth).collect::<String>();
-    match *node {
-        Node::Leaf(ref leaf) => {
-            println!(
-                "{}Leaf: num_keys: {}, keys: {:?}",
-                spaces, leaf.num_keys, leaf.keys
-            );
+        if let Some(ref mut bloom) = self.bloom {
+            bloom.clear();
+            for key in keys.iter() {
+                bloom.insert(key);
+            }
         }
 
-        Node::Internal(ref internal) => {
-            println!(
-                "{}Internal: num_keys: {}, keys: {:?}",
-                spaces, internal.num_keys, internal.keys
-            );
-            for child_ref in internal.children.iter() {
-                print_node(child_ref, depth + 2);
-            }
+        let mut height = 0;
+        while subtree_capacity(height, self.fanout) < num_keys {
+            height += 1;
         }
+
+        self.root = build_subtree(
+            &mut keys.into_iter(),
+            num_keys,
+            height,
+            self.fanout,
+            &self.alloc,
+        );
+        self.num_keys = num_keys;
+        self.mod_count = self.mod_count.wrapping_add(1);
+        self.removals_since_compact = 0;
+        self.link_leaves();
     }
-}
 
-fn split_node<T: Key>(node: &mut Node<T>) -> SplitResult<T> {
-    match *node {
-        Node::Leaf(ref mut leaf) => split_leaf_node(leaf),
-        Node::Internal(ref mut internal) => split_internal_node(internal),
+    // Empties the tree, handing back every key in ascending order.
+    fn take_sorted_keys(&mut self) -> Vec<T> {
+        let mut keys = Vec::with_capacity(self.num_keys);
+        let root = mem::replace(&mut self.root, empty_leaf(&self.alloc));
+        drain_node(root, &mut keys);
+        self.leaf_chain = LeafChain::linked();
+        self.num_keys = 0;
+        self.mod_count = self.mod_count.wrapping_add(1);
+        keys
     }
-}
 
-fn split_internal_node<T: Key>(node: &mut InternalNode<T>) -> SplitResult<T> {
-    let right_keys = node.keys
-        .drain(BTREE_MEDIAN_INDEX + 1..)
-        .collect::<Vec<_>>();
-    let right_children = node.children
-        .drain(BTREE_MEDIAN_INDEX + 1..)
-        .collect::<Vec<_>>();
+    pub fn size(&self) -> usize {
+        self.num_keys
+    }
 
-    let median_key = node.keys.remove(BTREE_MEDIAN_INDEX);
+    pub fn len(&self) -> usize {
+        self.num_keys
+    }
 
-    let right = InternalNode {
-        num_keys: right_keys.len(),
-        keys: right_keys,
-        children: right_children,
-    };
+    pub fn is_empty(&self) -> bool {
+        self.num_keys == 0
+    }
 
-    node.num_keys = node.keys.len();
+    /// Number of levels in the tree; a tree which is a single leaf (even an empty one) has
+    /// height 1. Every leaf sits at the same depth, so this is the length of any root-to-leaf
+    /// path.
+    pub fn height(&self) -> usize {
+        let mut height = 1;
+        let mut node = &self.root;
+        while let Some(children) = node.children() {
+            node = &children[0];
+            height += 1;
+        }
+        height
+    }
 
-    SplitResult {
-        right: Node::Internal(right),
-        median_key,
+    /// Whether the whole tree is one leaf, holding at most `fanout().leaf_max_keys()` keys. Saves
+    /// hot paths on tiny trees from setting up an iterator or cursor to find that out.
+    pub fn is_single_leaf(&self) -> bool {
+        match self.root {
+            Node::Leaf(_) => true,
+            Node::Internal(_) => false,
+        }
     }
-}
 
-fn split_leaf_node<T: Key>(node: &mut LeafNode<T>) -> SplitResult<T> {
-    let right_keys = node.keys
-        .drain(BTREE_MEDIAN_INDEX + 1..)
-        .collect::<Vec<_>>();
-    let median_key = node.keys.remove(BTREE_MEDIAN_INDEX);
+    /// How many levels below the root `key` is stored (0 for the root itself), or None if it
+    /// isn't in the tree. Separator keys in internal nodes are found above the leaves.
+    pub fn depth_of(&self, key: &T) -> Option<usize> {
+        let mut depth = 0;
+        let mut node = &self.root;
+        loop {
+            match (search_keys(node.keys(), key), node.children()) {
+                (Ok(_), _) => return Some(depth),
+                (Err(index), Some(children)) => node = &children[index],
+                (Err(_), None) => return None,
+            }
+            depth += 1;
+        }
+    }
 
-    let right = LeafNode {
-        num_keys: right_keys.len(),
-        keys: right_keys,
-    };
+    /// Looks up `key` as `find` does, but only looking at nodes at most `max_depth` levels below
+    /// the root (so 0 is the root alone). Returns `Some` of the answer if that was enough to
+    /// settle it, or `None` if the descent would have to go deeper, leaving the caller to decide
+    /// whether a full lookup is worth it. A Bloom filter miss settles it without any descent.
+    pub fn find_within_depth(&self, key: &T, max_depth: usize) -> Option<bool> {
+        if let Some(ref bloom) = self.bloom {
+            if !bloom.may_contain(key) {
+                return Some(false);
+            }
+        }
 
-    node.num_keys = node.keys.len();
+        let mut depth = 0;
+        let mut node = &self.root;
+        loop {
+            match (search_keys(node.keys(), key), node.children()) {
+                (Ok(_), _) => return Some(true),
+                (Err(_), Some(_)) if depth == max_depth => return None,
+                (Err(index), Some(children)) => node = &children[index],
+                (Err(_), None) => return Some(false),
+            }
+            depth += 1;
+        }
+    }
 
-    SplitResult {
-        right: Node::Leaf(right),
-        median_key,
+    /// Counts the keys actually stored in the tree, overwriting the running total with the true
+    /// figure, and returns it. A debugging and repair aid in case any mutation path has let the
+    /// bookkeeping drift.
+    pub fn recount(&mut self) -> usize {
+        self.num_keys = recount_node(&mut self.root);
+        self.num_keys
     }
-}
 
-fn insert_at_node<T: Key>(node: &mut Node<T>, key: T) -> InsertState {
-    match *node {
-        Node::Internal(ref mut internal) => insert_at_internal_node(internal, key),
-        Node::Leaf(ref mut leaf) => insert_at_leaf_node(leaf, key),
+    #[cfg(test)]
+    fn set_num_keys(&mut self, num_keys: usize) {
+        self.num_keys = num_keys;
     }
-}
 
-fn insert_at_internal_node<T: Key>(internal: &mut InternalNode<T>, key: T) -> InsertState {
-    for i in 0..internal.num_keys {
-        match key.cmp(&internal.keys[i]) {
-            Ordering::Less => {
-                let mut insert_state = insert_at_node(&mut *internal.children[i], key);
+    /// Iterates over every key in the tree, in ascending order.
+    pub fn iter(&self) -> Iter<'_, T, A> {
+        Iter::new(&self.root)
+    }
 
-                if insert_state.must_split {
-                    let split_result = split_node(&mut *internal.children[i]);
+    /// Iterates in ascending order over the keys which are `>= start`. The iterator descends
+    /// directly to the starting position, so nothing before `start` is visited.
+    pub fn iter_from(&self, start: &T) -> Iter<'_, T, A> {
+        Iter::starting_at(&self.root, start)
+    }
 
-                    internal.keys.insert(i, split_result.median_key);
-                    internal
-                        .children
-                        .insert(i + 1, Box::new(split_result.right));
-                    internal.num_keys += 1;
+    /// Like `iter_from`, but stops just before the first key for which `stop` returns true; that
+    /// key and everything after it are never visited.
+    pub fn iter_until<'a, F: Fn(&T) -> bool + 'a>(
+        &'a self,
+        start: &T,
+        stop: F,
+    ) -> impl Iterator<Item = &'a T> {
+        self.iter_from(start).take_while(move |key| !stop(key))
+    }
 
-                    insert_state.must_split = internal.num_keys >= BTREE_MAX_KEYS;
+    /// Calls `agg` once for each run of consecutive keys with the same `group_of` value, passing
+    /// the group and how many keys are in the run, in ascending key order. For a grouping which
+    /// respects the key order (such as `k / 1000` on integers) every group is one run, so this is
+    /// a group-by over the whole tree in a single pass.
+    pub fn for_each_group<G: Eq, K: Fn(&T) -> G, F: FnMut(G, usize)>(
+        &self,
+        group_of: K,
+        mut agg: F,
+    ) {
+        let mut current: Option<(G, usize)> = None;
+
+        for key in self.iter() {
+            let group = group_of(key);
+            if let Some((ref run_group, ref mut count)) = current {
+                if *run_group == group {
+                    *count += 1;
+                    continue;
                 }
-
-                return insert_state;
             }
-
-            Ordering::Equal => {
-                return InsertState {
-                    success: false,
-                    must_split: false,
-                };
+            if let Some((run_group, count)) = current.replace((group, 1)) {
+                agg(run_group, count);
             }
+        }
 
-            Ordering::Greater => {}
+        if let Some((group, count)) = current {
+            agg(group, count);
         }
     }
 
-    let mut insert_state = insert_at_node(&mut *internal.children[internal.num_keys], key);
+    /// References to every key in ascending order, for random access without cloning.
+    pub fn key_refs(&self) -> Vec<&T> {
+        let mut refs = Vec::with_capacity(self.len());
+        refs.extend(self.iter());
+        refs
+    }
 
-    if insert_state.must_split {
-        let split_result = split_node(&mut *internal.children[internal.num_keys]);
+    /// Iterates in ascending order over chunks of `chunk_size` keys; only the last chunk can be
+    /// shorter. Panics if `chunk_size` is zero.
+    pub fn iter_chunks(&self, chunk_size: usize) -> impl Iterator<Item = Vec<&T>> {
+        assert!(chunk_size > 0, "chunk size must be positive");
+
+        let mut keys = self.iter();
+        ::std::iter::from_fn(move || {
+            let chunk = keys.by_ref().take(chunk_size).collect::<Vec<_>>();
+            if chunk.is_empty() {
+                None
+            } else {
+                Some(chunk)
+            }
+        })
+    }
 
-        internal.keys.push(split_result.median_key);
-        internal.children.push(Box::new(split_result.right));
-        internal.num_keys += 1;
+    /// The largest key strictly less than `key`, which need not itself be present.
+    pub fn predecessor(&self, key: &T) -> Option<&T> {
+        let mut best = None;
+        let mut node = &self.root;
+
+        loop {
+            let keys = node.keys();
+            // keys[..index] are all less than `key`
+            let index = search_keys(keys, key).unwrap_or_else(|i| i);
+            if index > 0 {
+                best = Some(&keys[index - 1]);
+            }
 
-        insert_state.must_split = internal.num_keys >= BTREE_MAX_KEYS;
+            match node.children() {
+                Some(children) => node = &children[index],
+                None => return best,
+            }
+        }
     }
 
-    insert_state
-}
-
-fn insert_at_leaf_node<T: Key>(leaf: &mut LeafNode<T>, key: T) -> InsertState {
-    for i in 0..leaf.num_keys {
-        match key.cmp(&leaf.keys[i]) {
-            Ordering::Less => {
-                leaf.keys.insert(i, key);
-                leaf.num_keys += 1;
-                return InsertState {
-                    success: true,
-                    must_split: leaf.num_keys >= BTREE_MAX_KEYS,
-                };
+    /// The smallest key strictly greater than `key`, which need not itself be present.
+    pub fn successor(&self, key: &T) -> Option<&T> {
+        let mut best = None;
+        let mut node = &self.root;
+
+        loop {
+            let keys = node.keys();
+            // keys[index..] are all greater than `key`
+            let index = match search_keys(keys, key) {
+                Ok(i) => i + 1,
+                Err(i) => i,
+            };
+            if index < keys.len() {
+                best = Some(&keys[index]);
             }
 
-            Ordering::Equal => {
-                return InsertState {
-                    success: false,
-                    must_split: false,
-                };
+            match node.children() {
+                Some(children) => node = &children[index],
+                None => return best,
             }
-            Ordering::Greater => {}
         }
     }
 
-    leaf.keys.insert(leaf.num_keys, key);
-    leaf.num_keys += 1;
-
-    return InsertState {
-        success: true,
-        must_split: leaf.num_keys >= BTREE_MAX_KEYS,
-    };
-}
+    /// Removes every key within `range`, returning how many were removed. The surviving keys are
+    /// repacked into a fresh tree, so this costs time proportional to the whole tree; it's meant
+    /// for dropping big windows at once rather than a few keys.
+    pub fn remove_range<R: RangeBounds<T>>(&mut self, range: R) -> usize {
+        let removed = self
+            .iter_from_bound(range.start_bound())
+            .take_while(|&key| !past_end(key, range.end_bound()))
+            .count();
+
+        if removed > 0 {
+            let survivors = self
+                .take_sorted_keys()
+                .into_iter()
+                .filter(|key| !range.contains(key))
+                .collect();
+            self.rebuild_from_sorted(survivors);
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        removed
+    }
 
-    #[test]
-    fn empty_test_u32() {
-        let empty = BTree::<u32>::new();
+    // Ascending keys satisfying the given lower bound.
+    fn iter_from_bound<'a>(&'a self, start: Bound<&'a T>) -> impl Iterator<Item = &'a T> {
+        let (iter, excluded) = match start {
+            Bound::Included(start) => (self.iter_from(start), None),
+            Bound::Excluded(start) => (self.iter_from(start), Some(start)),
+            Bound::Unbounded => (self.iter(), None),
+        };
+        iter.skip_while(move |key| excluded == Some(*key))
+    }
 
-        assert!(!empty.find(&1331));
-        assert!(!empty.find(&642426344));
+    /// Walks the whole tree checking the structural B-tree invariants (ordering, node fill,
+    /// uniform leaf depth, bookkeeping counts), describing the first violation found.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        self.check_tree(false)
+    }
 
-        assert_eq!(empty.size(), 0 as usize);
+    /// Like `check_invariants`, but lets nodes run below the minimum fill or empty out, as they
+    /// may after lazy removal; see `set_lazy_removal`.
+    pub fn check_lazy_invariants(&self) -> Result<(), String> {
+        self.check_tree(true)
     }
 
-    #[test]
-    fn empty_test_i64() {
-        let empty = BTree::<i64>::new();
+    fn check_tree(&self, lazy: bool) -> Result<(), String> {
+        let mut leaf_depth = None;
+        let total = check_node(
+            &self.root,
+            true,
+            None,
+            None,
+            0,
+            self.fanout,
+            lazy,
+            &mut leaf_depth,
+        )?;
+
+        if total != self.num_keys {
+            return Err(format!(
+                "tree claims {} keys but holds {}",
+                self.num_keys, total
+            ));
+        }
 
-        assert!(!empty.find(&1331));
-        assert!(!empty.find(&642426344));
+        self.check_links()
+    }
+}
 
-        assert_eq!(empty.size(), 0 as usize);
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Inserts `key` if no equal key is present, and either way returns the instance now stored
+    /// in the tree: the new one, or the existing one it was a duplicate of. The reference borrows
+    /// the tree mutably, since the next insert may move keys around within their nodes.
+    ///
+    /// The borrow checker is what enforces that; there is no `unsafe` behind the reference, so
+    /// holding it across another insert is rejected at compile time:
+    ///
+    /// ```compile_fail
+    /// use catdb_lib::trees::BTree;
+    ///
+    /// let mut tree = BTree::new();
+    /// let stored = tree.insert_interned(1_u32);
+    /// tree.insert(2);
+    /// assert_eq!(*stored, 1);
+    /// ```
+    pub fn insert_interned(&mut self, key: T) -> &T {
+        let (_, rank) = self.insert_with_rank(key);
+        self.select(rank)
+            .expect("key is in the tree after inserting it")
     }
 
-    #[test]
-    fn test_insert_u32() {
-        let mut tree = BTree::<u32>::new();
+    /// The smallest key in the tree.
+    pub fn first(&self) -> Option<&T> {
+        self.select(0)
+    }
 
-        assert!(tree.size() == 0 as usize);
+    /// The largest key in the tree.
+    pub fn last(&self) -> Option<&T> {
+        self.len().checked_sub(1).and_then(|last| self.select(last))
+    }
 
-        assert!(tree.insert(123));
+    /// A cursor positioned just before the first key which is `>= key`.
+    pub fn cursor_at(&self, key: &T) -> Cursor<'_, T, A> {
+        let mut visited = 0;
+        Cursor::new(&self.root, key, &mut visited).0
+    }
+}
 
-        assert!(tree.size() == 1 as usize);
-        assert!(tree.find(&123));
-        assert!(!tree.find(&43));
-        assert!(!tree.find(&5278945));
+impl<T: Key> BTree<T> {
+    /// Builds a tree directly from keys which are already in strictly ascending order, packing
+    /// the nodes without doing any splits. If the input is not sorted and deduplicated, the
+    /// resulting tree is well-formed but lookups will give wrong answers.
+    pub fn from_sorted<I: IntoIterator<Item = T>>(sorted: I) -> BTree<T> {
+        let mut tree = BTree::new();
+        tree.rebuild_from_sorted(sorted.into_iter().collect());
+        tree
+    }
 
-        assert!(tree.insert(5278945));
+    /// Like `from_sorted`, but checks as it goes that the keys really are strictly ascending,
+    /// failing on the first out-of-order or repeated pair instead of building a tree which gives
+    /// wrong answers.
+    pub fn try_from_sorted<I: IntoIterator<Item = T>>(
+        sorted: I,
+    ) -> Result<BTree<T>, UnsortedError<T>> {
+        let mut keys: Vec<T> = Vec::new();
+        for key in sorted {
+            if let Some(previous) = keys.last() {
+                if *previous >= key {
+                    let index = keys.len();
+                    return Err(UnsortedError {
+                        index,
+                        previous: keys.pop().unwrap(),
+                        next: key,
+                    });
+                }
+            }
+            keys.push(key);
+        }
 
-        assert!(tree.size() == 2 as usize);
-        assert!(tree.find(&123));
-        assert!(!tree.find(&43));
-        assert!(tree.find(&5278945));
+        let mut tree = BTree::new();
+        tree.rebuild_from_sorted(keys);
+        Ok(tree)
+    }
 
-        assert!(!tree.insert(5278945));
+    /// Like `from_sorted`, but the input only has to be non-decreasing: runs of equal keys are
+    /// collapsed as they stream in, keeping the first of each run.
+    pub fn from_sorted_dedup<I: IntoIterator<Item = T>>(sorted: I) -> BTree<T> {
+        let mut keys: Vec<T> = Vec::new();
+        for key in sorted {
+            if let Some(previous) = keys.last() {
+                debug_assert!(*previous <= key, "from_sorted_dedup input is not sorted");
+                if *previous == key {
+                    continue;
+                }
+            }
+            keys.push(key);
+        }
 
-        assert!(tree.size() == 2 as usize);
-        assert!(tree.find(&123));
-        assert!(!tree.find(&43));
-        assert!(tree.find(&5278945));
+        let mut tree = BTree::new();
+        tree.rebuild_from_sorted(keys);
+        tree
     }
+}
 
-    #[test]
-    fn test_insert_more_i32() {
-        let mut tree = BTree::<i32>::new();
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    // An empty tree with the same fanout, removal settings, (empty) Bloom filter and allocator as
+    // this one.
+    fn empty_like(&self) -> BTree<T, A> {
+        let mut bloom = self.bloom.clone();
+        if let Some(ref mut bloom) = bloom {
+            bloom.clear();
+        }
 
-        for i in 0..50 {
+        BTree {
+            bloom,
+            lazy_removal: self.lazy_removal,
+            compact_threshold: self.compact_threshold,
+            ..BTree::with_fanout_in(self.fanout, self.alloc.clone())
+        }
+    }
+
+    /// Splits the tree into `boundaries.len() + 1` trees covering consecutive key ranges: the
+    /// first takes the keys below `boundaries[0]`, the `i`th those from `boundaries[i - 1]` up to
+    /// (not including) `boundaries[i]`, and the last everything from the final boundary on. All
+    /// the partitions are cut from a single in-order pass and packed densely, and keep this
+    /// tree's settings. Panics unless `boundaries` is strictly ascending.
+    pub fn partition_by(mut self, boundaries: &[T]) -> Vec<BTree<T, A>> {
+        assert!(
+            boundaries.windows(2).all(|pair| pair[0] < pair[1]),
+            "partition boundaries must be strictly ascending"
+        );
+
+        let mut keys = self.take_sorted_keys().into_iter().peekable();
+        let mut partitions = Vec::with_capacity(boundaries.len() + 1);
+
+        for boundary in boundaries.iter().map(Some).chain(Some(None)) {
+            let mut part_keys = Vec::new();
+            while let Some(key) = keys.next_if(|key| boundary.is_none_or(|b| key < b)) {
+                part_keys.push(key);
+            }
+
+            let mut part = self.empty_like();
+            part.rebuild_from_sorted(part_keys);
+            partitions.push(part);
+        }
+        partitions
+    }
+}
+
+// Batches at least this many times smaller than the tree are inserted key by key; bigger ones
+// are merged in by rebuilding the whole tree, which costs about one move per key.
+const BATCH_REBUILD_RATIO: usize = 32;
+
+impl<T: Key + Clone, A: NodeAlloc> BTree<T, A> {
+    /// Adds every key of `sorted`, which must be strictly ascending; keys already in the tree
+    /// are skipped. A small batch is inserted key by key, while a batch which is large next to
+    /// the tree is merged with the existing keys in a single pass and the tree rebuilt densely
+    /// packed -- so a big batch lying entirely above the current maximum ends up appended as
+    /// full leaves rather than a trail of half-empty split nodes.
+    pub fn insert_sorted_batch(&mut self, sorted: &[T]) {
+        debug_assert!(
+            sorted.windows(2).all(|pair| pair[0] < pair[1]),
+            "insert_sorted_batch requires strictly ascending keys"
+        );
+
+        if sorted.len() * BATCH_REBUILD_RATIO < self.num_keys {
+            for key in sorted {
+                self.insert(key.clone());
+            }
+            return;
+        }
+
+        let existing = self.take_sorted_keys();
+        let mut merged = Vec::with_capacity(existing.len() + sorted.len());
+        let mut batch = sorted.iter().peekable();
+
+        for key in existing {
+            while let Some(next) = batch.next_if(|next| **next < key) {
+                merged.push(next.clone());
+            }
+            if batch.peek() == Some(&&key) {
+                batch.next();
+            }
+            merged.push(key);
+        }
+        merged.extend(batch.cloned());
+
+        self.rebuild_from_sorted(merged);
+    }
+}
+
+impl<T: Key> Default for BTree<T> {
+    fn default() -> BTree<T> {
+        BTree::new()
+    }
+}
+
+impl<T: Key + Clone, A: NodeAlloc> BTree<T, A> {
+    /// An immutable copy of the tree which can be shared with reader threads while this tree
+    /// carries on being modified. This is a deep copy unless the `cow` feature is on, in which
+    /// case the two trees share every node but the root until one of them writes to it.
+    pub fn snapshot(&self) -> Arc<BTree<T, A>> {
+        Arc::new(self.clone())
+    }
+
+    /// Every key, cloned into a vector in ascending order.
+    pub fn to_sorted_vec(&self) -> Vec<T> {
+        let mut keys = Vec::with_capacity(self.size());
+        for run in self.leaves() {
+            keys.extend_from_slice(run);
+        }
+        keys
+    }
+}
+
+impl<T: Key, A: NodeAlloc> PartialEq for BTree<T, A> {
+    fn eq(&self, other: &BTree<T, A>) -> bool {
+        self.num_keys == other.num_keys && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Key, A: NodeAlloc> Eq for BTree<T, A> {}
+
+// Hashes only the contents, in order, to agree with `PartialEq`.
+impl<T: Key + Hash, A: NodeAlloc> Hash for BTree<T, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.num_keys.hash(state);
+        for key in self.iter() {
+            key.hash(state);
+        }
+    }
+}
+
+impl<T: Key + Debug, A: NodeAlloc> Debug for BTree<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// Input to `try_from_sorted` which was not strictly ascending: the key at position `index` was
+/// not greater than the one before it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsortedError<T> {
+    pub index: usize,
+    pub previous: T,
+    pub next: T,
+}
+
+impl<T: Key + Debug> Display for UnsortedError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let problem = if self.previous == self.next {
+            "repeats"
+        } else {
+            "is smaller than"
+        };
+        write!(
+            f,
+            "input is not strictly ascending: key {:?} at position {} {} the key {:?} before it",
+            self.next, self.index, problem, self.previous
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Key + Debug> StdError for UnsortedError<T> {}
+
+impl<T: Key, A: NodeAlloc> Node<T, A> {
+    // number of keys in this node and all of its descendants
+    fn subtree_len(&self) -> usize {
+        match *self {
+            Node::Leaf(ref leaf) => leaf.num_keys,
+            Node::Internal(ref internal) => internal.subtree_keys,
+        }
+    }
+
+    fn keys(&self) -> &[T] {
+        match *self {
+            Node::Leaf(ref leaf) => &leaf.keys,
+            Node::Internal(ref internal) => &internal.keys,
+        }
+    }
+
+    // None for a leaf
+    fn children(&self) -> Option<&[Child<T, A>]> {
+        match *self {
+            Node::Leaf(_) => None,
+            Node::Internal(ref internal) => Some(&internal.children),
+        }
+    }
+
+    fn as_node_ref(&self) -> NodeRef<'_, T, A> {
+        match *self {
+            Node::Internal(ref internal) => NodeRef::Internal(internal),
+            Node::Leaf(ref leaf) => NodeRef::Leaf(leaf),
+        }
+    }
+
+    // Whether the cached bounds of an internal node show that none of its keys lie in `range`.
+    // Never true of a leaf, or of keys without bounds.
+    fn misses<R: RangeBounds<T>>(&self, range: &R) -> bool {
+        let (min, max) = match *self {
+            Node::Internal(InternalNode {
+                subtree_min: Some(ref min),
+                subtree_max: Some(ref max),
+                ..
+            }) => (min, max),
+            _ => return false,
+        };
+        let past_max = match range.start_bound() {
+            Bound::Included(start) => start > max,
+            Bound::Excluded(start) => start >= max,
+            Bound::Unbounded => false,
+        };
+        let short_of_min = match range.end_bound() {
+            Bound::Included(end) => end < min,
+            Bound::Excluded(end) => end <= min,
+            Bound::Unbounded => false,
+        };
+        past_max || short_of_min
+    }
+}
+
+/// Position of `key` within the sorted `keys`: `Ok(i)` if `keys[i] == key`, otherwise `Err(i)`
+/// where `i` is the index of the first key greater than `key`.
+fn search_keys<T: Key>(keys: &[T], key: &T) -> Result<usize, usize> {
+    keys.binary_search(key)
+}
+
+// The stored key equal to `key`, if any.
+fn stored<'a, T: Key, A: NodeAlloc>(mut node: &'a Node<T, A>, key: &T) -> Option<&'a T> {
+    loop {
+        let position = search_keys(node.keys(), key);
+        match (position, node.children()) {
+            (Ok(index), _) => return Some(&node.keys()[index]),
+            (Err(index), Some(children)) => node = &children[index],
+            (Err(_), None) => return None,
+        }
+    }
+}
+
+// The stored key equal to `key`, if any, for updating in place. Under `cow` the path down to it
+// is unshared, so a read-only descent first makes sure there is a key to find.
+fn stored_mut<'a, T: Key, A: NodeAlloc>(
+    mut node: &'a mut Node<T, A>,
+    key: &T,
+) -> Option<&'a mut T> {
+    if cfg!(feature = "cow") && stored(node, key).is_none() {
+        return None;
+    }
+
+    loop {
+        match *node {
+            Node::Leaf(ref mut leaf) => {
+                return match search_keys(&leaf.keys, key) {
+                    Ok(index) => Some(&mut leaf.keys[index]),
+                    Err(_) => None,
+                };
+            }
+            Node::Internal(ref mut internal) => match search_keys(&internal.keys, key) {
+                Ok(index) => return Some(&mut internal.keys[index]),
+                Err(index) => node = child_mut(&mut internal.children[index]),
+            },
+        }
+    }
+}
+
+impl<T: Key + Debug + Display> BTree<T> {
+    /// Prints the structure written by `write_tree` to stdout.
+    #[cfg(any(feature = "std", test))]
+    pub fn draw_tree(&self) {
+        let mut out = String::new();
+        self.write_tree(&mut out)
+            .expect("writing to a String cannot fail");
+        print!("{}", out);
+    }
+
+    /// Writes the structure drawn by `draw_tree` to `w`: one line per node, children indented
+    /// two spaces beneath their parent.
+    pub fn write_tree<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        write_node(w, &self.root, 0)
+    }
+}
+
+fn write_node<T: Key + Debug + Display, W: fmt::Write, A: NodeAlloc>(
+    w: &mut W,
+    node: &Node<T, A>,
+    depth: usize,
+) -> fmt::Result {
+    let spaces = " ".repeat(depth);
+    match *node {
+        Node::Leaf(ref leaf) => writeln!(
+            w,
+            "{}Leaf: num_keys: {}, keys: {:?}",
+            spaces, leaf.num_keys, leaf.keys
+        ),
+
+        Node::Internal(ref internal) => {
+            writeln!(
+                w,
+                "{}Internal: num_keys: {}, keys: {:?}",
+                spaces, internal.num_keys, internal.keys
+            )?;
+            for child_ref in internal.children.iter() {
+                write_node(w, child_ref, depth + 2)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// whether `key` lies beyond the given upper bound
+fn past_end<T: Key>(key: &T, end: Bound<&T>) -> bool {
+    match end {
+        Bound::Included(end) => key > end,
+        Bound::Excluded(end) => key >= end,
+        Bound::Unbounded => false,
+    }
+}
+
+// Checks the subtree rooted at `node`, whose keys must all lie strictly between `lower` and
+// `upper`; returns how many keys it holds. With `lazy` set, nodes may be underfull or empty.
+#[allow(clippy::too_many_arguments)]
+fn check_node<T: Key, A: NodeAlloc>(
+    node: &Node<T, A>,
+    is_root: bool,
+    lower: Option<&T>,
+    upper: Option<&T>,
+    depth: usize,
+    fanout: Fanout,
+    lazy: bool,
+    leaf_depth: &mut Option<usize>,
+) -> Result<usize, String> {
+    let (keys, num_keys) = match *node {
+        Node::Leaf(ref leaf) => (&leaf.keys, leaf.num_keys),
+        Node::Internal(ref internal) => (&internal.keys, internal.num_keys),
+    };
+
+    if keys.len() != num_keys {
+        return Err(format!(
+            "node at depth {} records {} keys but holds {}",
+            depth,
+            num_keys,
+            keys.len()
+        ));
+    }
+    if num_keys > fanout.max_keys(node) {
+        return Err(format!(
+            "node at depth {} holds {} keys, which should have split",
+            depth, num_keys
+        ));
+    }
+    // only median splits promise half-full nodes
+    if !is_root
+        && !lazy
+        && fanout.split_policy() == SplitPolicy::Median
+        && num_keys < fanout.min_keys(node)
+    {
+        return Err(format!(
+            "non-root node at depth {} holds only {} keys",
+            depth, num_keys
+        ));
+    }
+    if !keys.windows(2).all(|pair| pair[0] < pair[1]) {
+        return Err(format!("keys out of order in node at depth {}", depth));
+    }
+
+    let below = match (keys.first(), lower) {
+        (Some(first), Some(lo)) => first <= lo,
+        _ => false,
+    };
+    let above = match (keys.last(), upper) {
+        (Some(last), Some(hi)) => last >= hi,
+        _ => false,
+    };
+    if below || above {
+        return Err(format!(
+            "node at depth {} holds keys outside the range its parent routes to it",
+            depth
+        ));
+    }
+
+    match *node {
+        Node::Leaf(_) => {
+            if *leaf_depth.get_or_insert(depth) != depth {
+                return Err(format!(
+                    "leaves at depths {} and {}",
+                    leaf_depth.unwrap(),
+                    depth
+                ));
+            }
+            Ok(num_keys)
+        }
+
+        Node::Internal(ref internal) => {
+            if internal.children.len() != num_keys + 1 {
+                return Err(format!(
+                    "internal node at depth {} has {} keys but {} children",
+                    depth,
+                    num_keys,
+                    internal.children.len()
+                ));
+            }
+            if num_keys == 0 && !lazy {
+                return Err(format!("internal node at depth {} has no keys", depth));
+            }
+
+            let mut total = num_keys;
+            for (i, child) in internal.children.iter().enumerate() {
+                let child_lower = if i == 0 { lower } else { Some(&keys[i - 1]) };
+                let child_upper = if i == num_keys { upper } else { Some(&keys[i]) };
+                total += check_node(
+                    child,
+                    false,
+                    child_lower,
+                    child_upper,
+                    depth + 1,
+                    fanout,
+                    lazy,
+                    leaf_depth,
+                )?;
+            }
+            if internal.subtree_keys != total {
+                return Err(format!(
+                    "internal node at depth {} records {} keys below it but holds {}",
+                    depth, internal.subtree_keys, total
+                ));
+            }
+            if internal.subtree_min != lowest_bound(internal)
+                || internal.subtree_max != highest_bound(internal)
+            {
+                return Err(format!(
+                    "internal node at depth {} has stale bounds for its subtree",
+                    depth
+                ));
+            }
+            Ok(total)
+        }
+    }
+}
+
+// Counts the keys in a subtree, repairing all of the bookkeeping counts within it.
+fn recount_node<T: Key, A: NodeAlloc>(node: &mut Node<T, A>) -> usize {
+    match *node {
+        Node::Leaf(ref mut leaf) => {
+            leaf.num_keys = leaf.keys.len();
+            leaf.num_keys
+        }
+        Node::Internal(ref mut internal) => {
+            internal.num_keys = internal.keys.len();
+            internal.subtree_keys = internal.num_keys;
+            for child in internal.children.iter_mut() {
+                internal.subtree_keys += recount_node(child_mut(child));
+            }
+            refresh_bounds(internal);
+            internal.subtree_keys
+        }
+    }
+}
+
+// the smallest key of a subtree as a bound, or `None` if it is empty or the keys have no bounds
+fn min_bound<T: Key, A: NodeAlloc>(node: &Node<T, A>) -> Option<T> {
+    match *node {
+        Node::Leaf(ref leaf) => leaf.keys.first().and_then(Key::copy_bound),
+        Node::Internal(ref internal) => internal.subtree_min.as_ref().and_then(Key::copy_bound),
+    }
+}
+
+fn max_bound<T: Key, A: NodeAlloc>(node: &Node<T, A>) -> Option<T> {
+    match *node {
+        Node::Leaf(ref leaf) => leaf.keys.last().and_then(Key::copy_bound),
+        Node::Internal(ref internal) => internal.subtree_max.as_ref().and_then(Key::copy_bound),
+    }
+}
+
+// The bound an internal node should cache for its smallest key. Past the first child only when
+// that child is empty, which lazy removal can leave behind, in which case the key after it is
+// the smallest.
+fn lowest_bound<T: Key, A: NodeAlloc>(internal: &InternalNode<T, A>) -> Option<T> {
+    for (index, child) in internal.children.iter().enumerate() {
+        if let Some(bound) = min_bound(child) {
+            return Some(bound);
+        }
+        // either the child is empty, or the keys have no bounds and neither does this one
+        if let Some(key) = internal.keys.get(index) {
+            return key.copy_bound();
+        }
+    }
+    None
+}
+
+fn highest_bound<T: Key, A: NodeAlloc>(internal: &InternalNode<T, A>) -> Option<T> {
+    for (index, child) in internal.children.iter().enumerate().rev() {
+        if let Some(bound) = max_bound(child) {
+            return Some(bound);
+        }
+        if let Some(key) = index.checked_sub(1).map(|i| &internal.keys[i]) {
+            return key.copy_bound();
+        }
+    }
+    None
+}
+
+// A bound for `key` if it is below `min`, the smallest key of the subtree it is going into, so
+// that only an insert which moves the bound pays for copying it. A subtree with no bound is
+// empty, or its keys have none to copy.
+fn bound_if_below<T: Key>(key: &T, min: &Option<T>) -> Option<T> {
+    match *min {
+        Some(ref min) if key >= min => None,
+        _ => key.copy_bound(),
+    }
+}
+
+fn bound_if_above<T: Key>(key: &T, max: &Option<T>) -> Option<T> {
+    match *max {
+        Some(ref max) if key <= max => None,
+        _ => key.copy_bound(),
+    }
+}
+
+fn refresh_bounds<T: Key, A: NodeAlloc>(internal: &mut InternalNode<T, A>) {
+    internal.subtree_min = lowest_bound(internal);
+    internal.subtree_max = highest_bound(internal);
+}
+
+// Moves every key of the subtree into `out`, in ascending order.
+fn drain_node<T: Key, A: NodeAlloc>(node: Node<T, A>, out: &mut Vec<T>) {
+    match node {
+        Node::Leaf(leaf) => out.extend(leaf.keys),
+        Node::Internal(internal) => {
+            let mut keys = internal.keys.into_iter();
+            for child in internal.children {
+                drain_node(into_node(child), out);
+                out.extend(keys.next());
+            }
+        }
+    }
+}
+
+// most keys a subtree of the given height can hold without any node needing to split
+fn subtree_capacity(height: usize, fanout: Fanout) -> usize {
+    let mut capacity = fanout.leaf_max_keys();
+    for _ in 0..height {
+        capacity = capacity
+            .saturating_add(1)
+            .saturating_mul(fanout.internal_max_keys() + 1)
+            .saturating_sub(1);
+    }
+    capacity
+}
+
+// Empty leaves allocate nothing until their first key arrives; see `reserve_for_insert`.
+fn empty_leaf<T: Key, A: NodeAlloc>(alloc: &A) -> Node<T, A> {
+    Node::Leaf(LeafNode {
+        keys: vec_in(0, alloc),
+        num_keys: 0,
+        next: LeafLink::none(),
+    })
+}
+
+// Makes room for one more key in a leaf, growing geometrically from a small start but never past
+// the size at which the leaf splits, so a full leaf holds no slack beyond that.
+fn reserve_for_insert<T: Key, A: NodeAlloc>(leaf: &mut LeafNode<T, A>, fanout: Fanout) {
+    let len = leaf.keys.len();
+    if len == leaf.keys.capacity() {
+        let target = (len * 2).max(4).min(fanout.leaf_max_keys() + 1);
+        leaf.keys.reserve_exact(target - len);
+    }
+}
+
+// Builds a subtree of exactly the given height holding the next `num_keys` keys. Keys are spread
+// as evenly as possible over the fewest children that can hold them, which keeps every node at
+// least half full.
+fn build_subtree<T: Key, A: NodeAlloc, I: Iterator<Item = T>>(
+    keys: &mut I,
+    num_keys: usize,
+    height: usize,
+    fanout: Fanout,
+    alloc: &A,
+) -> Node<T, A> {
+    if height == 0 {
+        let mut leaf_keys = vec_in(fanout.leaf_max_keys() + 1, alloc);
+        leaf_keys.extend(keys.take(num_keys));
+
+        return Node::Leaf(LeafNode {
+            num_keys: leaf_keys.len(),
+            keys: leaf_keys,
+            next: LeafLink::none(),
+        });
+    }
+
+    let child_capacity = subtree_capacity(height - 1, fanout);
+    let num_children = (num_keys + 1 + child_capacity) / (child_capacity + 1);
+    let child_total = num_keys + 1 - num_children;
+
+    let mut internal = InternalNode {
+        num_keys: num_children - 1,
+        keys: vec_in(fanout.internal_max_keys() + 1, alloc),
+        children: vec_in(fanout.internal_max_keys() + 2, alloc),
+        subtree_keys: num_keys,
+        subtree_min: None,
+        subtree_max: None,
+    };
+
+    for i in 0..num_children {
+        let child_keys =
+            child_total / num_children + if i < child_total % num_children { 1 } else { 0 };
+        internal.children.push(new_child(
+            build_subtree(keys, child_keys, height - 1, fanout, alloc),
+            alloc,
+        ));
+
+        if i + 1 < num_children {
+            internal
+                .keys
+                .push(keys.next().expect("ran out of keys building a subtree"));
+        }
+    }
+
+    refresh_bounds(&mut internal);
+    Node::Internal(internal)
+}
+
+// Only called on a node which has just gone one key over its maximum, so it holds `max + 1`
+// keys; the median index is `ceil(max / 2)`, leaving at least one key on either side of it and
+// keeping the `median_index + 1..` splits in bounds.
+fn split_node<T: Key, A: NodeAlloc>(node: &mut Node<T, A>, fanout: Fanout) -> SplitResult<T, A> {
+    let median_index = fanout.median_index(node);
+    debug_assert_eq!(node.keys().len(), fanout.max_keys(node) + 1);
+    match *node {
+        Node::Leaf(ref mut leaf) => split_leaf_node(leaf, median_index),
+        Node::Internal(ref mut internal) => split_internal_node(internal, median_index),
+    }
+}
+
+fn split_internal_node<T: Key, A: NodeAlloc>(
+    node: &mut InternalNode<T, A>,
+    median_index: usize,
+) -> SplitResult<T, A> {
+    let right_keys = node.keys.split_off(median_index + 1);
+    let right_children = node.children.split_off(median_index + 1);
+
+    let median_key = node.keys.remove(median_index);
+
+    let mut right = InternalNode {
+        num_keys: right_keys.len(),
+        subtree_keys: right_keys.len()
+            + right_children
+                .iter()
+                .map(|c| c.subtree_len())
+                .sum::<usize>(),
+        keys: right_keys,
+        children: right_children,
+        subtree_min: None,
+        subtree_max: None,
+    };
+
+    node.num_keys = node.keys.len();
+    node.subtree_keys -= right.subtree_keys + 1;
+    refresh_bounds(node);
+    refresh_bounds(&mut right);
+
+    SplitResult {
+        right: Node::Internal(right),
+        median_key,
+    }
+}
+
+fn split_leaf_node<T: Key, A: NodeAlloc>(
+    node: &mut LeafNode<T, A>,
+    median_index: usize,
+) -> SplitResult<T, A> {
+    let right_keys = node.keys.split_off(median_index + 1);
+    let median_key = node.keys.remove(median_index);
+
+    let right = LeafNode {
+        num_keys: right_keys.len(),
+        keys: right_keys,
+        next: node.next.take(),
+    };
+
+    node.num_keys = node.keys.len();
+
+    SplitResult {
+        right: Node::Leaf(right),
+        median_key,
+    }
+}
+
+fn insert_at_node<T: Key, A: NodeAlloc>(
+    node: &mut Node<T, A>,
+    key: T,
+    fanout: Fanout,
+    alloc: &A,
+) -> InsertState<T> {
+    match *node {
+        Node::Internal(ref mut internal) => insert_at_internal_node(internal, key, fanout, alloc),
+        Node::Leaf(ref mut leaf) => insert_at_leaf_node(leaf, key, fanout),
+    }
+}
+
+fn insert_at_internal_node<T: Key, A: NodeAlloc>(
+    internal: &mut InternalNode<T, A>,
+    key: T,
+    fanout: Fanout,
+    alloc: &A,
+) -> InsertState<T> {
+    for i in 0..internal.num_keys {
+        match key.cmp(&internal.keys[i]) {
+            Ordering::Less => {
+                // only a key under the first child can be a new smallest
+                let new_min = if i == 0 {
+                    bound_if_below(&key, &internal.subtree_min)
+                } else {
+                    None
+                };
+                let mut insert_state =
+                    insert_at_node(child_mut(&mut internal.children[i]), key, fanout, alloc);
+
+                if insert_state.rejected.is_none() {
+                    internal.subtree_keys += 1;
+                }
+
+                if insert_state.must_split {
+                    let split_result = split_node(child_mut(&mut internal.children[i]), fanout);
+
+                    internal.keys.insert(i, split_result.median_key);
+                    internal
+                        .children
+                        .insert(i + 1, new_child(split_result.right, alloc));
+                    link_split_leaf(&mut internal.children, i);
+                    internal.num_keys += 1;
+
+                    insert_state.splits += 1;
+                    insert_state.must_split = internal.num_keys > fanout.internal_max_keys();
+                }
+
+                if insert_state.rejected.is_none() && new_min.is_some() {
+                    internal.subtree_min = new_min;
+                }
+
+                return insert_state;
+            }
+
+            Ordering::Equal => {
+                return InsertState {
+                    rejected: Some(key),
+                    must_split: false,
+                    splits: 0,
+                };
+            }
+
+            Ordering::Greater => {}
+        }
+    }
+
+    // the key goes under the last child, so it may be the new largest, and the new smallest too
+    // if that is the only child
+    let new_min = if internal.num_keys == 0 {
+        bound_if_below(&key, &internal.subtree_min)
+    } else {
+        None
+    };
+    let new_max = bound_if_above(&key, &internal.subtree_max);
+    let mut insert_state = insert_at_node(
+        child_mut(&mut internal.children[internal.num_keys]),
+        key,
+        fanout,
+        alloc,
+    );
+
+    if insert_state.rejected.is_none() {
+        internal.subtree_keys += 1;
+    }
+
+    if insert_state.must_split {
+        let split_result = split_node(child_mut(&mut internal.children[internal.num_keys]), fanout);
+
+        internal.keys.push(split_result.median_key);
+        internal.children.push(new_child(split_result.right, alloc));
+        link_split_leaf(&mut internal.children, internal.num_keys);
+        internal.num_keys += 1;
+
+        insert_state.splits += 1;
+        insert_state.must_split = internal.num_keys > fanout.internal_max_keys();
+    }
+
+    if insert_state.rejected.is_none() {
+        if new_min.is_some() {
+            internal.subtree_min = new_min;
+        }
+        if new_max.is_some() {
+            internal.subtree_max = new_max;
+        }
+    }
+
+    insert_state
+}
+
+fn insert_at_leaf_node<T: Key, A: NodeAlloc>(
+    leaf: &mut LeafNode<T, A>,
+    key: T,
+    fanout: Fanout,
+) -> InsertState<T> {
+    for i in 0..leaf.num_keys {
+        match key.cmp(&leaf.keys[i]) {
+            Ordering::Less => {
+                reserve_for_insert(leaf, fanout);
+                leaf.keys.insert(i, key);
+                leaf.num_keys += 1;
+                return InsertState {
+                    rejected: None,
+                    must_split: leaf.num_keys > fanout.leaf_max_keys(),
+                    splits: 0,
+                };
+            }
+
+            Ordering::Equal => {
+                return InsertState {
+                    rejected: Some(key),
+                    must_split: false,
+                    splits: 0,
+                };
+            }
+            Ordering::Greater => {}
+        }
+    }
+
+    reserve_for_insert(leaf, fanout);
+    leaf.keys.insert(leaf.num_keys, key);
+    leaf.num_keys += 1;
+
+    InsertState {
+        rejected: None,
+        must_split: leaf.num_keys > fanout.leaf_max_keys(),
+        splits: 0,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unnecessary_cast)] // the oldest tests spell their sizes `0 as usize`
+mod tests {
+    use super::*;
+
+    use std::ops::Bound;
+
+    #[test]
+    fn empty_test_u32() {
+        let empty = BTree::<u32>::new();
+
+        assert!(!empty.find(&1331));
+        assert!(!empty.find(&642426344));
+
+        assert_eq!(empty.size(), 0 as usize);
+    }
+
+    #[test]
+    fn empty_test_i64() {
+        let empty = BTree::<i64>::new();
+
+        assert!(!empty.find(&1331));
+        assert!(!empty.find(&642426344));
+
+        assert_eq!(empty.size(), 0 as usize);
+    }
+
+    #[test]
+    fn test_insert_u32() {
+        let mut tree = BTree::<u32>::new();
+
+        assert!(tree.size() == 0 as usize);
+
+        assert!(tree.insert(123));
+
+        assert!(tree.size() == 1 as usize);
+        assert!(tree.find(&123));
+        assert!(!tree.find(&43));
+        assert!(!tree.find(&5278945));
+
+        assert!(tree.insert(5278945));
+
+        assert!(tree.size() == 2 as usize);
+        assert!(tree.find(&123));
+        assert!(!tree.find(&43));
+        assert!(tree.find(&5278945));
+
+        assert!(!tree.insert(5278945));
+
+        assert!(tree.size() == 2 as usize);
+        assert!(tree.find(&123));
+        assert!(!tree.find(&43));
+        assert!(tree.find(&5278945));
+    }
+
+    #[test]
+    fn test_insert_more_i32() {
+        let mut tree = BTree::<i32>::new();
+
+        for i in 0..50 {
             assert!(tree.insert(i));
             assert_eq!(tree.size(), (i + 1) as usize);
 
-            tree.draw_tree();
+            tree.draw_tree();
+
+            for j in 0..1000 {
+                assert_eq!(tree.find(&j), j <= i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_out_of_order_i64() {
+        let mut count = 0;
+        let mut tree = BTree::<i64>::new();
+
+        for x in 0..BTREE_MIN_KEYS + 1 {
+            assert_eq!(tree.size(), count);
+            assert!(!tree.find(&(x as i64)));
+
+            tree.insert(x as i64);
+            count += 1;
+
+            assert_eq!(tree.size(), count);
+            assert!(tree.find(&(x as i64)));
+        }
+
+        for x in BTREE_MAX_KEYS * 50..BTREE_MAX_KEYS * 60 {
+            assert_eq!(tree.size(), count);
+            assert!(!tree.find(&(x as i64)));
+
+            tree.insert(x as i64);
+            count += 1;
+
+            assert_eq!(tree.size(), count);
+            assert!(tree.find(&(x as i64)));
+        }
+
+        for x in BTREE_MIN_KEYS + 1..100 {
+            assert_eq!(tree.size(), count);
+            assert!(!tree.find(&(x as i64)));
+
+            tree.insert(x as i64);
+            count += 1;
+
+            assert_eq!(tree.size(), count);
+            assert!(tree.find(&(x as i64)));
+        }
+    }
+
+    #[test]
+    fn test_insert_or_get_existing() {
+        let mut tree = BTree::<u64>::new();
+
+        assert_eq!(tree.insert_or_get_existing(7), Ok(()));
+        assert_eq!(tree.size(), 1);
+        assert_eq!(tree.insert_or_get_existing(7), Err(7));
+        assert_eq!(tree.size(), 1);
+
+        for i in 0..1000 {
+            assert_eq!(tree.insert_or_get_existing(i * 3), Ok(()));
+        }
+        assert_eq!(tree.size(), 1001);
+        for i in 0..1000 {
+            assert_eq!(tree.insert_or_get_existing(i * 3), Err(i * 3));
+        }
+        assert_eq!(tree.size(), 1001);
+    }
+
+    #[test]
+    fn test_insert_all_or_none_new_batch() {
+        let mut tree = BTree::from_sorted((0..100_u32).map(|k| 2 * k));
+
+        assert_eq!(tree.insert_all_or_none(vec![1, 301, 7, 1001]), Ok(()));
+        assert_eq!(tree.len(), 104);
+        assert!(tree.find(&301) && tree.find(&7));
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_insert_all_or_none_rejects_whole_batch() {
+        let mut tree = BTree::from_sorted((0..100_u32).map(|k| 2 * k));
+        let before = tree.clone();
+
+        assert_eq!(tree.insert_all_or_none(vec![1, 3, 44, 5]), Err(44));
+        assert_eq!(tree, before);
+
+        assert_eq!(tree.insert_all_or_none(vec![9, 11, 9, 13]), Err(9));
+        assert_eq!(tree, before);
+        assert!(!tree.find(&11));
+    }
+
+    #[test]
+    fn test_partition_by() {
+        let tree = BTree::from_sorted(0..1000_u32);
+        let parts = tree.partition_by(&[250, 500, 750]);
+
+        assert_eq!(parts.len(), 4);
+        for (i, part) in parts.iter().enumerate() {
+            assert_eq!(part.len(), 250);
+            assert_eq!(part.first(), Some(&(250 * i as u32)));
+            assert_eq!(part.last(), Some(&(250 * i as u32 + 249)));
+            assert_eq!(part.check_invariants(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_partition_by_uneven() {
+        let tree = BTree::from_sorted((0..100_i32).map(|k| k * 10));
+        let parts = tree.partition_by(&[-5, 0, 1, 995, 5000]);
+
+        let lens = parts.iter().map(|p| p.len()).collect::<Vec<_>>();
+        assert_eq!(lens, vec![0, 0, 1, 99, 0, 0]);
+        assert_eq!(BTree::<i32>::new().partition_by(&[]).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "strictly ascending")]
+    fn test_partition_by_rejects_unsorted_boundaries() {
+        BTree::from_sorted(0..10_u32).partition_by(&[5, 3]);
+    }
+
+    #[test]
+    fn test_insert_reporting() {
+        let mut tree = BTree::new();
+        let mut total_splits = 0;
+
+        for i in 0..100_000_u64 {
+            let height = tree.height();
+            let report = tree.insert_reporting(i);
+
+            assert!(report.inserted);
+            assert_eq!(report.height_increased, tree.height() > height);
+            if report.height_increased {
+                // every level split, root included
+                assert_eq!(report.splits, height);
+            }
+            total_splits += report.splits;
+        }
+
+        // every split adds one node, and every root split a new root besides
+        assert_eq!(total_splits + tree.height(), tree.nodes().count());
+
+        let report = tree.insert_reporting(5);
+        assert_eq!(
+            report,
+            InsertReport {
+                inserted: false,
+                splits: 0,
+                height_increased: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_sorted() {
+        for &n in [0, 1, 30, 31, 32, 960, 961, 5000, 30_000].iter() {
+            let tree = BTree::from_sorted(0..n as u64);
+
+            assert_eq!(tree.size(), n);
+            assert_eq!(tree.iter().count(), n);
+            for i in 0..n as u64 {
+                assert!(tree.find(&i));
+            }
+            assert!(!tree.find(&(n as u64)));
+        }
+    }
+
+    #[test]
+    fn test_try_from_sorted() {
+        let tree = BTree::try_from_sorted(0..5000_u32).unwrap();
+        assert_eq!(tree, BTree::from_sorted(0..5000));
+        assert_eq!(tree.check_invariants(), Ok(()));
+
+        assert!(BTree::<u32>::try_from_sorted(vec![]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_try_from_sorted_rejects_unsorted() {
+        let err = BTree::try_from_sorted(vec![1_u32, 5, 9, 7, 11]).unwrap_err();
+        assert_eq!(
+            err,
+            UnsortedError {
+                index: 3,
+                previous: 9,
+                next: 7
+            }
+        );
+        assert!(err
+            .to_string()
+            .contains("7 at position 3 is smaller than the key 9"));
+    }
+
+    #[test]
+    fn test_try_from_sorted_rejects_duplicates() {
+        let err = BTree::try_from_sorted(vec![1_i64, 2, 2, 3]).unwrap_err();
+        assert_eq!((err.index, err.previous, err.next), (2, 2, 2));
+        assert!(err.to_string().contains("repeats"));
+    }
+
+    #[test]
+    fn test_invariants_hold() {
+        for &n in [0, 1, 30, 31, 32, 960, 961, 5000, 30_000].iter() {
+            assert_eq!(BTree::from_sorted(0..n as u64).check_invariants(), Ok(()));
+        }
+
+        let mut tree = BTree::<i64>::new();
+        for i in 0..5000 {
+            tree.insert((i * 7919) % 5003);
+            if i % 97 == 0 {
+                assert_eq!(tree.check_invariants(), Ok(()));
+            }
+        }
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
 
-            for j in 0..1000 {
-                assert_eq!(tree.find(&j), j <= i);
+    #[test]
+    fn test_invariants_catch_bad_count() {
+        let mut tree = BTree::from_sorted(0..100_u32);
+        tree.set_num_keys(99);
+        assert!(tree.check_invariants().is_err());
+    }
+
+    #[test]
+    fn test_recount_repairs_subtree_counts() {
+        let mut tree = BTree::from_sorted(0..5000_u32);
+        if let Node::Internal(ref mut root) = tree.root {
+            root.subtree_keys = 3;
+        }
+        assert!(tree.check_invariants().is_err());
+
+        assert_eq!(tree.recount(), 5000);
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[derive(Clone, Debug)]
+    struct Record {
+        id: u32,
+        payload: &'static str,
+    }
+
+    keyed_by!(Record, id);
+
+    #[test]
+    fn test_replayed_inserts_change_nothing() {
+        let mut tree = BTree::with_fanout(Fanout::new(4, 4));
+        for key in 0..1000_u32 {
+            assert!(tree.insert(key));
+        }
+
+        let snapshot = |tree: &BTree<u32>| {
+            let mut layout = String::new();
+            tree.write_tree(&mut layout).unwrap();
+            (tree.size(), tree.height(), tree.to_sorted_vec(), layout)
+        };
+        let before = snapshot(&tree);
+        let mod_count = tree.mod_count;
+
+        for key in 0..1000 {
+            assert!(!tree.insert(key));
+        }
+        for key in (0..1000).rev() {
+            assert!(!tree.insert(key));
+        }
+
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(snapshot(&tree), before);
+        assert_eq!(tree.mod_count, mod_count);
+    }
+
+    #[test]
+    fn test_inserts_copy_bounds_only_when_they_move() {
+        use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+        static COPIES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+        struct Counted(u64);
+
+        impl Key for Counted {
+            fn copy_bound(&self) -> Option<Counted> {
+                COPIES.fetch_add(1, AtomicOrdering::Relaxed);
+                Some(self.clone())
             }
         }
+
+        let mut tree = BTree::from_sorted((0..3000).map(|k| Counted(1000 * k)));
+        assert!(tree.height() >= 3);
+
+        // every key goes down the right spine, but none of them is a new largest key
+        COPIES.store(0, AtomicOrdering::Relaxed);
+        for key in 2_998_001..2_999_000 {
+            tree.insert(Counted(key));
+        }
+        // what copies there are come from the internal nodes which split
+        assert!(COPIES.load(AtomicOrdering::Relaxed) < 100);
+        assert_eq!(tree.check_invariants(), Ok(()));
+
+        tree.insert(Counted(5_000_000));
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree.last(), Some(&Counted(5_000_000)));
     }
 
     #[test]
-    fn test_insert_out_of_order_i64() {
-        let mut count = 0;
-        let mut tree = BTree::<i64>::new();
+    fn test_is_single_leaf() {
+        let mut tree = BTree::new();
+        assert!(tree.is_single_leaf());
 
-        for x in 0..BTREE_MIN_KEYS + 1 {
-            assert_eq!(tree.size(), count);
-            assert!(!tree.find(&(x as i64)));
+        for key in 0..5_u32 {
+            tree.insert(key);
+        }
+        assert!(tree.is_single_leaf());
 
-            tree.insert(x as i64);
-            count += 1;
+        let leaf_max = tree.fanout().leaf_max_keys() as u32;
+        for key in 5..=leaf_max {
+            tree.insert(key);
+        }
+        assert!(!tree.is_single_leaf());
+        assert_eq!(tree.height(), 2);
+    }
 
-            assert_eq!(tree.size(), count);
-            assert!(tree.find(&(x as i64)));
+    #[test]
+    fn test_find_within_depth() {
+        let tree = BTree::from_sorted(0..100_000_u32);
+        let height = tree.height();
+        assert!(height >= 3);
+
+        // a root separator is settled by the root alone
+        let root_key = tree.root.keys()[0];
+        assert_eq!(tree.depth_of(&root_key), Some(0));
+        assert_eq!(tree.find_within_depth(&root_key, 0), Some(true));
+
+        // a leaf key needs the whole height
+        let leaf_key = 1;
+        assert_eq!(tree.depth_of(&leaf_key), Some(height - 1));
+        assert_eq!(tree.find_within_depth(&leaf_key, 0), None);
+        assert_eq!(tree.find_within_depth(&leaf_key, height - 2), None);
+        assert_eq!(tree.find_within_depth(&leaf_key, height - 1), Some(true));
+        assert_eq!(tree.find_within_depth(&leaf_key, 100), Some(true));
+
+        // so does ruling a key out, unless a Bloom filter does it first
+        assert_eq!(tree.find_within_depth(&200_000, 1), None);
+        assert_eq!(tree.find_within_depth(&200_000, height - 1), Some(false));
+        assert_eq!(BTree::<u32>::new().find_within_depth(&1, 0), Some(false));
+
+        let mut with_bloom = BTree::with_bloom(1000);
+        for key in 0..1000_u32 {
+            with_bloom.insert(2 * key);
         }
+        assert!(with_bloom.height() > 1);
+        assert!((0..1000_u32)
+            .map(|key| 2 * key + 1)
+            .any(|key| with_bloom.find_within_depth(&key, 0) == Some(false)));
+    }
 
-        for x in BTREE_MAX_KEYS * 50..BTREE_MAX_KEYS * 60 {
-            assert_eq!(tree.size(), count);
-            assert!(!tree.find(&(x as i64)));
+    #[test]
+    fn test_find_by_id_alone() {
+        let mut tree = BTree::new();
+        for id in (0..500).rev() {
+            tree.insert(Record {
+                id: 3 * id,
+                payload: if id % 2 == 0 { "even" } else { "odd" },
+            });
+        }
 
-            tree.insert(x as i64);
-            count += 1;
+        let found = tree.find_by(|record| record.id.cmp(&231)).unwrap();
+        assert_eq!(found.id, 231);
+        assert_eq!(found.payload, "odd");
+        assert_eq!(tree.find_by(|record| record.id.cmp(&0)).unwrap().id, 0);
+        assert_eq!(
+            tree.find_by(|record| record.id.cmp(&1497)).unwrap().id,
+            1497
+        );
+
+        assert!(tree.find_by(|record| record.id.cmp(&232)).is_none());
+        assert!(tree.find_by(|record| record.id.cmp(&5000)).is_none());
+        assert!(BTree::<Record>::new()
+            .find_by(|_| Ordering::Equal)
+            .is_none());
+    }
 
-            assert_eq!(tree.size(), count);
-            assert!(tree.find(&(x as i64)));
+    #[derive(Clone, Debug)]
+    struct Tally {
+        word: u32,
+        count: u32,
+    }
+
+    keyed_by!(Tally, word);
+
+    #[test]
+    fn test_update_many_counts_occurrences() {
+        let stream = (0..20_000_u32)
+            .map(|i| (i * 7919) % 1009 % (1 + i % 300))
+            .collect::<Vec<_>>();
+        let mut expected = ::std::collections::BTreeMap::new();
+        for &word in &stream {
+            *expected.entry(word).or_insert(0) += 1;
         }
 
-        for x in BTREE_MIN_KEYS + 1..100 {
-            assert_eq!(tree.size(), count);
-            assert!(!tree.find(&(x as i64)));
+        let mut tree = BTree::new();
+        tree.update_many(
+            stream.iter().map(|&word| Tally { word, count: 1 }),
+            |stored, update| stored.count += update.count,
+        );
+
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree.len(), expected.len());
+        let counts = tree
+            .iter()
+            .map(|tally| (tally.word, tally.count))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, expected.into_iter().collect::<Vec<_>>());
+    }
 
-            tree.insert(x as i64);
-            count += 1;
+    #[test]
+    fn test_update_many_in_place_bumps_mod_count() {
+        let mut tree = BTree::from_sorted((0..100).map(|word| Tally { word, count: 1 }));
+        let before = tree.mod_count();
+
+        tree.update_many_with(
+            vec![Tally { word: 5, count: 9 }],
+            ConflictPolicy::keep_left(),
+        );
+        assert_eq!(tree.mod_count(), before);
+
+        tree.update_many_with(
+            vec![Tally { word: 5, count: 9 }],
+            ConflictPolicy::keep_right(),
+        );
+        assert!(tree.mod_count() != before);
+    }
 
-            assert_eq!(tree.size(), count);
-            assert!(tree.find(&(x as i64)));
+    #[test]
+    #[should_panic(expected = "tree was modified during iteration")]
+    fn test_update_many_in_place_invalidates_detached_iter() {
+        let mut tree = BTree::from_sorted((0..100).map(|word| Tally { word, count: 1 }));
+        let mut detached = tree.detached_iter();
+        detached.next(&tree);
+
+        tree.update_many_with(
+            vec![Tally { word: 5, count: 9 }],
+            ConflictPolicy::keep_right(),
+        );
+        detached.next(&tree);
+    }
+
+    #[test]
+    fn test_get_key_value_returns_stored_instance() {
+        let mut tree = BTree::new();
+        for id in 0..500 {
+            tree.insert(Record {
+                id,
+                payload: if id % 2 == 0 { "even" } else { "odd" },
+            });
+        }
+
+        let probe = Record {
+            id: 77,
+            payload: "probe",
+        };
+        let stored = tree.get_key_value(&probe).unwrap();
+        assert_eq!(stored.id, 77);
+        assert_eq!(stored.payload, "odd");
+
+        let probe = Record {
+            id: 500,
+            payload: "probe",
+        };
+        assert!(tree.get_key_value(&probe).is_none());
+    }
+
+    #[test]
+    fn test_try_insert() {
+        let mut tree = BTree::new();
+        for i in 0..1000_u32 {
+            assert!(tree.try_insert((i * 37) % 1000).is_ok());
+        }
+
+        match tree.try_insert(500) {
+            Err(Error::DuplicateKey) => {}
+            other => panic!("expected a duplicate key error, got {:?}", other),
+        }
+        assert_eq!(tree.size(), 1000);
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_from_sorted_dedup() {
+        let tree = BTree::from_sorted_dedup(vec![1_u32, 1, 2, 2, 2, 3]);
+        assert_eq!(tree.size(), 3);
+        assert_eq!(tree.iter().cloned().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(tree.check_invariants(), Ok(()));
+
+        let big = BTree::from_sorted_dedup((0..30_000_u64).map(|i| i / 3));
+        assert_eq!(big, BTree::from_sorted(0..10_000));
+    }
+
+    #[test]
+    fn test_from_sorted_dedup_keeps_first() {
+        let records = vec![
+            (1, "first"),
+            (1, "second"),
+            (2, "only"),
+            (3, "first"),
+            (3, "x"),
+        ];
+        let tree = BTree::from_sorted_dedup(
+            records
+                .into_iter()
+                .map(|(id, payload)| Record { id, payload }),
+        );
+
+        let payloads = tree.iter().map(|r| r.payload).collect::<Vec<_>>();
+        assert_eq!(payloads, vec!["first", "only", "first"]);
+    }
+
+    #[test]
+    fn test_insert_interned() {
+        let mut tree = BTree::new();
+        for id in (0..500).filter(|id| id % 7 != 0) {
+            tree.insert(Record {
+                id,
+                payload: "original",
+            });
+        }
+
+        let stored = tree.insert_interned(Record {
+            id: 77,
+            payload: "new",
+        });
+        assert_eq!((stored.id, stored.payload), (77, "new"));
+
+        let stored = tree.insert_interned(Record {
+            id: 78,
+            payload: "duplicate",
+        });
+        assert_eq!((stored.id, stored.payload), (78, "original"));
+
+        let stored = tree.insert_interned(Record {
+            id: 77,
+            payload: "again",
+        });
+        assert_eq!(stored.payload, "new");
+        assert_eq!(tree.size(), 429);
+    }
+
+    #[test]
+    fn test_write_tree() {
+        let mut out = String::new();
+        BTree::from_sorted(1..4_u32).write_tree(&mut out).unwrap();
+        assert_eq!(out, "Leaf: num_keys: 3, keys: [1, 2, 3]\n");
+
+        let mut tree = BTree::new();
+        for i in 0..31_u32 {
+            tree.insert(i);
+        }
+        out.clear();
+        tree.write_tree(&mut out).unwrap();
+
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "Internal: num_keys: 1, keys: [15]");
+        assert!(lines[1].starts_with("  Leaf: num_keys: 15, keys: [0, 1, "));
+        assert!(lines[2].starts_with("  Leaf: num_keys: 15, keys: [16, 17, "));
+    }
+
+    #[test]
+    fn test_key_refs() {
+        let mut tree = BTree::new();
+        for i in 0..3000_u64 {
+            tree.insert((i * 7919) % 3000);
+        }
+
+        let refs = tree.key_refs();
+        assert_eq!(refs.len(), tree.len());
+        assert!(refs.windows(2).all(|pair| pair[0] < pair[1]));
+        for i in (0..3000).step_by(13) {
+            assert_eq!(Some(refs[i]), tree.select(i));
+        }
+    }
+
+    #[test]
+    fn test_iter_until() {
+        let tree = BTree::from_sorted((0..5000_u32).map(|i| i * 2));
+
+        let keys = tree
+            .iter_until(&1001, |key| *key >= 1020)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![1002, 1004, 1006, 1008, 1010, 1012, 1014, 1016, 1018]
+        );
+
+        // stops straight away, or never
+        assert_eq!(tree.iter_until(&1000, |_| true).next(), None);
+        assert_eq!(tree.iter_until(&9990, |_| false).count(), 5);
+        assert_eq!(tree.iter_until(&20_000, |_| false).next(), None);
+    }
+
+    #[test]
+    fn test_for_each_group() {
+        let tree = BTree::from_sorted(0..10_000_u32);
+
+        let mut groups = Vec::new();
+        tree.for_each_group(|k| k / 1000, |group, count| groups.push((group, count)));
+        assert_eq!(groups, (0..10).map(|g| (g, 1000)).collect::<Vec<_>>());
+
+        // a grouping which doesn't follow the key order reports each run separately
+        let mut runs = Vec::new();
+        BTree::from_sorted(0..10_u32)
+            .for_each_group(|k| k % 4 < 2, |group, count| runs.push((group, count)));
+        assert_eq!(
+            runs,
+            vec![(true, 2), (false, 2), (true, 2), (false, 2), (true, 2)]
+        );
+
+        let mut calls = 0;
+        BTree::<u32>::new().for_each_group(|k| *k, |_, _| calls += 1);
+        assert_eq!(calls, 0);
+    }
+
+    #[test]
+    fn test_iter_chunks() {
+        let tree = BTree::from_sorted(0..1000_u32);
+
+        for chunk_size in [1, 7, 100, 999, 1000, 5000].iter() {
+            let chunks = tree.iter_chunks(*chunk_size).collect::<Vec<_>>();
+            let (last, full) = chunks.split_last().unwrap();
+
+            assert!(full.iter().all(|chunk| chunk.len() == *chunk_size));
+            assert!(!last.is_empty() && last.len() <= *chunk_size);
+            assert_eq!(chunks.iter().map(|c| c.len()).sum::<usize>(), tree.len());
+            assert!(chunks.concat().into_iter().eq(tree.iter()));
+        }
+
+        assert_eq!(BTree::<u32>::new().iter_chunks(10).count(), 0);
+    }
+
+    #[test]
+    fn test_depth_of() {
+        let mut tree = BTree::new();
+        for i in 0..10_000_u32 {
+            tree.insert(i);
+        }
+        let leaf_depth = tree.height() - 1;
+        assert!(leaf_depth >= 2);
+
+        // the first key promoted, when the first leaf split
+        let first_median = 15;
+        assert!(tree.depth_of(&first_median).unwrap() < leaf_depth);
+        assert_eq!(tree.depth_of(&tree.root.keys()[0]), Some(0));
+
+        let at_leaves = (0..10_000)
+            .filter(|k| tree.depth_of(k) == Some(leaf_depth))
+            .count();
+        assert!(at_leaves > 9000);
+        assert_eq!(tree.depth_of(&10_000), None);
+    }
+
+    #[test]
+    fn test_signed_extremes() {
+        let extremes = [i64::MIN, -1, 0, 1, i64::MAX];
+        let mut keys = extremes.to_vec();
+        for i in 0..500 {
+            keys.push(i - 250);
+            keys.push(i64::MIN + i);
+            keys.push(i64::MAX - i);
+        }
+
+        let mut tree = BTree::new();
+        for key in keys.iter().rev() {
+            tree.insert(*key);
+        }
+        keys.sort();
+        keys.dedup();
+
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree.iter().cloned().collect::<Vec<_>>(), keys);
+        for key in keys.iter() {
+            assert!(tree.find(key));
+        }
+        assert_eq!(tree.first(), Some(&i64::MIN));
+        assert_eq!(tree.last(), Some(&i64::MAX));
+        assert_eq!(tree.predecessor(&i64::MIN), None);
+        assert_eq!(tree.successor(&i64::MAX), None);
+        assert_eq!(tree.predecessor(&0), Some(&-1));
+        assert_eq!(tree.rank(&0), keys.iter().filter(|k| **k < 0).count());
+        assert_eq!(tree.range(-2..=2).count(), 5);
+
+        for key in extremes.iter() {
+            assert!(tree.remove(key));
+            assert!(!tree.find(key));
+        }
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_i32_extremes() {
+        let mut tree = BTree::new();
+        for key in [i32::MAX, 0, i32::MIN, -1, 1, i32::MIN + 1, i32::MAX - 1].iter() {
+            tree.insert(*key);
+        }
+
+        let keys = tree.iter().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![i32::MIN, i32::MIN + 1, -1, 0, 1, i32::MAX - 1, i32::MAX]
+        );
+        assert!(tree.find(&i32::MIN) && tree.find(&i32::MAX));
+        assert!(!tree.find(&2));
+
+        let packed = BTree::from_sorted(i32::MAX - 5000..=i32::MAX);
+        assert_eq!(packed.len(), 5001);
+        assert_eq!(packed.last(), Some(&i32::MAX));
+        assert_eq!(packed.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_first_last() {
+        let mut tree = BTree::<i32>::new();
+        assert_eq!((tree.first(), tree.last()), (None, None));
+
+        for i in 0..2000 {
+            tree.insert((i * 7919) % 2003 - 1000);
+        }
+        assert_eq!(tree.first(), tree.iter().next());
+        assert_eq!(tree.last(), tree.iter().last());
+    }
+
+    #[test]
+    #[should_panic(expected = "BTree key count overflowed usize")]
+    fn test_key_count_overflow_panics() {
+        let mut tree = BTree::new();
+        tree.insert(1_u32);
+        tree.set_num_keys(usize::MAX);
+
+        tree.insert(2);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "BTree key count disagrees with its contents")]
+    fn test_key_count_mismatch_caught_in_debug() {
+        let mut tree = BTree::new();
+        tree.insert(1_u32);
+        tree.set_num_keys(usize::MAX - 5);
+
+        tree.insert(2);
+    }
+
+    #[test]
+    fn test_recount_repairs_count() {
+        let mut tree = BTree::<u64>::new();
+        for i in 0..5000 {
+            tree.insert(i * 11 % 5003);
+        }
+        assert_eq!(tree.recount(), 5000);
+
+        tree.set_num_keys(17);
+        assert_eq!(tree.len(), 17);
+
+        assert_eq!(tree.recount(), 5000);
+        assert_eq!(tree.len(), 5000);
+        assert_eq!(tree.check_invariants(), Ok(()));
+
+        let mut empty = BTree::<u32>::new();
+        empty.set_num_keys(3);
+        assert_eq!(empty.recount(), 0);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_insert_sorted_batch_overlapping() {
+        let mut tree = BTree::from_sorted((0..2000_u64).map(|i| i * 2));
+
+        // overlaps everything; large enough to take the merge path
+        let batch = (500..3500_u64).collect::<Vec<_>>();
+        tree.insert_sorted_batch(&batch);
+
+        let mut expected = (0..2000_u64).map(|i| i * 2).collect::<Vec<_>>();
+        expected.extend(batch.iter().filter(|k| *k % 2 == 1 || **k >= 4000));
+        expected.sort();
+
+        assert_eq!(tree.size(), expected.len());
+        assert_eq!(tree.iter().cloned().collect::<Vec<_>>(), expected);
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_insert_sorted_batch_small() {
+        let mut tree = BTree::from_sorted(0..10_000_i32);
+
+        tree.insert_sorted_batch(&[-5, 3, 17, 10_000, 12_000]);
+
+        assert_eq!(tree.size(), 10_003);
+        assert!(tree.find(&-5) && tree.find(&10_000) && tree.find(&12_000));
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_insert_sorted_batch_above_max() {
+        let mut tree = BTree::<u32>::new();
+        for i in 0..1000 {
+            tree.insert(i);
+        }
+
+        let batch = (1000..50_000).collect::<Vec<_>>();
+        tree.insert_sorted_batch(&batch);
+
+        assert_eq!(tree.size(), 50_000);
+        assert!(tree.iter().cloned().eq(0..50_000));
+        assert_eq!(tree.check_invariants(), Ok(()));
+
+        let mut empty = BTree::<u32>::new();
+        empty.insert_sorted_batch(&batch);
+        assert_eq!(empty.size(), batch.len());
+        assert_eq!(empty.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_remove_range_middle() {
+        let mut tree = BTree::from_sorted(0..10_000_u64);
+
+        assert_eq!(tree.remove_range(2500..7500), 5000);
+        assert_eq!(tree.size(), 5000);
+        assert!(tree.iter().cloned().eq((0..2500).chain(7500..10_000)));
+        assert_eq!(tree.check_invariants(), Ok(()));
+
+        assert_eq!(tree.remove_range(2000..=8000), 1001);
+        assert!(tree.iter().cloned().eq((0..2000).chain(8001..10_000)));
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_remove_range_open_ended() {
+        let mut tree = BTree::<i32>::new();
+        for i in (0..3000).rev() {
+            tree.insert(i);
+        }
+
+        assert_eq!(tree.remove_range(..1000), 1000);
+        assert!(tree.iter().cloned().eq(1000..3000));
+        assert_eq!(tree.remove_range(2990..), 10);
+        assert!(tree.iter().cloned().eq(1000..2990));
+        assert_eq!(tree.check_invariants(), Ok(()));
+
+        assert_eq!(tree.remove_range(..), 1990);
+        assert!(tree.is_empty());
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_remove_range_empty() {
+        let mut tree = BTree::from_sorted((0..1000_u32).map(|i| i * 10));
+
+        assert_eq!(tree.remove_range(500..500), 0);
+        assert_eq!(tree.remove_range(501..510), 0);
+        assert_eq!(tree.remove_range(20_000..), 0);
+        assert_eq!(
+            tree.remove_range((Bound::Excluded(500), Bound::Excluded(510))),
+            0
+        );
+        assert_eq!(tree.size(), 1000);
+        assert_eq!(
+            tree.remove_range((Bound::Excluded(500), Bound::Included(510))),
+            1
+        );
+        assert_eq!(tree.size(), 999);
+        assert_eq!(tree.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_tree_is_send_and_sync() {
+        fn assert_send_sync<S: Send + Sync>() {}
+
+        assert_send_sync::<BTree<u64>>();
+        assert_send_sync::<Iter<'static, u64>>();
+        assert_send_sync::<Cursor<'static, u64>>();
+
+        // a key which isn't `Sync` still lets the tree move between threads, as long as children
+        // aren't shared
+        #[derive(Clone, Debug)]
+        struct Hits {
+            id: u32,
+            hits: ::std::cell::Cell<u32>,
+        }
+
+        keyed_by!(Hits, id);
+
+        fn assert_send<S: Send>() {}
+        #[cfg(not(feature = "cow"))]
+        assert_send::<BTree<Hits>>();
+    }
+
+    #[test]
+    fn test_snapshot_readers_and_writer() {
+        use std::thread;
+
+        let mut tree = BTree::<u64>::with_bloom(1000);
+        for i in 0..1000 {
+            tree.insert(i * 2);
+        }
+
+        let snapshot = tree.snapshot();
+        let readers = (0..4)
+            .map(|_| {
+                let snapshot = snapshot.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        assert_eq!(snapshot.size(), 1000);
+                        for i in 0..2000 {
+                            assert_eq!(snapshot.find(&i), i % 2 == 0);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 0..1000 {
+            tree.insert(i * 2 + 1);
+        }
+        tree.remove_range(..500);
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(tree.size(), 1500);
+        assert_eq!(snapshot.size(), 1000);
+        assert!(snapshot.iter().cloned().eq((0..1000).map(|i| i * 2)));
+    }
+
+    // counts the nodes below the root which this tree does not share with any other; a shared
+    // node shares its whole subtree
+    #[cfg(feature = "cow")]
+    fn unshared(node: &Node<u64>) -> usize {
+        node.children().map_or(0, |children| {
+            children
+                .iter()
+                .filter(|child| Arc::strong_count(child) == 1)
+                .map(|child| 1 + unshared(child))
+                .sum()
+        })
+    }
+
+    #[cfg(feature = "cow")]
+    #[test]
+    fn test_snapshot_shares_untouched_nodes() {
+        // ascending inserts leave half-full leaves, so one more key won't split anything
+        let mut tree = BTree::new();
+        for i in 0..20_000_u64 {
+            tree.insert(i * 2);
+        }
+        let height = tree.height();
+        assert!(height >= 3);
+
+        let snapshot = tree.snapshot();
+        assert_eq!(unshared(&tree.root), 0);
+
+        assert!(tree.insert(10_001));
+        assert!(!snapshot.find(&10_001));
+        assert_eq!(snapshot.size(), 20_000);
+        assert!(snapshot.iter().cloned().eq((0..20_000).map(|i| i * 2)));
+
+        // only the path down to the changed leaf was copied
+        assert_eq!(unshared(&tree.root), height - 1);
+        assert_eq!(unshared(&snapshot.root), height - 1);
+    }
+
+    #[cfg(feature = "cow")]
+    #[test]
+    fn test_snapshot_untouched_by_writes_that_do_nothing() {
+        let mut tree = BTree::from_sorted((0..20_000_u64).map(|i| i * 2));
+        assert!(tree.height() >= 3);
+        let snapshot = tree.snapshot();
+
+        assert!(!tree.insert(10_000));
+        assert!(!tree.remove(&10_001));
+        assert!(stored_mut(&mut tree.root, &10_001).is_none());
+        assert_eq!(unshared(&tree.root), 0);
+
+        assert!(stored_mut(&mut tree.root, &10_000).is_some());
+        assert_eq!(unshared(&tree.root), tree.height() - 1);
+        assert_eq!(snapshot.size(), 20_000);
+    }
+
+    #[test]
+    fn test_predecessor_successor() {
+        let tree = BTree::from_sorted((0..5000_i64).map(|i| i * 10));
+
+        // between keys
+        assert_eq!(tree.predecessor(&12_345), Some(&12_340));
+        assert_eq!(tree.successor(&12_345), Some(&12_350));
+
+        // equal to existing keys, wherever they live in the tree
+        for i in 1..4999 {
+            assert_eq!(tree.predecessor(&(i * 10)), Some(&(i * 10 - 10)));
+            assert_eq!(tree.successor(&(i * 10)), Some(&(i * 10 + 10)));
+        }
+
+        // extremes
+        assert_eq!(tree.predecessor(&0), None);
+        assert_eq!(tree.predecessor(&-5), None);
+        assert_eq!(tree.predecessor(&1), Some(&0));
+        assert_eq!(tree.successor(&49_990), None);
+        assert_eq!(tree.successor(&100_000), None);
+        assert_eq!(tree.successor(&49_989), Some(&49_990));
+        assert_eq!(tree.successor(&i64::MIN), Some(&0));
+        assert_eq!(tree.predecessor(&i64::MAX), Some(&49_990));
+
+        let empty = BTree::<i64>::new();
+        assert_eq!(empty.predecessor(&3), None);
+        assert_eq!(empty.successor(&3), None);
+    }
+
+    #[test]
+    fn test_hash_ignores_layout() {
+        use std::collections::HashSet;
+
+        let packed = BTree::from_sorted(0..1000_u32);
+        let mut grown = BTree::new();
+        for key in (0..1000).rev() {
+            grown.insert(key);
         }
+        let mut shrunk = BTree::from_sorted(0..2000_u32);
+        for key in 1000..2000 {
+            shrunk.remove(&key);
+        }
+        assert!(packed.fill_stats() != grown.fill_stats());
+
+        let mut set = HashSet::new();
+        set.insert(packed);
+        set.insert(grown);
+        set.insert(shrunk);
+        assert_eq!(set.len(), 1);
+
+        set.insert(BTree::from_sorted(1..1000));
+        set.insert(BTree::new());
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn test_equality_ignores_layout() {
+        let mut inserted = BTree::<u32>::new();
+        for i in (0..1000).rev() {
+            inserted.insert(i);
+        }
+
+        assert_eq!(inserted, BTree::from_sorted(0..1000));
+        assert!(inserted != BTree::from_sorted(0..999));
+        assert!(inserted != BTree::from_sorted(1..1001));
     }
 
     #[test]
@@ -435,4 +2899,28 @@ mod tests {
         }
     }
 
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct UserId(u64);
+
+    impl_key!(UserId(u64));
+
+    #[test]
+    fn impl_key_on_newtype() {
+        let mut tree = BTree::new();
+        for id in (0..500_u64).rev() {
+            assert!(tree.insert(UserId(id * 3)));
+        }
+        assert!(!tree.insert(UserId(0)));
+
+        assert_eq!(tree.len(), 500);
+        assert!(tree.find(&UserId(999)) && !tree.find(&UserId(1000)));
+        assert_eq!(tree.iter().next(), Some(&UserId(0)));
+        assert_eq!(tree.check_invariants(), Ok(()));
+
+        assert_eq!(<UserId as ByteKey>::WIDTH, 8);
+        let bytes = ByteKey::to_be_bytes(&UserId(0x0102));
+        assert_eq!(bytes, [0, 0, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(UserId::from_be_slice(&bytes), UserId(0x0102));
+    }
+
 }
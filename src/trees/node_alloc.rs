@@ -0,0 +1,189 @@
+#[cfg(feature = "allocator")]
+use std::alloc::Allocator;
+use std::boxed::Box;
+use std::sync::Arc;
+use std::vec::Vec;
+
+#[cfg(feature = "allocator")]
+pub use std::alloc::Global;
+
+/// Where a tree's nodes get their memory: every node, and the vectors of keys and children
+/// inside it, is allocated from the tree's `NodeAlloc`. The default `Global` is the ordinary
+/// heap.
+///
+/// With the nightly-only `allocator` feature this is any `Allocator` which can be cloned (each
+/// node's vectors keep a copy), so trees can draw node memory from a pool or an arena of the
+/// caller's choosing; see `BTree::new_in`. Without it `Global` is the only implementation.
+#[cfg(feature = "allocator")]
+pub trait NodeAlloc: Allocator + Clone {}
+
+#[cfg(feature = "allocator")]
+impl<A: Allocator + Clone> NodeAlloc for A {}
+
+/// Where a tree's nodes get their memory. Other allocators need the nightly-only `allocator`
+/// feature, which turns this into a blanket trait over `Allocator + Clone`; until then `Global`,
+/// the ordinary heap, is the only implementation.
+#[cfg(not(feature = "allocator"))]
+pub trait NodeAlloc: Clone + sealed::Sealed {}
+
+/// The ordinary heap.
+#[cfg(not(feature = "allocator"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Global;
+
+#[cfg(not(feature = "allocator"))]
+impl NodeAlloc for Global {}
+
+#[cfg(not(feature = "allocator"))]
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::Global {}
+}
+
+// Lets the stable storage types below name the allocator they have no use for, since a type
+// alias has to mention every one of its parameters.
+#[cfg(not(feature = "allocator"))]
+pub(super) trait Ignoring<A> {
+    type Same;
+}
+
+#[cfg(not(feature = "allocator"))]
+impl<T, A> Ignoring<A> for T {
+    type Same = T;
+}
+
+#[cfg(feature = "allocator")]
+pub(super) type NodeVec<T, A> = Vec<T, A>;
+#[cfg(not(feature = "allocator"))]
+pub(super) type NodeVec<T, A> = Vec<<T as Ignoring<A>>::Same>;
+
+#[cfg(feature = "allocator")]
+pub(super) type NodeBox<T, A> = Box<T, A>;
+#[cfg(not(feature = "allocator"))]
+pub(super) type NodeBox<T, A> = Box<<T as Ignoring<A>>::Same>;
+
+#[cfg(feature = "allocator")]
+pub(super) type NodeArc<T, A> = Arc<T, A>;
+#[cfg(not(feature = "allocator"))]
+pub(super) type NodeArc<T, A> = Arc<<T as Ignoring<A>>::Same>;
+
+#[cfg(feature = "allocator")]
+pub(super) fn vec_in<T, A: NodeAlloc>(capacity: usize, alloc: &A) -> NodeVec<T, A> {
+    Vec::with_capacity_in(capacity, alloc.clone())
+}
+
+#[cfg(not(feature = "allocator"))]
+pub(super) fn vec_in<T, A: NodeAlloc>(capacity: usize, _alloc: &A) -> Vec<T> {
+    Vec::with_capacity(capacity)
+}
+
+#[cfg(feature = "allocator")]
+pub(super) fn box_in<T, A: NodeAlloc>(value: T, alloc: &A) -> NodeBox<T, A> {
+    Box::new_in(value, alloc.clone())
+}
+
+#[cfg(not(feature = "allocator"))]
+pub(super) fn box_in<T, A: NodeAlloc>(value: T, _alloc: &A) -> Box<T> {
+    Box::new(value)
+}
+
+#[cfg(feature = "allocator")]
+pub(super) fn arc_in<T, A: NodeAlloc>(value: T, alloc: &A) -> NodeArc<T, A> {
+    Arc::new_in(value, alloc.clone())
+}
+
+#[cfg(not(feature = "allocator"))]
+pub(super) fn arc_in<T, A: NodeAlloc>(value: T, _alloc: &A) -> Arc<T> {
+    Arc::new(value)
+}
+
+#[cfg(all(test, feature = "allocator"))]
+mod tests {
+    use std::alloc::{AllocError, Allocator, Global, Layout};
+    use std::cell::Cell;
+    use std::ptr::NonNull;
+    use std::rc::Rc;
+
+    use super::super::BTree;
+
+    // hands out memory from the global heap, keeping count of what is still outstanding
+    #[derive(Clone, Default)]
+    struct Counting {
+        live: Rc<Cell<usize>>,
+        total: Rc<Cell<usize>>,
+    }
+
+    unsafe impl Allocator for Counting {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.live.set(self.live.get() + 1);
+            self.total.set(self.total.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.live.set(self.live.get() - 1);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn nodes_come_from_the_tree_allocator() {
+        let alloc = Counting::default();
+        let mut tree = BTree::new_in(alloc.clone());
+        assert_eq!(alloc.total.get(), 0);
+
+        for key in (0..10_000_u64).map(|i| (i * 7919) % 10_000) {
+            tree.insert(key);
+        }
+        assert!(tree.height() >= 3);
+        // every node past the root is its own allocation, plus the vectors inside each node
+        let after_inserts = alloc.live.get();
+        assert!(after_inserts > 10_000 / 30, "{} live", after_inserts);
+
+        for key in (0..10_000_u64).filter(|key| key % 3 == 0) {
+            assert!(tree.remove(&key));
+        }
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert!(alloc.live.get() < after_inserts);
+        assert!(tree.find(&1) && !tree.find(&3));
+        assert_eq!(tree.iter().count(), 6666);
+
+        tree.compact();
+        assert_eq!(tree.len(), 6666);
+
+        drop(tree);
+        assert_eq!(alloc.live.get(), 0);
+        assert!(alloc.total.get() > after_inserts);
+    }
+
+    #[test]
+    fn reads_and_rebuilds_work_in_any_allocator() {
+        let alloc = Counting::default();
+        let mut tree = BTree::new_in(alloc.clone());
+        // an empty tree takes a batch as a dense rebuild, like `from_sorted` but in `alloc`
+        tree.insert_sorted_batch(&(0..5000_u64).map(|i| 2 * i).collect::<Vec<_>>());
+        let plain = BTree::from_sorted((0..5000_u64).map(|i| 2 * i));
+        assert!(tree.fill_efficiency() > 0.95);
+        assert!(alloc.live.get() > 0);
+
+        assert!(tree.range(101..=201).eq(plain.range(101..=201)));
+        assert_eq!(tree.rank(&1001), 501);
+        assert_eq!(tree[17], 34);
+        assert_eq!((tree.first(), tree.last()), (Some(&0), Some(&9998)));
+        assert_eq!(tree.cursor_at(&51).peek(), Some(&52));
+        assert_eq!(tree.stats().len, 5000);
+        assert_eq!(tree.nodes().count(), tree.node_count());
+        assert!(tree.leaves().flatten().eq(plain.iter()));
+        assert!(tree.contains_all(&[2, 4, 6]) && !tree.contains_any(&[1, 3]));
+        assert_eq!(tree, tree.clone());
+        assert_eq!(format!("{:?}", tree), format!("{:?}", plain));
+
+        // the partitions are carved out of the same allocator
+        let parts = tree.partition_by(&[5000]);
+        assert_eq!((parts[0].len(), parts[1].len()), (2500, 2500));
+        assert!(alloc.live.get() > 0);
+        drop(parts);
+        assert_eq!(alloc.live.get(), 0);
+    }
+}
@@ -0,0 +1,218 @@
+use std::slice;
+use std::vec::Vec;
+
+use super::{BTree, Global, InternalNode, Key, Node, NodeAlloc};
+
+/// A read-only view of one node of a `BTree`, for tools which want to look at its shape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeInfo<'a, T: 'a> {
+    /// Distance from the root, which is at depth 0.
+    pub depth: usize,
+    pub is_leaf: bool,
+    /// The keys held in this node itself; for an internal node, the separators between its
+    /// children.
+    pub keys: &'a [T],
+}
+
+/// Pre-order walk over the nodes of a `BTree`: each node comes before its children, and
+/// children are visited left to right.
+pub struct NodeIter<'a, T: 'a + Key, A: 'a + NodeAlloc = Global> {
+    stack: Vec<(&'a Node<T, A>, usize)>,
+}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    pub fn nodes(&self) -> NodeIter<'_, T, A> {
+        NodeIter {
+            stack: vec![(&self.root, 0)],
+        }
+    }
+
+    /// The keys held in internal nodes, in ascending order. They partition the key space along
+    /// the tree's own routing, which makes them natural split points for dividing work on the
+    /// keys between threads. Empty for a tree which is a single leaf.
+    pub fn separators(&self) -> Vec<&T> {
+        let mut separators = Vec::new();
+        push_separators(&self.root, &mut separators);
+        separators
+    }
+
+    /// The keys as sorted runs, left to right, for scans which want to work a leaf at a time:
+    /// each non-empty leaf's keys come as one slice, and each separator key of an internal node
+    /// as a one-key slice between the leaves on either side of it (this is a B-tree, not a B+
+    /// tree, so those keys live nowhere else). Joining the slices gives exactly the contents in
+    /// order.
+    pub fn leaves(&self) -> impl Iterator<Item = &[T]> {
+        // internal nodes on the path to the current leaf, each with the next separator to yield
+        let mut stack: Vec<(&InternalNode<T, A>, usize)> = Vec::new();
+        let mut next_subtree = Some(&self.root);
+
+        ::std::iter::from_fn(move || loop {
+            if let Some(mut node) = next_subtree.take() {
+                while let Node::Internal(ref internal) = *node {
+                    stack.push((internal, 0));
+                    node = &internal.children[0];
+                }
+                if let Node::Leaf(ref leaf) = *node {
+                    if !leaf.keys.is_empty() {
+                        return Some(&leaf.keys[..]);
+                    }
+                }
+            }
+
+            let &mut (internal, ref mut index) = stack.last_mut()?;
+            if *index < internal.num_keys {
+                *index += 1;
+                next_subtree = Some(&internal.children[*index]);
+                return Some(slice::from_ref(&internal.keys[*index - 1]));
+            }
+            stack.pop();
+        })
+    }
+}
+
+impl<'a, T: 'a + Key, A: 'a + NodeAlloc> Iterator for NodeIter<'a, T, A> {
+    type Item = NodeInfo<'a, T>;
+
+    fn next(&mut self) -> Option<NodeInfo<'a, T>> {
+        let (node, depth) = self.stack.pop()?;
+
+        if let Some(children) = node.children() {
+            // pushed right to left so the leftmost child is visited first
+            self.stack
+                .extend(children.iter().rev().map(|child| (&**child, depth + 1)));
+        }
+
+        Some(NodeInfo {
+            depth,
+            is_leaf: node.children().is_none(),
+            keys: node.keys(),
+        })
+    }
+}
+
+// in-order walk which skips the leaves
+fn push_separators<'a, T: Key, A: NodeAlloc>(node: &'a Node<T, A>, out: &mut Vec<&'a T>) {
+    if let Node::Internal(ref internal) = *node {
+        for (child, key) in internal.children.iter().zip(internal.keys.iter()) {
+            push_separators(child, out);
+            out.push(key);
+        }
+        push_separators(&internal.children[internal.num_keys], out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::BTree;
+
+    #[test]
+    fn root_first_then_children() {
+        let mut tree = BTree::new();
+        for i in 0..31_u32 {
+            tree.insert(i);
+        }
+
+        let nodes = tree.nodes().collect::<Vec<_>>();
+        assert_eq!(nodes.len(), 3);
+
+        assert_eq!(nodes[0].depth, 0);
+        assert!(!nodes[0].is_leaf);
+        assert_eq!(nodes[0].keys, &[15]);
+
+        assert_eq!((nodes[1].depth, nodes[1].is_leaf), (1, true));
+        assert_eq!(nodes[1].keys, &(0..15).collect::<Vec<_>>()[..]);
+        assert_eq!(nodes[2].keys, &(16..31).collect::<Vec<_>>()[..]);
+    }
+
+    #[test]
+    fn pre_order_covers_every_key() {
+        let tree = BTree::from_sorted(0..20_000_u64);
+        let height = tree.height();
+
+        let mut prev_depth = 0;
+        let mut all_keys = Vec::new();
+        for info in tree.nodes() {
+            // pre-order never jumps more than one level down at a time
+            assert!(info.depth <= prev_depth + 1);
+            assert_eq!(info.is_leaf, info.depth == height - 1);
+            prev_depth = info.depth;
+            all_keys.extend_from_slice(info.keys);
+        }
+
+        all_keys.sort();
+        assert_eq!(all_keys, (0..20_000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn empty_tree_is_one_leaf() {
+        let tree = BTree::<u32>::new();
+        let nodes = tree.nodes().collect::<Vec<_>>();
+
+        assert_eq!(nodes.len(), 1);
+        assert!(nodes[0].is_leaf);
+        assert!(nodes[0].keys.is_empty());
+    }
+
+    #[test]
+    fn separators_bound_the_leaves() {
+        let mut tree = BTree::new();
+        for i in 0..20_000_u64 {
+            tree.insert((i * 7919) % 20_011);
+        }
+        assert!(tree.height() >= 3);
+
+        let separators = tree.separators();
+        assert!(separators.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(separators.iter().all(|key| tree.find(key)));
+        assert_eq!(
+            separators.len(),
+            tree.nodes()
+                .filter(|info| !info.is_leaf)
+                .map(|info| info.keys.len())
+                .sum::<usize>()
+        );
+
+        // the leaf runs sit strictly between consecutive separators
+        let mut separators = separators.into_iter().peekable();
+        let mut previous = None;
+        for run in tree.leaves() {
+            if run.len() == 1 && separators.peek() == Some(&&run[0]) {
+                previous = separators.next();
+                continue;
+            }
+            if let Some(low) = previous {
+                assert!(*low < run[0]);
+            }
+            if let Some(high) = separators.peek() {
+                assert!(run[run.len() - 1] < **high);
+            }
+        }
+        assert_eq!(separators.next(), None);
+
+        assert!(BTree::from_sorted(0..10_u32).separators().is_empty());
+    }
+
+    #[test]
+    fn leaves_join_to_sorted_contents() {
+        let mut tree = BTree::new();
+        for i in 0..20_000_u64 {
+            tree.insert((i * 7919) % 20_011);
+        }
+
+        let joined = tree.leaves().flatten().copied().collect::<Vec<_>>();
+        assert_eq!(joined, tree.to_sorted_vec());
+
+        // most of the keys come in leaf-sized runs
+        let long_runs = tree.leaves().filter(|run| run.len() > 1).count();
+        assert!(long_runs > 0);
+        assert!(tree
+            .leaves()
+            .all(|run| run.len() <= tree.fanout().leaf_max_keys()));
+
+        assert_eq!(BTree::<u32>::new().leaves().count(), 0);
+        assert_eq!(
+            BTree::from_sorted(0..5_u32).leaves().collect::<Vec<_>>(),
+            vec![&[0, 1, 2, 3, 4][..]]
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use std::string::String;
+use std::string::ToString;
+
+use super::{BTree, Key, NodeAlloc};
+
+/// One step of a recorded sequence of tree operations, for fuzzers and property tests to drive
+/// a tree through `apply_op` or `replay` and for failing sequences to be written down exactly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op<T> {
+    Insert(T),
+    Remove(T),
+    Find(T),
+    /// Removes the key at this position in ascending order, if there is one.
+    RemoveNth(usize),
+    Compact,
+}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Performs `op`, discarding its result. In debug builds the whole tree is then checked with
+    /// `check_invariants`, panicking with the op and the violation if it fails; that makes each
+    /// op cost O(n), which is the point when hunting for the step which broke something.
+    ///
+    /// In lazy removal mode the check is `check_lazy_invariants` instead, since removals are
+    /// allowed to leave nodes underfull or empty there. Nodes emptied that way stay so after
+    /// lazy mode is switched off, so `compact` such a tree before applying more ops to it.
+    pub fn apply_op(&mut self, op: Op<T>) {
+        let checked = if cfg!(debug_assertions) {
+            Some(describe(&op))
+        } else {
+            None
+        };
+
+        match op {
+            Op::Insert(key) => {
+                self.insert(key);
+            }
+            Op::Remove(key) => {
+                self.remove(&key);
+            }
+            Op::Find(key) => {
+                self.find(&key);
+            }
+            Op::RemoveNth(index) => {
+                self.remove_nth(index);
+            }
+            Op::Compact => self.compact(),
+        }
+
+        if let Some(op) = checked {
+            let result = if self.lazy_removal() {
+                self.check_lazy_invariants()
+            } else {
+                self.check_invariants()
+            };
+            if let Err(violation) = result {
+                panic!("tree is broken after {}: {}", op, violation);
+            }
+        }
+    }
+}
+
+impl<T: Key + Clone> BTree<T> {
+    /// A fresh default tree with every op of `ops` applied in turn.
+    pub fn replay(ops: &[Op<T>]) -> BTree<T> {
+        let mut tree = BTree::new();
+        for op in ops {
+            tree.apply_op(op.clone());
+        }
+        tree
+    }
+}
+
+// names the op for the panic message without asking for `T: Debug`
+fn describe<T>(op: &Op<T>) -> String {
+    match *op {
+        Op::Insert(_) => "an insert".to_string(),
+        Op::Remove(_) => "a remove".to_string(),
+        Op::Find(_) => "a find".to_string(),
+        Op::RemoveNth(index) => format!("remove_nth({})", index),
+        Op::Compact => "compact".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BTree, Fanout};
+    use super::Op;
+
+    #[test]
+    fn replay_through_root_split_and_collapse() {
+        // 31 keys overflow the default 30-key root leaf into two minimally full halves, so the
+        // first removal underflows one with nothing for its sibling to lend, and the two merge
+        // back into a single root leaf under the old separator
+        let mut ops = (0..31_u32).map(Op::Insert).collect::<Vec<_>>();
+        ops.extend(vec![
+            Op::Find(15),
+            Op::Remove(30),
+            Op::Remove(29),
+            Op::Remove(28),
+            Op::Insert(28),
+            Op::RemoveNth(0),
+            Op::Remove(16),
+            Op::Remove(17),
+            Op::Remove(18),
+            Op::Remove(19),
+            Op::Remove(1000),
+            Op::RemoveNth(1000),
+        ]);
+
+        let split = BTree::replay(&ops[..31]);
+        assert_eq!(split.height(), 2);
+        let merged = BTree::replay(&ops[..33]);
+        assert_eq!(merged.height(), 1);
+        assert_eq!(merged.len(), 30);
+
+        let tree = BTree::replay(&ops);
+        assert_eq!(tree.height(), 1);
+        let expected = (1..16).chain(20..29).collect::<Vec<_>>();
+        assert_eq!(tree.to_sorted_vec(), expected);
+    }
+
+    #[test]
+    fn apply_op_on_narrow_tree() {
+        let mut tree = BTree::with_fanout(Fanout::new(4, 4));
+        let ops = vec![
+            Op::Insert(5_u64),
+            Op::Insert(1),
+            Op::Insert(9),
+            Op::Insert(3),
+            Op::Insert(7),
+            Op::Insert(2),
+            Op::Insert(8),
+            Op::Remove(5),
+            Op::Remove(1),
+            Op::Remove(9),
+            Op::Compact,
+            Op::Insert(4),
+        ];
+        for op in ops {
+            tree.apply_op(op);
+        }
+        assert_eq!(tree.to_sorted_vec(), vec![2, 3, 4, 7, 8]);
+    }
+
+    #[test]
+    fn apply_op_allows_lazy_removal_holes() {
+        let mut tree = BTree::with_fanout(Fanout::new(4, 4));
+        for key in 0..200_u64 {
+            tree.apply_op(Op::Insert(key));
+        }
+        tree.set_lazy_removal(true);
+
+        // emptying whole leaves, and the internal keys between them, leaves nothing to merge with
+        for key in (0..200).filter(|key| key % 50 != 0) {
+            tree.apply_op(Op::Remove(key));
+        }
+        tree.apply_op(Op::RemoveNth(1));
+        assert!(tree.check_invariants().is_err());
+        assert_eq!(tree.check_lazy_invariants(), Ok(()));
+        assert_eq!(tree.to_sorted_vec(), vec![0, 100, 150]);
+
+        tree.apply_op(Op::Compact);
+        tree.set_lazy_removal(false);
+        tree.apply_op(Op::Insert(7));
+        assert_eq!(tree.to_sorted_vec(), vec![0, 7, 100, 150]);
+    }
+}
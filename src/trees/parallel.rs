@@ -0,0 +1,72 @@
+use std::vec::Vec;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use super::{BTree, Key};
+
+#[cfg(feature = "rayon")]
+impl<T: Key + Send + Sync> BTree<T> {
+    /// Looks up every key in `keys`, returning the results in the same order as the input.
+    /// Lookups are spread across the rayon thread pool.
+    pub fn find_many(&self, keys: &[T]) -> Vec<bool> {
+        keys.par_iter().map(|key| self.find(key)).collect()
+    }
+}
+
+/// Collects a parallel iterator into a tree: the keys are gathered and sorted on the rayon pool,
+/// then packed with `from_sorted_dedup`. Duplicates are collapsed. The sort is stable and the
+/// gathering keeps the iterator's order, so when equal keys differ the one kept is always the
+/// first in that order, however the threads were scheduled.
+#[cfg(feature = "rayon")]
+impl<T: Key + Send> FromParallelIterator<T> for BTree<T> {
+    fn from_par_iter<I: IntoParallelIterator<Item = T>>(par_iter: I) -> BTree<T> {
+        let mut keys = Vec::from_par_iter(par_iter);
+        keys.par_sort();
+        BTree::from_sorted_dedup(keys)
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+impl<T: Key> BTree<T> {
+    /// Looks up every key in `keys`, returning the results in the same order as the input.
+    /// Enable the `rayon` feature to spread the lookups across threads.
+    pub fn find_many(&self, keys: &[T]) -> Vec<bool> {
+        keys.iter().map(|key| self.find(key)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::BTree;
+
+    #[test]
+    fn find_many_matches_find() {
+        let mut tree = BTree::<u64>::new();
+        for i in 0..10_000 {
+            tree.insert(i * 3);
+        }
+
+        let probes = (0..50_000)
+            .map(|i| (i * 7919) % 40_000)
+            .collect::<Vec<u64>>();
+        let expected = probes.iter().map(|k| tree.find(k)).collect::<Vec<_>>();
+
+        assert_eq!(tree.find_many(&probes), expected);
+        assert!(tree.find_many(&[]).is_empty());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn collect_from_parallel_iterator() {
+        use rayon::prelude::*;
+
+        let shuffled = (0..100_000_u64)
+            .map(|i| (i * 7919) % 100_000)
+            .collect::<Vec<_>>();
+        let tree = shuffled.par_iter().map(|key| key / 2).collect::<BTree<_>>();
+
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree, BTree::from_sorted(0..50_000));
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::RangeBounds;
+use std::path::Path;
+
+use super::{BTree, ByteKey};
+
+// Layout: a fixed header followed by the payload.
+//
+//   magic        4 bytes, `MAGIC`
+//   version      1 byte, `FORMAT_VERSION`
+//   key width    1 byte, `ByteKey::WIDTH` of the keys that were written
+//   key count    8 bytes, big-endian
+//   checksum     4 bytes, big-endian CRC-32 of the payload
+//
+// The payload is every key in ascending order using its fixed-width `ByteKey` encoding.
+const MAGIC: [u8; 4] = *b"CTDB";
+const FORMAT_VERSION: u8 = 2;
+const HEADER_LEN: usize = 18;
+
+impl<T: ByteKey> BTree<T> {
+    /// Writes every key to `path`, replacing whatever was there.
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write_keys(self.iter(), self.size(), &mut out)?;
+        out.flush()
+    }
+
+    /// Writes just the keys within `range` to `w`, in the same format as `save_to`, so that
+    /// `load_from` reads them back as a tree of their own. Handy for shipping part of a tree.
+    pub fn save_range<R: RangeBounds<T>, W: Write>(&self, range: R, w: &mut W) -> io::Result<()> {
+        let keys = self.range(range);
+        let count = keys.len();
+        write_keys(keys, count, w)
+    }
+
+    /// Reads a tree previously written with `save_to`, checking the header and checksum so that
+    /// a damaged file is reported as an error instead of producing a garbage tree.
+    pub fn load_from(path: &Path) -> io::Result<BTree<T>> {
+        let mut input = BufReader::new(File::open(path)?);
+
+        let mut header = [0; HEADER_LEN];
+        input
+            .read_exact(&mut header)
+            .map_err(|_| corrupt("file is too short to hold a header".to_string()))?;
+
+        if header[0..4] != MAGIC {
+            return Err(corrupt("not a catdb file (bad magic number)".to_string()));
+        }
+        if header[4] != FORMAT_VERSION {
+            return Err(corrupt(format!(
+                "unsupported file format version {} (expected {})",
+                header[4], FORMAT_VERSION
+            )));
+        }
+        if header[5] as usize != T::WIDTH {
+            return Err(corrupt(format!(
+                "file holds {}-byte keys but this tree uses {}-byte keys",
+                header[5],
+                T::WIDTH
+            )));
+        }
+
+        let count = u64::from_be_bytes(copy_array(&header[6..14]));
+        let checksum = u32::from_be_bytes(copy_array(&header[14..18]));
+
+        let mut payload = Vec::new();
+        input.read_to_end(&mut payload)?;
+
+        if (payload.len() as u64) != count.saturating_mul(T::WIDTH as u64) {
+            return Err(corrupt(format!(
+                "header promises {} keys ({} bytes) but the payload has {} bytes",
+                count,
+                count.saturating_mul(T::WIDTH as u64),
+                payload.len()
+            )));
+        }
+
+        let actual = crc32(&payload);
+        if actual != checksum {
+            return Err(corrupt(format!(
+                "checksum mismatch: header says {:08x} but payload hashes to {:08x}",
+                checksum, actual
+            )));
+        }
+
+        Ok(BTree::from_sorted(
+            payload.chunks(T::WIDTH).map(T::from_be_slice),
+        ))
+    }
+}
+
+// writes a header and `count` keys, which must be in ascending order
+fn write_keys<'a, T: 'a + ByteKey, I: Iterator<Item = &'a T>, W: Write>(
+    keys: I,
+    count: usize,
+    w: &mut W,
+) -> io::Result<()> {
+    let mut payload = Vec::with_capacity(count * T::WIDTH);
+    for key in keys {
+        payload.extend_from_slice(key.to_be_bytes().as_ref());
+    }
+
+    w.write_all(&header(count, T::WIDTH, crc32(&payload)))?;
+    w.write_all(&payload)
+}
+
+fn header(count: usize, width: usize, checksum: u32) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[0..4].copy_from_slice(&MAGIC);
+    header[4] = FORMAT_VERSION;
+    header[5] = width as u8;
+    header[6..14].copy_from_slice(&(count as u64).to_be_bytes());
+    header[14..18].copy_from_slice(&checksum.to_be_bytes());
+    header
+}
+
+pub(super) fn corrupt(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(super) fn copy_array<A: Default + AsMut<[u8]>>(bytes: &[u8]) -> A {
+    let mut array = A::default();
+    array.as_mut().copy_from_slice(bytes);
+    array
+}
+
+// CRC-32 (IEEE 802.3, reflected polynomial 0xEDB88320), computed bit by bit
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+
+    use super::super::fixtures::temp_path;
+    use super::super::BTree;
+    use super::{crc32, HEADER_LEN};
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_path("round-trip");
+
+        let mut tree = BTree::<u64>::new();
+        for i in 0..100_000_u64 {
+            tree.insert((i * 7919) % 1_000_003);
+        }
+
+        tree.save_to(&path).unwrap();
+        let loaded = BTree::<u64>::load_from(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.size(), 100_000);
+        assert_eq!(loaded, tree);
+    }
+
+    #[test]
+    fn save_range_loads_as_its_own_tree() {
+        let path = temp_path("range");
+        let tree = BTree::<u32>::from_sorted(0..20_000);
+
+        let mut bytes = Vec::new();
+        tree.save_range(5000..6000, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), HEADER_LEN + 1000 * 4);
+        fs::write(&path, &bytes).unwrap();
+        let loaded = BTree::<u32>::load_from(&path).unwrap();
+
+        assert_eq!(loaded, BTree::from_sorted(5000..6000));
+
+        let mut bytes = Vec::new();
+        tree.save_range(30_000.., &mut bytes).unwrap();
+        fs::write(&path, &bytes).unwrap();
+        let loaded = BTree::<u32>::load_from(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn save_and_load_signed_and_empty() {
+        let path = temp_path("signed");
+
+        let tree = BTree::<i32>::from_sorted(-500..500);
+        tree.save_to(&path).unwrap();
+        assert_eq!(BTree::<i32>::load_from(&path).unwrap(), tree);
+
+        let empty = BTree::<i32>::new();
+        empty.save_to(&path).unwrap();
+        assert_eq!(BTree::<i32>::load_from(&path).unwrap(), empty);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    // saves a small tree, lets `damage` mangle the bytes on disk, and returns the load error
+    fn load_damaged<F: FnOnce(&mut Vec<u8>)>(name: &str, damage: F) -> io::Error {
+        let path = temp_path(name);
+
+        BTree::<u32>::from_sorted(0..1000).save_to(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        damage(&mut bytes);
+        fs::write(&path, &bytes).unwrap();
+
+        let result = BTree::<u32>::load_from(&path);
+        fs::remove_file(&path).unwrap();
+
+        result.unwrap_err()
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn load_rejects_flipped_payload_byte() {
+        let err = load_damaged("flipped", |bytes| bytes[HEADER_LEN + 1234] ^= 0x10);
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("checksum"));
+    }
+
+    #[test]
+    fn load_rejects_bad_header_fields() {
+        let err = load_damaged("magic", |bytes| bytes[0] = b'X');
+        assert!(err.to_string().contains("magic"));
+
+        let err = load_damaged("version", |bytes| bytes[4] = 99);
+        assert!(err.to_string().contains("version"));
+
+        let err = load_damaged("width", |bytes| bytes[5] = 8);
+        assert!(err.to_string().contains("4-byte keys"));
+
+        let err = load_damaged("count", |bytes| bytes[13] ^= 1);
+        assert!(err.to_string().contains("promises"));
+    }
+
+    #[test]
+    fn load_rejects_truncated_file() {
+        let err = load_damaged("truncated", |bytes| {
+            let len = bytes.len();
+            bytes.truncate(len - 3);
+        });
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        for &len in [0, 3, HEADER_LEN - 1].iter() {
+            let err = load_damaged("short", |bytes| bytes.truncate(len));
+            assert!(err.to_string().contains("too short"));
+        }
+    }
+
+    #[test]
+    fn load_rejects_wrong_key_type() {
+        let path = temp_path("wrong-type");
+
+        BTree::<u64>::from_sorted(0..10).save_to(&path).unwrap();
+        let err = BTree::<u32>::load_from(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert!(err.to_string().contains("8-byte keys"));
+    }
+}
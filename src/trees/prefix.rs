@@ -0,0 +1,109 @@
+use std::ops::Bound;
+use std::vec::Vec;
+
+use super::BTree;
+
+impl BTree<Vec<u8>> {
+    /// Iterates in order over the keys which start with `prefix`. This is the range from
+    /// `prefix` itself up to the first byte string past every extension of it; an empty prefix
+    /// matches every key.
+    pub fn prefix_scan<'a>(&'a self, prefix: &[u8]) -> impl Iterator<Item = &'a Vec<u8>> {
+        let end = match prefix_upper_bound(prefix) {
+            Some(upper) => Bound::Excluded(upper),
+            None => Bound::Unbounded,
+        };
+        self.range((Bound::Included(prefix.to_vec()), end))
+    }
+}
+
+// The smallest byte string greater than everything starting with `prefix`: drop any trailing
+// 0xFF bytes and bump the last one left. None if there is nothing left, when every key at or
+// after the prefix matches.
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let keep = prefix.iter().rposition(|&byte| byte != 0xFF)?;
+    let mut upper = prefix[..=keep].to_vec();
+    upper[keep] += 1;
+    Some(upper)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::BTree;
+    use super::prefix_upper_bound;
+
+    fn keys() -> BTree<Vec<u8>> {
+        let mut tree = BTree::new();
+        let words: &[&[u8]] = &[
+            b"",
+            b"a",
+            b"app",
+            b"apple",
+            b"applesauce",
+            b"apply",
+            b"apq",
+            b"b",
+            b"ba",
+            b"\xff",
+            b"\xff\xff",
+            b"\xff\xff\x00",
+            b"a\xff",
+            b"a\xff\x01",
+            b"b\x00",
+        ];
+        for word in words {
+            tree.insert(word.to_vec());
+        }
+        tree
+    }
+
+    fn scan(tree: &BTree<Vec<u8>>, prefix: &[u8]) -> Vec<Vec<u8>> {
+        tree.prefix_scan(prefix).cloned().collect()
+    }
+
+    fn brute_force(tree: &BTree<Vec<u8>>, prefix: &[u8]) -> Vec<Vec<u8>> {
+        tree.iter()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn shared_prefixes() {
+        let tree = keys();
+        assert_eq!(
+            scan(&tree, b"app"),
+            vec![
+                b"app".to_vec(),
+                b"apple".to_vec(),
+                b"applesauce".to_vec(),
+                b"apply".to_vec()
+            ]
+        );
+        assert_eq!(scan(&tree, b"apple"), brute_force(&tree, b"apple"));
+        assert_eq!(scan(&tree, b"b"), brute_force(&tree, b"b"));
+        assert_eq!(scan(&tree, b"c"), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn empty_prefix_is_everything() {
+        let tree = keys();
+        assert_eq!(scan(&tree, b"").len(), tree.len());
+    }
+
+    #[test]
+    fn trailing_ff_bytes() {
+        let tree = keys();
+        for prefix in [&b"\xff"[..], b"\xff\xff", b"a\xff", b"\xff\xff\xff"].iter() {
+            assert_eq!(scan(&tree, prefix), brute_force(&tree, prefix));
+        }
+        assert_eq!(scan(&tree, b"\xff").len(), 3);
+    }
+
+    #[test]
+    fn upper_bounds() {
+        assert_eq!(prefix_upper_bound(b"abc"), Some(b"abd".to_vec()));
+        assert_eq!(prefix_upper_bound(b"a\xff\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_upper_bound(b"\xff\xff"), None);
+        assert_eq!(prefix_upper_bound(b""), None);
+    }
+}
@@ -0,0 +1,109 @@
+// Randomized checks of the structural guarantees: after every step of many seeded random
+// insert / remove sequences the tree must pass `check_invariants`, agree with a `BTreeSet`
+// holding the same keys, and stay within the B-tree height bound.
+
+use std::collections::BTreeSet;
+
+use super::{BTree, Fanout};
+
+// xorshift64*, so that failures reproduce from the printed seed
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+// Every node but the root holds at least `min_keys` keys, so a tree of `len` keys needs no more
+// than ceil(log_min(len)) levels below the root.
+fn height_bound(len: usize, min_keys: usize) -> usize {
+    let mut levels = 0;
+    let mut reach = 1;
+    while reach < len {
+        reach = reach.saturating_mul(min_keys);
+        levels += 1;
+    }
+    levels + 1
+}
+
+fn run_sequence(seed: u64, fanout: Fanout, steps: usize, key_space: u64) {
+    let min_keys = fanout.leaf_max_keys().min(fanout.internal_max_keys()) / 2;
+    let mut rng = Rng::new(seed);
+    let mut tree = BTree::with_fanout(fanout);
+    let mut model = BTreeSet::new();
+
+    for step in 0..steps {
+        let key = rng.below(key_space);
+        // inserts outnumber removes so the trees actually grow
+        if rng.below(5) < 3 {
+            assert_eq!(tree.insert(key), model.insert(key), "seed {}", seed);
+        } else {
+            assert_eq!(tree.remove(&key), model.remove(&key), "seed {}", seed);
+        }
+
+        assert_eq!(tree.len(), model.len(), "seed {} step {}", seed, step);
+        assert!(
+            tree.height() <= height_bound(tree.len(), min_keys),
+            "seed {} step {}: height {} for {} keys",
+            seed,
+            step,
+            tree.height(),
+            tree.len()
+        );
+        if step % 8 == 0 {
+            assert_eq!(
+                tree.check_invariants(),
+                Ok(()),
+                "seed {} step {}",
+                seed,
+                step
+            );
+        }
+    }
+
+    assert_eq!(tree.check_invariants(), Ok(()), "seed {}", seed);
+    assert!(tree.iter().eq(model.iter()), "seed {}", seed);
+}
+
+#[test]
+fn random_sequences_keep_invariants_with_narrow_nodes() {
+    for seed in 0..2000 {
+        run_sequence(seed, Fanout::new(4, 5), 150, 200);
+    }
+}
+
+#[test]
+fn random_sequences_keep_invariants_with_default_nodes() {
+    for seed in 0..200 {
+        run_sequence(seed, Fanout::default(), 1500, 3000);
+    }
+}
+
+#[test]
+fn long_random_sequences_keep_invariants() {
+    for seed in 0..4 {
+        run_sequence(seed, Fanout::new(6, 7), 30_000, 20_000);
+    }
+}
+
+#[test]
+fn height_bound_matches_hand_computed_values() {
+    assert_eq!(height_bound(0, 15), 1);
+    assert_eq!(height_bound(1, 15), 1);
+    assert_eq!(height_bound(15, 15), 2);
+    assert_eq!(height_bound(16, 15), 3);
+    assert_eq!(height_bound(225, 15), 3);
+    assert_eq!(height_bound(226, 15), 4);
+}
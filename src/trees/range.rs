@@ -0,0 +1,287 @@
+use std::ops::{Bound, RangeBounds};
+
+use super::{BTree, Cursor, Global, Key, Node, NodeAlloc};
+
+/// Iterator over the keys of a `BTree` within a range, from either end.
+///
+/// A cursor sits at each end of the window and the two step towards each other; the number of
+/// keys between them is worked out up front from the subtree counts, so the iterator knows when
+/// they meet without comparing keys on every step.
+pub struct Range<'a, T: 'a + Key, A: 'a + NodeAlloc = Global> {
+    front: Cursor<'a, T, A>,
+    back: Cursor<'a, T, A>,
+    remaining: usize,
+}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Iterates over the keys within `range`, in ascending order, or descending via `rev`. Both
+    /// ends are found by a single descent each, which also counts the keys before that end. A
+    /// range whose start lies past its end is empty.
+    pub fn range<R: RangeBounds<T>>(&self, range: R) -> Range<'_, T, A> {
+        let mut visited = 0;
+        self.range_counting(&range, &mut visited)
+    }
+
+    // `range`, adding the number of nodes the searches for its two ends looked at to `visited`
+    pub(super) fn range_counting<R: RangeBounds<T>>(
+        &self,
+        range: &R,
+        visited: &mut usize,
+    ) -> Range<'_, T, A> {
+        let (front, start_rank) = match range.start_bound() {
+            Bound::Unbounded => (Cursor::before_first(&self.root), 0),
+            Bound::Included(start) => Cursor::new(&self.root, start, visited),
+            Bound::Excluded(start) => past(Cursor::new(&self.root, start, visited), start),
+        };
+        let (back, end_rank) = match range.end_bound() {
+            Bound::Unbounded => (Cursor::after_last(&self.root), self.len()),
+            Bound::Excluded(end) => Cursor::new(&self.root, end, visited),
+            Bound::Included(end) => past(Cursor::new(&self.root, end, visited), end),
+        };
+
+        Range {
+            front,
+            back,
+            remaining: end_rank.saturating_sub(start_rank),
+        }
+    }
+
+    /// Whether any key lies within `range`. A single descent finds the first key at or after the
+    /// start, which is then checked against the end, however many keys the window holds.
+    pub fn any_in_range<R: RangeBounds<T>>(&self, range: R) -> bool {
+        let mut visited = 0;
+        self.any_in_range_counting(&range, &mut visited)
+    }
+
+    // `any_in_range`, adding the number of nodes whose keys it looked at to `visited`
+    pub(super) fn any_in_range_counting<R: RangeBounds<T>>(
+        &self,
+        range: &R,
+        visited: &mut usize,
+    ) -> bool {
+        match (
+            first_from(&self.root, range.start_bound(), visited),
+            range.end_bound(),
+        ) {
+            (None, _) => false,
+            (Some(_), Bound::Unbounded) => true,
+            (Some(key), Bound::Included(end)) => key <= end,
+            (Some(key), Bound::Excluded(end)) => key < end,
+        }
+    }
+}
+
+// Moves a cursor sought to `key`, with `rank` keys before it, past `key` if the tree holds it.
+fn past<'a, T: Key, A: NodeAlloc>(
+    (mut cursor, rank): (Cursor<'a, T, A>, usize),
+    key: &T,
+) -> (Cursor<'a, T, A>, usize) {
+    if cursor.peek() == Some(key) {
+        cursor.next();
+        (cursor, rank + 1)
+    } else {
+        (cursor, rank)
+    }
+}
+
+// The smallest key of the subtree which satisfies the lower bound `start`, looking at one node
+// on each level, and at none below a child whose keys all fall short of `start`.
+fn first_from<'a, T: Key, A: NodeAlloc>(
+    node: &'a Node<T, A>,
+    start: Bound<&T>,
+    visited: &mut usize,
+) -> Option<&'a T> {
+    *visited += 1;
+    let keys = node.keys();
+    // keys[index..] all satisfy `start`, and so does everything in the children after `index`
+    let index = match start {
+        Bound::Included(start) => keys.partition_point(|key| key < start),
+        Bound::Excluded(start) => keys.partition_point(|key| key <= start),
+        Bound::Unbounded => 0,
+    };
+
+    match node.children() {
+        Some(children) => {
+            let child = &children[index];
+            let below = if child.misses(&(start, Bound::Unbounded)) {
+                None
+            } else {
+                first_from(child, start, visited)
+            };
+            below.or(keys.get(index))
+        }
+        None => keys.get(index),
+    }
+}
+
+impl<'a, T: 'a + Key, A: 'a + NodeAlloc> Iterator for Range<'a, T, A> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.front.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T: 'a + Key, A: 'a + NodeAlloc> DoubleEndedIterator for Range<'a, T, A> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.back.prev()
+    }
+}
+
+impl<'a, T: 'a + Key, A: 'a + NodeAlloc> ExactSizeIterator for Range<'a, T, A> {}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use super::super::BTree;
+
+    // multiples of 5 below 10_000
+    fn fives() -> BTree<u32> {
+        let mut tree = BTree::new();
+        for i in 0..2000 {
+            tree.insert((i * 7919) % 2000 * 5);
+        }
+        tree
+    }
+
+    fn expected(tree: &BTree<u32>, lo: Bound<u32>, hi: Bound<u32>) -> Vec<u32> {
+        tree.iter()
+            .cloned()
+            .filter(|k| match lo {
+                Bound::Included(lo) => *k >= lo,
+                Bound::Excluded(lo) => *k > lo,
+                Bound::Unbounded => true,
+            })
+            .filter(|k| match hi {
+                Bound::Included(hi) => *k <= hi,
+                Bound::Excluded(hi) => *k < hi,
+                Bound::Unbounded => true,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn range_matches_filtered_iteration() {
+        // lazy removals leave underfull and empty nodes for the descents to count their way past
+        let mut thinned = fives();
+        thinned.set_lazy_removal(true);
+        for key in (0..10_000).step_by(15) {
+            thinned.remove(&key);
+        }
+        let points = [0, 1, 5, 148, 150, 151, 5000, 9995, 9996, 20_000];
+
+        let mut bounds = vec![Bound::Unbounded];
+        for p in points.iter() {
+            bounds.push(Bound::Included(*p));
+            bounds.push(Bound::Excluded(*p));
+        }
+
+        for tree in [fives(), thinned].iter() {
+            for lo in bounds.iter() {
+                for hi in bounds.iter() {
+                    let want = expected(tree, *lo, *hi);
+                    let forward = tree.range((*lo, *hi)).cloned().collect::<Vec<_>>();
+                    assert_eq!(forward, want, "{:?}..{:?}", lo, hi);
+                    assert_eq!(tree.range((*lo, *hi)).len(), want.len());
+
+                    let mut reversed = tree.range((*lo, *hi)).rev().cloned().collect::<Vec<_>>();
+                    reversed.reverse();
+                    assert_eq!(reversed, forward, "{:?}..{:?} reversed", lo, hi);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rev_takes_keys_just_below() {
+        let tree = fives();
+        let newest = tree
+            .range(..5000)
+            .rev()
+            .take(3)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(newest, vec![4995, 4990, 4985]);
+
+        let window = tree.range(100..=120).rev().cloned().collect::<Vec<_>>();
+        assert_eq!(window, vec![120, 115, 110, 105, 100]);
+    }
+
+    #[test]
+    fn both_ends_meet_in_the_middle() {
+        let tree = fives();
+        let mut range = tree.range(10..=40);
+
+        assert_eq!(range.next(), Some(&10));
+        assert_eq!(range.next_back(), Some(&40));
+        assert_eq!(range.next(), Some(&15));
+        assert_eq!(range.next_back(), Some(&35));
+        assert_eq!(range.next_back(), Some(&30));
+        assert_eq!(range.next(), Some(&20));
+        assert_eq!(range.next(), Some(&25));
+        assert_eq!(range.next(), None);
+        assert_eq!(range.next_back(), None);
+    }
+
+    #[test]
+    fn any_in_range_on_sparse_tree() {
+        let tree = BTree::from_sorted((0..1000_u32).map(|k| k * 1000));
+
+        assert!(tree.any_in_range(5000..=5000));
+        assert!(!tree.any_in_range(5001..6000));
+        assert!(tree.any_in_range(5001..=6000));
+        assert!(!tree.any_in_range((Bound::Excluded(5000), Bound::Excluded(6000))));
+        assert!(tree.any_in_range(4500..5500));
+        assert!(!tree.any_in_range(999_001..));
+        assert!(tree.any_in_range(..1));
+        assert!(!tree.any_in_range(..0));
+        assert!(tree.any_in_range(..));
+        assert!(!tree.any_in_range((Bound::Included(7000), Bound::Excluded(6000))));
+
+        for lo in (0..20_000).step_by(333) {
+            for width in [0, 1, 500, 1000, 3000].iter() {
+                let range = lo..lo + width;
+                assert_eq!(
+                    tree.any_in_range(range.clone()),
+                    tree.range(range).next().is_some()
+                );
+            }
+        }
+        assert!(!BTree::<u32>::new().any_in_range(..));
+
+        // however wide the window, the answer comes from one root-to-leaf descent
+        assert!(tree.height() > 1);
+        for &(lo, hi) in [(1, 999_000), (0, 1_000_000), (1, 1000), (5001, 6000)].iter() {
+            let (found, visited) = tree.any_in_range_instrumented(lo..hi);
+            assert_eq!(found, tree.range(lo..hi).next().is_some());
+            assert_eq!(visited, tree.height(), "{}..{}", lo, hi);
+        }
+        assert_eq!(tree.range(1..999_000).len(), 998);
+    }
+
+    #[test]
+    fn inverted_and_empty_ranges() {
+        let tree = fives();
+        assert_eq!(
+            tree.range((Bound::Included(50), Bound::Excluded(10)))
+                .next(),
+            None
+        );
+        assert_eq!(tree.range(11..14).next_back(), None);
+        assert_eq!(BTree::<u32>::new().range(..).next(), None);
+        assert_eq!(BTree::<u32>::new().range(..).next_back(), None);
+    }
+}
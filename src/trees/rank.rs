@@ -0,0 +1,293 @@
+use std::ops::{Bound, Index, RangeBounds};
+use std::vec::Vec;
+
+use super::{search_keys, BTree, Key, NodeAlloc};
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// The key at position `index` in ascending order (0 is the smallest), found in a single
+    /// descent using the per-subtree key counts.
+    pub fn select(&self, mut index: usize) -> Option<&T> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let mut node = &self.root;
+        loop {
+            let children = match node.children() {
+                Some(children) => children,
+                None => return Some(&node.keys()[index]),
+            };
+
+            // each child is followed by the key separating it from the next child
+            let mut child_index = 0;
+            loop {
+                let child_len = children[child_index].subtree_len();
+                if index < child_len {
+                    break;
+                }
+                if index == child_len {
+                    return Some(&node.keys()[child_index]);
+                }
+                index -= child_len + 1;
+                child_index += 1;
+            }
+            node = &children[child_index];
+        }
+    }
+
+    /// Alias for `select`, for treating the tree as a sorted sequence.
+    pub fn nth(&self, index: usize) -> Option<&T> {
+        self.select(index)
+    }
+
+    /// The number of keys strictly less than `key`, which need not itself be present; if it is,
+    /// this is its position in ascending order.
+    pub fn rank(&self, key: &T) -> usize {
+        let mut rank = 0;
+        let mut node = &self.root;
+
+        loop {
+            let position = search_keys(node.keys(), key);
+            let index = position.unwrap_or_else(|i| i);
+            rank += index;
+
+            let children = match node.children() {
+                Some(children) => children,
+                None => return rank,
+            };
+
+            rank += children[..index]
+                .iter()
+                .map(|child| child.subtree_len())
+                .sum::<usize>();
+
+            match position {
+                Ok(_) => return rank + children[index].subtree_len(),
+                Err(_) => node = &children[index],
+            }
+        }
+    }
+
+    /// The number of keys within `window` which are strictly less than `key`, for percentiles over
+    /// part of the tree. `key` needn't lie in the window: below it the answer is 0, and above it
+    /// every key in the window counts. An empty (or backwards) window always gives 0. Costs three
+    /// `rank`-style descents however wide the window is.
+    pub fn rank_within<R: RangeBounds<T>>(&self, key: &T, window: R) -> usize {
+        let before = match window.start_bound() {
+            Bound::Included(start) => self.rank(start),
+            Bound::Excluded(start) => self.rank(start) + self.find(start) as usize,
+            Bound::Unbounded => 0,
+        };
+        let through = match window.end_bound() {
+            Bound::Included(end) => self.rank(end) + self.find(end) as usize,
+            Bound::Excluded(end) => self.rank(end),
+            Bound::Unbounded => self.len(),
+        };
+
+        // ranks are positions in the whole tree; the window holds those in before..through
+        let through = through.max(before);
+        self.rank(key).clamp(before, through) - before
+    }
+
+    /// Inserts `key` if it is not already present, returning whether it was inserted along with
+    /// its rank (position in ascending order) either way. Inserting a key never changes how many
+    /// keys are below it, so the rank is read before the insert descends.
+    pub fn insert_with_rank(&mut self, key: T) -> (bool, usize) {
+        let rank = self.rank(&key);
+        (self.insert(key), rank)
+    }
+}
+
+impl<T: Key + Clone, A: NodeAlloc> BTree<T, A> {
+    /// Boundary keys splitting the sorted contents into `n` buckets of nearly equal size: the
+    /// `i`th boundary is the key at position `len * i / n`, so each one starts a bucket. Asking
+    /// for more buckets than there are keys gives one bucket per key; no buckets (or an empty
+    /// tree) gives no boundaries.
+    pub fn equi_depth_buckets(&self, n: usize) -> Vec<T> {
+        let len = self.len();
+        let n = n.min(len);
+
+        (1..n)
+            .map(|i| {
+                let index = (len as u128 * i as u128 / n as u128) as usize;
+                self.select(index)
+                    .expect("bucket boundary within the tree")
+                    .clone()
+            })
+            .collect()
+    }
+}
+
+impl<T: Key, A: NodeAlloc> Index<usize> for BTree<T, A> {
+    type Output = T;
+
+    /// Panics if `index >= len()`, as for `Vec`.
+    fn index(&self, index: usize) -> &T {
+        match self.select(index) {
+            Some(key) => key,
+            None => panic!(
+                "index out of bounds: the len is {} but the index is {}",
+                self.len(),
+                index
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::{Bound, RangeBounds};
+
+    use super::super::BTree;
+
+    fn shuffled(n: u64) -> BTree<u64> {
+        let mut tree = BTree::new();
+        for i in 0..n {
+            tree.insert((i * 7919) % n * 3);
+        }
+        tree
+    }
+
+    #[test]
+    fn select_matches_iteration() {
+        let tree = shuffled(5003);
+        let sorted = tree.iter().collect::<Vec<_>>();
+
+        for (i, key) in sorted.iter().enumerate() {
+            assert_eq!(tree.select(i), Some(*key));
+            assert_eq!(tree.nth(i), Some(*key));
+        }
+        assert_eq!(tree.select(sorted.len()), None);
+        assert_eq!(BTree::<u32>::new().select(0), None);
+    }
+
+    #[test]
+    fn insert_with_rank_reports_position_in_prefix() {
+        let mut tree = BTree::new();
+        let mut inserted = Vec::new();
+
+        for i in 0..2000_u64 {
+            let key = (i * 7919) % 1009;
+            let expected_new = !inserted.contains(&key);
+            if expected_new {
+                inserted.push(key);
+            }
+            inserted.sort();
+
+            let position = inserted.binary_search(&key).unwrap();
+            assert_eq!(tree.insert_with_rank(key), (expected_new, position));
+        }
+        assert_eq!(tree.len(), 1009);
+    }
+
+    #[test]
+    fn rank_within_matches_brute_force() {
+        let tree = shuffled(2000);
+        let brute = |key: u64, window: (Bound<u64>, Bound<u64>)| {
+            tree.iter()
+                .filter(|k| window.contains(*k) && **k < key)
+                .count()
+        };
+
+        let windows = [
+            (Bound::Included(300), Bound::Excluded(3000)),
+            (Bound::Excluded(300), Bound::Included(3000)),
+            (Bound::Included(301), Bound::Included(2999)),
+            (Bound::Unbounded, Bound::Excluded(4500)),
+            (Bound::Excluded(4500), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Unbounded),
+            // empty and backwards windows
+            (Bound::Included(900), Bound::Excluded(900)),
+            (Bound::Included(1000), Bound::Included(10)),
+        ];
+        let keys = [0, 299, 300, 301, 1500, 1501, 2999, 3000, 3001, 5997, 10_000];
+
+        for &window in windows.iter() {
+            for &key in keys.iter() {
+                assert_eq!(
+                    tree.rank_within(&key, window),
+                    brute(key, window),
+                    "key {} in {:?}",
+                    key,
+                    window
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rank_within_clamps_outside_window() {
+        let tree = BTree::from_sorted(0..1000_u32);
+
+        assert_eq!(tree.rank_within(&5, 100..200), 0);
+        assert_eq!(tree.rank_within(&100, 100..200), 0);
+        assert_eq!(tree.rank_within(&150, 100..200), 50);
+        assert_eq!(tree.rank_within(&200, 100..200), 100);
+        assert_eq!(tree.rank_within(&5000, 100..200), 100);
+        assert_eq!(tree.rank_within(&5000, 100..=200), 101);
+        assert_eq!(tree.rank_within(&500, ..), tree.rank(&500));
+        assert_eq!(BTree::<u32>::new().rank_within(&1, 0..10), 0);
+    }
+
+    #[test]
+    fn rank_matches_iteration() {
+        let tree = shuffled(5003);
+
+        for probe in 0..5003 * 3 + 2 {
+            let expected = tree.iter().take_while(|k| **k < probe).count();
+            assert_eq!(tree.rank(&probe), expected);
+        }
+    }
+
+    #[test]
+    fn select_after_from_sorted() {
+        let tree = BTree::from_sorted(1000..40_000_i32);
+        for i in (0..39_000).step_by(37) {
+            assert_eq!(tree.select(i), Some(&(1000 + i as i32)));
+            assert_eq!(tree.rank(&(1000 + i as i32)), i);
+        }
+    }
+
+    #[test]
+    fn equi_depth_buckets_near_percentiles() {
+        let tree = BTree::from_sorted(0..100_000_u64);
+
+        let deciles = tree.equi_depth_buckets(10);
+        assert_eq!(deciles.len(), 9);
+        for (i, boundary) in deciles.iter().enumerate() {
+            let expected = (i as u64 + 1) * 10_000;
+            assert!(boundary.max(&expected) - boundary.min(&expected) <= 10);
+        }
+
+        let percentiles = tree.equi_depth_buckets(100);
+        assert_eq!(percentiles.len(), 99);
+        assert_eq!(percentiles[49], 50_000);
+    }
+
+    #[test]
+    fn equi_depth_buckets_edge_cases() {
+        assert!(BTree::<u64>::new().equi_depth_buckets(4).is_empty());
+        assert!(shuffled(10).equi_depth_buckets(0).is_empty());
+        assert!(shuffled(10).equi_depth_buckets(1).is_empty());
+
+        // more buckets than keys: every key past the first starts its own bucket
+        let tree = BTree::from_sorted(vec![1_u64, 2, 3]);
+        assert_eq!(tree.equi_depth_buckets(50), vec![2, 3]);
+    }
+
+    #[test]
+    fn index_matches_first() {
+        let tree = shuffled(1000);
+
+        assert_eq!(tree[0], *tree.first().unwrap());
+        assert_eq!(tree[tree.len() - 1], *tree.last().unwrap());
+        assert_eq!(tree[10], 30);
+    }
+
+    #[test]
+    #[should_panic(expected = "index out of bounds: the len is 1000 but the index is 1000")]
+    fn index_past_len_panics() {
+        let tree = shuffled(1000);
+        let _ = tree[1000];
+    }
+}
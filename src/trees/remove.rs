@@ -0,0 +1,694 @@
+use std::mem;
+use std::vec::Vec;
+
+use super::{
+    child_mut, highest_bound, into_node, lowest_bound, refresh_bounds, search_keys, stored, BTree,
+    Fanout, InternalNode, Key, Node, NodeAlloc, BATCH_REBUILD_RATIO,
+};
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Removes `key`, returning whether it was present.
+    ///
+    /// Normally the tree is rebalanced on the way back up, borrowing from or merging with a
+    /// sibling whenever a node drops below the minimum size. With lazy removal turned on (see
+    /// `set_lazy_removal`) the key is simply dropped from its node and nothing is merged.
+    pub fn remove(&mut self, key: &T) -> bool {
+        self.take(key).is_some()
+    }
+
+    /// Removes and returns the `index`th smallest key, or `None` if there are no more than
+    /// `index` keys. The key is found through the subtree counts as `select` does, then removed
+    /// and rebalanced like any other.
+    pub fn remove_nth(&mut self, index: usize) -> Option<T> {
+        if index >= self.num_keys {
+            return None;
+        }
+        self.take_target(Target::Rank(index))
+    }
+
+    /// Removes and returns the smallest key.
+    pub fn pop_first(&mut self) -> Option<T> {
+        self.remove_nth(0)
+    }
+
+    /// Removes and returns the largest key.
+    pub fn pop_last(&mut self) -> Option<T> {
+        let last = self.num_keys.checked_sub(1)?;
+        self.remove_nth(last)
+    }
+
+    /// Removes every key of `keys` (duplicates and absent keys are fine), returning how many
+    /// were present. The same threshold as `insert_sorted_batch` picks the strategy: a batch at
+    /// least 32 times smaller than the tree is removed key by key, honouring lazy removal, while
+    /// a bigger one is sorted and walked alongside the existing keys in a single pass, with the
+    /// survivors rebuilt into a densely packed tree. A bigger batch none of whose keys are
+    /// present leaves the tree untouched.
+    pub fn remove_all<I: IntoIterator<Item = T>>(&mut self, keys: I) -> usize {
+        let mut batch = keys.into_iter().collect::<Vec<_>>();
+
+        if batch.len() * BATCH_REBUILD_RATIO < self.num_keys {
+            return batch.iter().filter(|key| self.remove(key)).count();
+        }
+
+        batch.sort_unstable();
+        batch.dedup();
+
+        let mut doomed = batch.iter().peekable();
+        let any_present = self.iter().any(|key| {
+            while doomed.next_if(|next| *next < key).is_some() {}
+            doomed.peek() == Some(&key)
+        });
+        if !any_present {
+            return 0;
+        }
+
+        let before = self.num_keys;
+        let mut doomed = batch.iter().peekable();
+        let mut survivors = Vec::with_capacity(before);
+        for key in self.take_sorted_keys() {
+            while doomed.next_if(|next| **next < key).is_some() {}
+            if doomed.peek() == Some(&&key) {
+                doomed.next();
+            } else {
+                survivors.push(key);
+            }
+        }
+
+        let removed = before - survivors.len();
+        self.rebuild_from_sorted(survivors);
+        removed
+    }
+
+    // Removes `key` and hands it back, if it was present.
+    fn take(&mut self, key: &T) -> Option<T> {
+        self.take_target(Target::Key(key))
+    }
+
+    fn take_target(&mut self, target: Target<'_, T>) -> Option<T> {
+        // the descent unshares every node it passes under `cow`, so make sure there is a key
+        if let Target::Key(key) = target {
+            if cfg!(feature = "cow") && stored(&self.root, key).is_none() {
+                return None;
+            }
+        }
+
+        let removal = self.removal();
+        let removed = remove_at_node(&mut self.root, target, removal);
+
+        if removed.is_some() {
+            self.num_keys -= 1;
+            self.mod_count = self.mod_count.wrapping_add(1);
+            self.removals_since_compact += 1;
+            // merges free leaves which others link to
+            self.leaf_chain.unlink();
+            self.collapse_root();
+        }
+        removed
+    }
+
+    /// Turns lazy removal on or off. In lazy mode `remove` never merges or rebalances: the key
+    /// leaves a hole in its node, which keeps deletes cheap but lets nodes fall below the minimum
+    /// size (or empty out entirely) and holds on to their memory. Lookups, iteration and `len`
+    /// only ever see live keys, but `check_invariants` will report the underfull nodes until
+    /// `compact` is called (`check_lazy_invariants` lets them pass). Switching lazy mode off does
+    /// not compact by itself.
+    pub fn set_lazy_removal(&mut self, lazy: bool) {
+        self.lazy_removal = lazy;
+    }
+
+    pub fn lazy_removal(&self) -> bool {
+        self.lazy_removal
+    }
+
+    /// Repacks the live keys densely into a fresh tree, releasing the space left behind by
+    /// removals and restoring every B-tree invariant. Costs about one move per key.
+    pub fn compact(&mut self) {
+        let keys = self.take_sorted_keys();
+        self.rebuild_from_sorted(keys);
+    }
+
+    /// Calls `compact` if enough keys have been removed since the tree was last rebuilt: more
+    /// than the compaction threshold (see `set_compact_threshold`) times the number of keys
+    /// left. Cheap enough to call after every batch of deletes.
+    pub fn maybe_compact(&mut self) {
+        if self.removals_since_compact as f64 > self.compact_threshold * self.num_keys as f64 {
+            self.compact();
+        }
+    }
+
+    /// Sets how many removals per remaining key `maybe_compact` waits for; the default of 1.0
+    /// compacts once at least half of the keys have been removed since the last rebuild. Panics
+    /// if `threshold` is negative or NaN.
+    pub fn set_compact_threshold(&mut self, threshold: f64) {
+        assert!(
+            threshold >= 0.0,
+            "compaction threshold must be non-negative, got {}",
+            threshold
+        );
+        self.compact_threshold = threshold;
+    }
+
+    pub fn compact_threshold(&self) -> f64 {
+        self.compact_threshold
+    }
+
+    fn removal(&self) -> Removal {
+        Removal {
+            lazy: self.lazy_removal,
+            fanout: self.fanout,
+        }
+    }
+
+    // An internal root left with no keys has a single child, which takes its place.
+    fn collapse_root(&mut self) {
+        loop {
+            let only_child = match self.root {
+                Node::Internal(ref mut root) if root.keys.is_empty() => root.children.pop(),
+                _ => return,
+            };
+            match only_child {
+                Some(child) => self.root = into_node(child),
+                None => return,
+            }
+        }
+    }
+}
+
+// how a removal treats the nodes it leaves smaller
+#[derive(Clone, Copy)]
+struct Removal {
+    lazy: bool,
+    fanout: Fanout,
+}
+
+// the key a removal is after
+#[derive(Clone, Copy)]
+enum Target<'a, T: 'a> {
+    Key(&'a T),
+    // position among the keys of the subtree being searched
+    Rank(usize),
+}
+
+// Where `target` lies in `node`: `Ok(i)` if it is the node's own key `i`, otherwise `Err` with
+// the child it would be under and what to look for there.
+fn locate<'a, T: Key, A: NodeAlloc>(
+    node: &Node<T, A>,
+    target: Target<'a, T>,
+) -> Result<usize, (usize, Target<'a, T>)> {
+    match target {
+        Target::Key(key) => search_keys(node.keys(), key).map_err(|index| (index, target)),
+        Target::Rank(mut rank) => match node.children() {
+            None if rank < node.keys().len() => Ok(rank),
+            None => Err((rank, target)),
+            Some(children) => {
+                // each child is followed by the key separating it from the next child
+                for (index, child) in children.iter().enumerate() {
+                    let child_len = child.subtree_len();
+                    if rank < child_len {
+                        return Err((index, Target::Rank(rank)));
+                    }
+                    if rank == child_len && index < node.keys().len() {
+                        return Ok(index);
+                    }
+                    rank -= child_len + 1;
+                }
+                Err((children.len(), Target::Rank(rank)))
+            }
+        },
+    }
+}
+
+fn remove_at_node<T: Key, A: NodeAlloc>(
+    node: &mut Node<T, A>,
+    target: Target<'_, T>,
+    removal: Removal,
+) -> Option<T> {
+    let position = locate(node, target);
+
+    match *node {
+        Node::Leaf(ref mut leaf) => match position {
+            Ok(index) => {
+                leaf.num_keys -= 1;
+                Some(leaf.keys.remove(index))
+            }
+            Err(_) => None,
+        },
+
+        Node::Internal(ref mut internal) => {
+            let removed = match position {
+                // the largest key of the child to the left takes the removed key's place
+                Ok(index) => match pop_last(child_mut(&mut internal.children[index]), removal) {
+                    Some(replacement) => {
+                        let removed = mem::replace(&mut internal.keys[index], replacement);
+                        if !removal.lazy {
+                            fix_underflow(internal, index, removal.fanout);
+                        }
+                        Some(removed)
+                    }
+                    // only possible after lazy removals: the child to the left is empty, so
+                    // it goes along with the key
+                    None => {
+                        internal.children.remove(index);
+                        internal.num_keys -= 1;
+                        Some(internal.keys.remove(index))
+                    }
+                },
+                Err((index, target)) => {
+                    let removed =
+                        remove_at_node(child_mut(&mut internal.children[index]), target, removal);
+                    if removed.is_some() && !removal.lazy {
+                        fix_underflow(internal, index, removal.fanout);
+                    }
+                    removed
+                }
+            };
+
+            if let Some(ref key) = removed {
+                internal.subtree_keys -= 1;
+                // the bounds only move when one of them is the key that went
+                if internal.subtree_min.as_ref().is_none_or(|min| min == key) {
+                    internal.subtree_min = lowest_bound(internal);
+                }
+                if internal.subtree_max.as_ref().is_none_or(|max| max == key) {
+                    internal.subtree_max = highest_bound(internal);
+                }
+            }
+            removed
+        }
+    }
+}
+
+// Removes and returns the largest key of the subtree, if it has any keys.
+fn pop_last<T: Key, A: NodeAlloc>(node: &mut Node<T, A>, removal: Removal) -> Option<T> {
+    match *node {
+        Node::Leaf(ref mut leaf) => {
+            let popped = leaf.keys.pop();
+            leaf.num_keys = leaf.keys.len();
+            popped
+        }
+
+        Node::Internal(ref mut internal) => {
+            let last = internal.children.len() - 1;
+            let popped = match pop_last(child_mut(&mut internal.children[last]), removal) {
+                Some(popped) => {
+                    if !removal.lazy {
+                        fix_underflow(internal, last, removal.fanout);
+                    }
+                    Some(popped)
+                }
+                // only possible after lazy removals: the rightmost child is empty, so this
+                // node's last key is the largest, and the empty child goes with it
+                None => {
+                    let popped = internal.keys.pop();
+                    if popped.is_some() {
+                        internal.children.pop();
+                        internal.num_keys -= 1;
+                    }
+                    popped
+                }
+            };
+
+            if popped.is_some() {
+                internal.subtree_keys -= 1;
+                refresh_bounds(internal);
+            }
+            popped
+        }
+    }
+}
+
+// If `parent.children[index]` has dropped below the minimum, tops it back up by borrowing a key
+// through the parent from a sibling which can spare one, or else merges it with a sibling.
+fn fix_underflow<T: Key, A: NodeAlloc>(
+    parent: &mut InternalNode<T, A>,
+    index: usize,
+    fanout: Fanout,
+) {
+    let min_keys = fanout.min_keys(&parent.children[index]);
+    if parent.children[index].keys().len() >= min_keys {
+        return;
+    }
+
+    if index > 0 && parent.children[index - 1].keys().len() > min_keys {
+        rotate_right(parent, index - 1);
+    } else if index < parent.num_keys && parent.children[index + 1].keys().len() > min_keys {
+        rotate_left(parent, index);
+    } else if index > 0 {
+        merge_children(parent, index - 1);
+    } else if index < parent.num_keys {
+        merge_children(parent, index);
+    }
+}
+
+// Moves the last key of the child at `index` up into the parent, and the separator down to the
+// front of the child after it.
+fn rotate_right<T: Key, A: NodeAlloc>(parent: &mut InternalNode<T, A>, index: usize) {
+    let separator = &mut parent.keys[index];
+    let (left, right) = {
+        let (left, right) = parent.children.split_at_mut(index + 1);
+        (child_mut(&mut left[index]), child_mut(&mut right[0]))
+    };
+
+    match (left, right) {
+        (&mut Node::Leaf(ref mut left), &mut Node::Leaf(ref mut right)) => {
+            let up = left.keys.pop().expect("borrowing from an empty sibling");
+            right.keys.insert(0, mem::replace(separator, up));
+            left.num_keys = left.keys.len();
+            right.num_keys = right.keys.len();
+        }
+        (&mut Node::Internal(ref mut left), &mut Node::Internal(ref mut right)) => {
+            let up = left.keys.pop().expect("borrowing from an empty sibling");
+            let child = left.children.pop().expect("internal node with no children");
+            let moved = child.subtree_len() + 1;
+
+            right.keys.insert(0, mem::replace(separator, up));
+            right.children.insert(0, child);
+            left.num_keys = left.keys.len();
+            right.num_keys = right.keys.len();
+            left.subtree_keys -= moved;
+            right.subtree_keys += moved;
+            refresh_bounds(left);
+            refresh_bounds(right);
+        }
+        _ => unreachable!("siblings are always at the same height"),
+    }
+}
+
+// Moves the first key of the child at `index + 1` up into the parent, and the separator down to
+// the end of the child before it.
+fn rotate_left<T: Key, A: NodeAlloc>(parent: &mut InternalNode<T, A>, index: usize) {
+    let separator = &mut parent.keys[index];
+    let (left, right) = {
+        let (left, right) = parent.children.split_at_mut(index + 1);
+        (child_mut(&mut left[index]), child_mut(&mut right[0]))
+    };
+
+    match (left, right) {
+        (&mut Node::Leaf(ref mut left), &mut Node::Leaf(ref mut right)) => {
+            let up = right.keys.remove(0);
+            left.keys.push(mem::replace(separator, up));
+            left.num_keys = left.keys.len();
+            right.num_keys = right.keys.len();
+        }
+        (&mut Node::Internal(ref mut left), &mut Node::Internal(ref mut right)) => {
+            let up = right.keys.remove(0);
+            let child = right.children.remove(0);
+            let moved = child.subtree_len() + 1;
+
+            left.keys.push(mem::replace(separator, up));
+            left.children.push(child);
+            left.num_keys = left.keys.len();
+            right.num_keys = right.keys.len();
+            left.subtree_keys += moved;
+            right.subtree_keys -= moved;
+            refresh_bounds(left);
+            refresh_bounds(right);
+        }
+        _ => unreachable!("siblings are always at the same height"),
+    }
+}
+
+// Folds the child at `index + 1`, and the separator between them, into the child at `index`.
+fn merge_children<T: Key, A: NodeAlloc>(parent: &mut InternalNode<T, A>, index: usize) {
+    let separator = parent.keys.remove(index);
+    let right = parent.children.remove(index + 1);
+    parent.num_keys -= 1;
+
+    match (child_mut(&mut parent.children[index]), into_node(right)) {
+        (&mut Node::Leaf(ref mut left), Node::Leaf(right)) => {
+            left.keys.push(separator);
+            left.keys.extend(right.keys);
+            left.num_keys = left.keys.len();
+        }
+        (&mut Node::Internal(ref mut left), Node::Internal(right)) => {
+            left.keys.push(separator);
+            left.keys.extend(right.keys);
+            left.children.extend(right.children);
+            left.num_keys = left.keys.len();
+            left.subtree_keys += right.subtree_keys + 1;
+            left.subtree_max = right.subtree_max;
+        }
+        _ => unreachable!("siblings are always at the same height"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::scattered;
+    use super::super::BTree;
+
+    #[test]
+    fn remove_all_large_batch_rebuilds() {
+        let mut tree = BTree::new();
+        for key in scattered(10_000) {
+            tree.insert(key);
+        }
+
+        // every third key, each twice, plus some which were never there
+        let batch = (0..10_000)
+            .filter(|k| k % 3 == 0)
+            .flat_map(|k| vec![k, k])
+            .chain(20_000..20_100);
+        assert_eq!(tree.remove_all(batch), 3334);
+
+        assert_eq!(tree.len(), 6666);
+        let expected = (0..10_000).filter(|k| k % 3 != 0).collect::<Vec<_>>();
+        assert_eq!(tree.to_sorted_vec(), expected);
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree.removals_since_compact, 0);
+    }
+
+    #[test]
+    fn remove_all_small_batch_key_by_key() {
+        let mut tree = BTree::from_sorted(0..10_000_u64);
+
+        assert_eq!(tree.remove_all(vec![5, 17, 17, 9_999, 10_000]), 3);
+
+        assert_eq!(tree.len(), 9_997);
+        assert!(!tree.find(&5) && !tree.find(&17) && !tree.find(&9_999));
+        assert!(tree.find(&6) && tree.find(&9_998));
+        assert_eq!(tree.check_invariants(), Ok(()));
+        // removed in place rather than rebuilt
+        assert_eq!(tree.removals_since_compact, 3);
+
+        assert_eq!(tree.remove_all(Vec::new()), 0);
+        assert_eq!(BTree::<u64>::new().remove_all(vec![1, 2]), 0);
+    }
+
+    #[test]
+    fn remove_all_large_batch_of_absent_keys_leaves_tree_alone() {
+        let mut tree = BTree::new();
+        for key in scattered(3000) {
+            tree.insert(key);
+        }
+        tree.set_lazy_removal(true);
+        for key in (0..3000).filter(|k| k % 4 == 0) {
+            tree.remove(&key);
+        }
+        let heap = tree.estimated_heap_bytes();
+        let mod_count = tree.mod_count();
+
+        // no rebuild: the lazy holes stay, and so does the count towards compacting them
+        let absent = (0..3000).filter(|k| k % 4 == 0).chain(5000..6000);
+        assert_eq!(tree.remove_all(absent), 0);
+        assert_eq!(tree.removals_since_compact, 750);
+        assert_eq!(tree.estimated_heap_bytes(), heap);
+        assert_eq!(tree.mod_count(), mod_count);
+        assert_eq!(tree.len(), 2250);
+    }
+
+    #[test]
+    fn remove_everything_in_scattered_order() {
+        let mut tree = BTree::new();
+        for key in scattered(5000) {
+            tree.insert(key);
+        }
+
+        for (i, key) in scattered(5000).iter().rev().enumerate() {
+            assert!(tree.remove(key));
+            assert!(!tree.find(key));
+            assert_eq!(tree.len(), 5000 - i - 1);
+            if i % 97 == 0 {
+                assert_eq!(tree.check_invariants(), Ok(()));
+            }
+        }
+
+        assert!(tree.is_empty());
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree.iter().next(), None);
+    }
+
+    #[test]
+    fn maybe_compact_after_mass_deletes() {
+        let mut tree = BTree::from_sorted(0..1_000_000_u64);
+        tree.set_lazy_removal(true);
+
+        // a few deletes are not worth a rebuild
+        for key in 0..1000 {
+            assert!(tree.remove(&key));
+        }
+        let (height, nodes) = (tree.height(), tree.node_count());
+        tree.maybe_compact();
+        assert_eq!(tree.node_count(), nodes);
+
+        for key in (1000..1_000_000).filter(|k| k % 10 != 0) {
+            assert!(tree.remove(&key));
+        }
+        assert_eq!((tree.height(), tree.node_count()), (height, nodes));
+
+        tree.maybe_compact();
+        assert_eq!(tree.len(), 99_900);
+        assert!(tree.height() < height);
+        assert!(tree.node_count() * 5 < nodes);
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert!(tree.iter().cloned().eq((1000..1_000_000).step_by(10)));
+
+        // the count starts again from the rebuild
+        let nodes = tree.node_count();
+        tree.maybe_compact();
+        assert_eq!(tree.node_count(), nodes);
+    }
+
+    #[test]
+    fn compact_threshold_is_configurable() {
+        let mut tree = BTree::builder().compact_threshold(0.1).build();
+        for key in 0..1000_u32 {
+            tree.insert(key);
+        }
+        assert_eq!(tree.compact_threshold(), 0.1);
+
+        for key in 0..90 {
+            tree.remove(&key);
+        }
+        let nodes = tree.node_count();
+        tree.maybe_compact();
+        assert_eq!(tree.node_count(), nodes);
+
+        // 91 removals against 909 keys left crosses a tenth
+        tree.remove(&90);
+        tree.maybe_compact();
+        assert!(tree.node_count() < nodes);
+        assert_eq!(tree, BTree::from_sorted(91..1000));
+    }
+
+    #[test]
+    fn remove_keeps_the_rest() {
+        let mut tree = BTree::from_sorted(0..10_000_u32);
+        for key in (0..10_000).filter(|k| k % 3 != 0) {
+            assert!(tree.remove(&key));
+        }
+
+        assert_eq!(tree.check_invariants(), Ok(()));
+        let expected = (0..10_000).filter(|k| k % 3 == 0).collect::<Vec<_>>();
+        assert_eq!(tree.iter().cloned().collect::<Vec<_>>(), expected);
+        assert_eq!(tree.select(100), Some(&300));
+    }
+
+    #[test]
+    fn remove_missing_key() {
+        let mut tree = BTree::from_sorted((0..1000_u32).map(|k| 2 * k));
+
+        assert!(!tree.remove(&7));
+        assert!(!tree.remove(&5000));
+        assert!(tree.remove(&8));
+        assert!(!tree.remove(&8));
+        assert_eq!(tree.len(), 999);
+        assert!(!BTree::<u32>::new().remove(&1));
+    }
+
+    #[test]
+    fn lazy_removal_then_compact() {
+        let mut tree = BTree::new();
+        for key in scattered(20_000) {
+            tree.insert(key);
+        }
+        tree.set_lazy_removal(true);
+
+        for key in scattered(20_000).into_iter().filter(|k| k % 10 != 0) {
+            assert!(tree.remove(&key));
+            assert!(!tree.find(&key));
+        }
+        assert_eq!(tree.size(), 2000);
+        assert_eq!(tree.len(), tree.iter().count());
+        assert!(tree.iter().all(|k| k % 10 == 0));
+        assert_eq!(tree.first(), Some(&0));
+        assert_eq!(tree.last(), Some(&19_990));
+        assert_eq!(tree.select(1), Some(&10));
+
+        let before = tree.estimated_heap_bytes();
+        tree.compact();
+
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree.size(), 2000);
+        assert!(tree.estimated_heap_bytes() < before / 4);
+        assert_eq!(
+            tree.iter().cloned().collect::<Vec<_>>(),
+            (0..2000).map(|k| 10 * k).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn lazy_removal_of_everything() {
+        let mut tree = BTree::from_sorted(0..5000_u64);
+        tree.set_lazy_removal(true);
+
+        for key in scattered(5000) {
+            assert!(tree.remove(&key));
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.iter().next(), None);
+        assert_eq!(tree.first(), None);
+
+        tree.insert(42);
+        assert_eq!(tree.iter().cloned().collect::<Vec<_>>(), vec![42]);
+
+        tree.compact();
+        assert_eq!(tree.check_invariants(), Ok(()));
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn remove_nth_zero_matches_pop_first() {
+        let mut by_index = BTree::new();
+        for key in scattered(3000) {
+            by_index.insert(key);
+        }
+        let mut by_pop = by_index.clone();
+
+        for expected in 0..3000 {
+            let removed = by_index.remove_nth(0);
+            assert_eq!(removed, Some(expected));
+            assert_eq!(removed, by_pop.pop_first());
+        }
+        assert_eq!(by_index.remove_nth(0), None);
+        assert_eq!(by_pop.pop_first(), None);
+        assert_eq!(by_index.check_invariants(), Ok(()));
+        assert_eq!(by_pop.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn remove_nth_from_the_middle() {
+        for &lazy in [false, true].iter() {
+            let mut tree = BTree::from_sorted((0..2000_u64).map(|k| 3 * k));
+            tree.set_lazy_removal(lazy);
+            let mut expected = tree.iter().cloned().collect::<Vec<_>>();
+
+            for i in 0..500 {
+                let index = (i * 7919) % expected.len();
+                assert_eq!(tree.remove_nth(index), Some(expected.remove(index)));
+            }
+            assert_eq!(tree.remove_nth(expected.len()), None);
+            assert_eq!(tree.pop_last(), expected.pop());
+
+            // lazy removals are free to leave nodes underfull
+            let checked = if lazy {
+                tree.check_lazy_invariants()
+            } else {
+                tree.check_invariants()
+            };
+            assert_eq!(checked, Ok(()));
+            assert_eq!(tree.size(), expected.len());
+            assert_eq!(tree.iter().cloned().collect::<Vec<_>>(), expected);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::BTreeSet;
+use std::vec::Vec;
+
+use rand::Rng;
+
+use super::{BTree, Key, NodeAlloc};
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// `n` distinct keys chosen uniformly at random, in ascending order. Only the chosen positions
+    /// are drawn (by Floyd's method) and each is fetched with `select`, so this costs
+    /// O(n log n) however large the tree is. If `n` is at least `len()`, every key is returned.
+    pub fn sample<R: Rng>(&self, n: usize, rng: &mut R) -> Vec<&T> {
+        let len = self.len();
+        if n >= len {
+            return self.iter().collect();
+        }
+
+        let mut chosen = BTreeSet::new();
+        for upper in len - n..len {
+            let index = rng.gen_range(0..=upper);
+            if !chosen.insert(index) {
+                chosen.insert(upper);
+            }
+        }
+
+        chosen
+            .into_iter()
+            .map(|index| {
+                self.select(index)
+                    .expect("sampled index is less than len()")
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::super::BTree;
+
+    #[test]
+    fn sample_is_distinct_and_present() {
+        let tree = BTree::from_sorted((0..10_000_u64).map(|k| 7 * k));
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+
+        for &n in [0, 1, 50, 9_999].iter() {
+            let sample = tree.sample(n, &mut rng);
+            assert_eq!(sample.len(), n);
+            assert!(sample.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(sample.iter().all(|key| tree.find(key)));
+        }
+    }
+
+    #[test]
+    fn sample_of_everything() {
+        let tree = BTree::from_sorted(0..100_u32);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let all = tree.iter().collect::<Vec<_>>();
+        assert_eq!(tree.sample(100, &mut rng), all);
+        assert_eq!(tree.sample(1000, &mut rng), all);
+        assert!(BTree::<u32>::new().sample(3, &mut rng).is_empty());
+    }
+}
@@ -0,0 +1,427 @@
+use std::mem;
+use std::ops::RangeBounds;
+
+use super::{BTree, Child, Key, Node, NodeAlloc, Range};
+
+/// How full the nodes of a tree are, split by leaves and internal nodes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FillStats {
+    pub node_count: usize,
+
+    pub leaf_count: usize,
+    pub min_leaf_keys: usize,
+    pub max_leaf_keys: usize,
+    pub avg_leaf_keys: f64,
+
+    /// Zero for a tree which is a single leaf, as are the other internal node figures.
+    pub internal_count: usize,
+    pub min_internal_keys: usize,
+    pub max_internal_keys: usize,
+    pub avg_internal_keys: f64,
+}
+
+/// Overall health figures for a tree, gathered in a single pass; see `BTree::stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeStats {
+    pub len: usize,
+    pub height: usize,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    /// Average number of keys per leaf.
+    pub avg_leaf_fill: f64,
+    /// Average number of keys per internal node; zero for a tree which is a single leaf.
+    pub avg_internal_fill: f64,
+    /// As given by `BTree::estimated_heap_bytes`.
+    pub estimated_heap_bytes: usize,
+}
+
+// running min / max / total over one kind of node
+#[derive(Default)]
+struct Tally {
+    count: usize,
+    min: usize,
+    max: usize,
+    total: usize,
+}
+
+impl Tally {
+    fn add(&mut self, keys: usize) {
+        self.min = if self.count == 0 {
+            keys
+        } else {
+            self.min.min(keys)
+        };
+        self.max = self.max.max(keys);
+        self.total += keys;
+        self.count += 1;
+    }
+
+    fn average(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total as f64 / self.count as f64
+        }
+    }
+}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Number of nodes, leaves and internal nodes alike.
+    pub fn node_count(&self) -> usize {
+        self.nodes().count()
+    }
+
+    /// Node fill statistics, gathered in a single pass over the tree. Useful for spotting trees
+    /// left sparse by insertion patterns, such as the half-empty leaves that sequential inserts
+    /// leave behind.
+    pub fn fill_stats(&self) -> FillStats {
+        let mut leaves = Tally::default();
+        let mut internals = Tally::default();
+        tally_node(&self.root, &mut leaves, &mut internals);
+
+        FillStats {
+            node_count: leaves.count + internals.count,
+
+            leaf_count: leaves.count,
+            min_leaf_keys: leaves.min,
+            max_leaf_keys: leaves.max,
+            avg_leaf_keys: leaves.average(),
+
+            internal_count: internals.count,
+            min_internal_keys: internals.min,
+            max_internal_keys: internals.max,
+            avg_internal_keys: internals.average(),
+        }
+    }
+}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// The keys held in leaves as a fraction of the room the leaves have, from 0.0 to 1.0 (and
+    /// 0.0 for an empty tree). Ascending inserts under `SplitPolicy::Median` leave each leaf but
+    /// the rightmost only half full, which shows up here as a figure of about 0.5.
+    pub fn fill_efficiency(&self) -> f64 {
+        let fill = self.fill_stats();
+        fill.avg_leaf_keys / self.fanout.leaf_max_keys() as f64
+    }
+
+    /// Size, shape, fill and memory figures for the tree, all from one walk over the nodes.
+    pub fn stats(&self) -> TreeStats {
+        let mut leaves = Tally::default();
+        let mut internals = Tally::default();
+        let node_bytes = tally_node(&self.root, &mut leaves, &mut internals);
+        let bloom_bytes = self.bloom.as_ref().map_or(0, |bloom| bloom.heap_bytes());
+
+        TreeStats {
+            len: self.len(),
+            height: self.height(),
+            node_count: leaves.count + internals.count,
+            leaf_count: leaves.count,
+            avg_leaf_fill: leaves.average(),
+            avg_internal_fill: internals.average(),
+            estimated_heap_bytes: node_bytes + bloom_bytes,
+        }
+    }
+
+    /// Roughly how many bytes the tree has allocated on the heap: node allocations, the capacity
+    /// of every key and child vector, and the Bloom filter if there is one. Heap data owned by
+    /// the keys themselves is not included.
+    pub fn estimated_heap_bytes(&self) -> usize {
+        self.stats().estimated_heap_bytes
+    }
+}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Looks up `key` as `find` does, also returning how many key comparisons the descent made
+    /// (zero if the Bloom filter ruled the key out). Kept apart from `find` so ordinary lookups
+    /// don't pay for the counting.
+    pub fn find_instrumented(&self, key: &T) -> (bool, usize) {
+        if let Some(ref bloom) = self.bloom {
+            if !bloom.may_contain(key) {
+                return (false, 0);
+            }
+        }
+
+        let mut comparisons = 0;
+        let mut node = &self.root;
+        loop {
+            let position = node.keys().binary_search_by(|probe| {
+                comparisons += 1;
+                probe.cmp(key)
+            });
+            match (position, node.children()) {
+                (Ok(_), _) => return (true, comparisons),
+                (Err(index), Some(children)) => node = &children[index],
+                (Err(_), None) => return (false, comparisons),
+            }
+        }
+    }
+}
+
+impl<T: Key, A: NodeAlloc> BTree<T, A> {
+    /// Answers `any_in_range`, also returning how many nodes the descent looked at. Kept apart
+    /// from `any_in_range` for the same reason as `find_instrumented`.
+    pub fn any_in_range_instrumented<R: RangeBounds<T>>(&self, range: R) -> (bool, usize) {
+        let mut visited = 0;
+        let found = self.any_in_range_counting(&range, &mut visited);
+        (found, visited)
+    }
+
+    /// Builds `range`, also returning how many nodes were searched to find its two ends (an
+    /// unbounded end is found without a search). Each search stops above an internal child whose
+    /// smallest and largest keys, which it keeps a copy of, show the end lies past all of it, so
+    /// a query falling in a gap between clusters of keys can stop well short of the leaves. Kept
+    /// apart from `range` for the same reason as `find_instrumented`.
+    pub fn range_instrumented<R: RangeBounds<T>>(&self, range: R) -> (Range<'_, T, A>, usize) {
+        let mut visited = 0;
+        let range = self.range_counting(&range, &mut visited);
+        (range, visited)
+    }
+}
+
+// Adds every node of the subtree to the tallies, and returns the heap bytes it owns (not
+// counting the node itself).
+fn tally_node<T: Key, A: NodeAlloc>(
+    node: &Node<T, A>,
+    leaves: &mut Tally,
+    internals: &mut Tally,
+) -> usize {
+    match *node {
+        Node::Leaf(ref leaf) => {
+            leaves.add(leaf.num_keys);
+            leaf.keys.capacity() * mem::size_of::<T>()
+        }
+        Node::Internal(ref internal) => {
+            internals.add(internal.num_keys);
+            let own = internal.keys.capacity() * mem::size_of::<T>()
+                + internal.children.capacity() * mem::size_of::<Child<T, A>>();
+            internal.children.iter().fold(own, |total, child| {
+                total + mem::size_of::<Node<T, A>>() + tally_node(child, leaves, internals)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use super::super::{BTree, Fanout, Key, SplitPolicy, BTREE_MAX_KEYS, BTREE_MIN_KEYS};
+
+    #[test]
+    fn fill_efficiency_of_sequential_inserts() {
+        let mut median = BTree::new();
+        let mut right_biased =
+            BTree::with_fanout(Fanout::default().with_split_policy(SplitPolicy::RightBiased));
+        for key in 0..100_000_u32 {
+            median.insert(key);
+            right_biased.insert(key);
+        }
+
+        let efficiency = median.fill_efficiency();
+        assert!(efficiency > 0.45 && efficiency < 0.55, "{}", efficiency);
+        assert!(right_biased.fill_efficiency() > 0.95);
+        assert!(BTree::from_sorted(0..100_000_u32).fill_efficiency() > 0.95);
+        assert_eq!(BTree::<u32>::new().fill_efficiency(), 0.0);
+    }
+
+    #[test]
+    fn stats_are_consistent() {
+        let tree = BTree::from_sorted(0..100_000_u64);
+        let stats = tree.stats();
+        let fill = tree.fill_stats();
+
+        assert_eq!(stats.len, 100_000);
+        assert_eq!(stats.height, tree.height());
+        assert_eq!(stats.node_count, tree.node_count());
+        assert_eq!(stats.leaf_count, fill.leaf_count);
+        assert!(stats.leaf_count <= stats.node_count);
+        assert!(stats.avg_leaf_fill >= BTREE_MIN_KEYS as f64);
+        assert!(stats.avg_leaf_fill <= BTREE_MAX_KEYS as f64);
+        assert_eq!(stats.avg_internal_fill, fill.avg_internal_keys);
+        assert_eq!(stats.estimated_heap_bytes, tree.estimated_heap_bytes());
+        assert!(stats.estimated_heap_bytes >= stats.len * mem::size_of::<u64>());
+
+        let empty = BTree::<u32>::new().stats();
+        assert_eq!((empty.len, empty.height, empty.node_count), (0, 1, 1));
+        assert_eq!(empty.avg_internal_fill, 0.0);
+    }
+
+    #[test]
+    fn range_searches_stop_at_cached_bounds() {
+        // ten clusters of 10_000 consecutive keys, a million apart
+        let tree = BTree::from_sorted(
+            (0..10_u64).flat_map(|c| (0..10_000).map(move |k| c * 1_000_000 + k)),
+        );
+        let height = tree.height();
+
+        let (all, visited) = tree.range_instrumented(..);
+        assert_eq!((all.len(), visited), (tree.len(), 0));
+
+        // the cost is in finding the two ends, not in how many keys lie between them
+        let (cluster, visited) = tree.range_instrumented(3_000_000..3_010_000);
+        assert!(cluster.cloned().eq(3_000_000..3_010_000));
+        assert!(visited <= 2 * height);
+
+        let (gap, visited) = tree.range_instrumented(3_500_000..=3_600_000);
+        assert_eq!(gap.len(), 0);
+        assert!(visited <= 2 * height);
+        assert!(!tree.any_in_range(3_500_000..=3_600_000));
+    }
+
+    #[test]
+    fn cached_bounds_prune_gaps_between_clusters() {
+        // forty clusters of 300 keys, inserted in scattered order and then thinned out, so the
+        // bounds have been through splits, rotations and merges
+        let mut tree = BTree::with_fanout(Fanout::new(4, 4));
+        let keys = (0..12_000_u64)
+            .map(|i| (i * 7919) % 12_000)
+            .map(|i| (i / 300) * 1_000_000 + i % 300)
+            .collect::<Vec<_>>();
+        for &key in keys.iter() {
+            tree.insert(key);
+        }
+        for key in keys.iter().filter(|key| *key % 7 == 0) {
+            tree.remove(key);
+        }
+        assert_eq!(tree.check_invariants(), Ok(()));
+        let height = tree.height();
+        assert!(height >= 5);
+
+        let (cluster, visited) = tree.range_instrumented(17_000_000..17_000_300);
+        assert_eq!(
+            cluster.len(),
+            300 - (0..300).filter(|key| key % 7 == 3).count()
+        );
+        assert!(visited <= 2 * height);
+
+        // a search for a key in the gap just below a separator stops above the child to the
+        // separator's left, whose bounds show it holds nothing that high, unless it is a leaf
+        let sorted = tree.iter().cloned().collect::<Vec<_>>();
+        let mut stopped = 0;
+        for separator in tree.separators() {
+            let below = sorted[tree.rank(separator) - 1];
+            if below + 1 < *separator {
+                let (mut range, visited) = tree.range_instrumented(..=below + 1);
+                assert_eq!(range.next_back(), Some(&below));
+                assert!(visited <= height);
+                stopped += (visited < height) as usize;
+            }
+        }
+        assert!(stopped > 0);
+
+        // with only the separators to go on, every gap would cost a full path down for each end;
+        // the bounds stop the searches wherever a key next to the gap is a separator
+        let (mut searched, mut descended) = (0, 0);
+        for gap in 0..39 {
+            let gap = gap * 1_000_000 + 300..(gap + 1) * 1_000_000;
+            let (range, visited) = tree.range_instrumented(gap.clone());
+            assert_eq!(range.len(), 0);
+            searched += visited;
+            let (found, visited) = tree.any_in_range_instrumented(gap);
+            assert!(!found);
+            assert!(visited <= height);
+            descended += visited;
+        }
+        assert!(searched < 39 * 2 * height, "{} for 39 gaps", searched);
+        assert!(descended < 39 * height, "{} for 39 gaps", descended);
+    }
+
+    #[test]
+    fn find_comparisons_scale_with_height() {
+        // a binary search over at most 30 keys, with a little slack for the final equality check
+        let per_level = 6;
+
+        for n in [10_u64, 1000, 100_000, 1_000_000].iter() {
+            let tree = BTree::from_sorted(0..*n);
+            let bound = tree.height() * per_level;
+
+            for probe in (0..*n + 5).step_by(*n as usize / 10 + 1) {
+                let (found, comparisons) = tree.find_instrumented(&probe);
+                assert_eq!(found, probe < *n);
+                assert!(comparisons >= 1);
+                assert!(comparisons <= bound, "{} > {}", comparisons, bound);
+            }
+        }
+
+        assert_eq!(BTree::from_sorted(0..10_u64).height(), 1);
+        assert_eq!(BTree::from_sorted(0..1_000_000_u64).height(), 5);
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    struct Wide([u64; 8]);
+
+    impl Key for Wide {}
+
+    #[test]
+    fn empty_trees_allocate_nothing() {
+        let trees = (0..10_000)
+            .map(|_| BTree::<Wide>::new())
+            .collect::<Vec<_>>();
+        let total = trees
+            .iter()
+            .map(|tree| tree.estimated_heap_bytes())
+            .sum::<usize>();
+        assert_eq!(total, 0);
+    }
+
+    #[test]
+    fn leaves_grow_no_further_than_a_split() {
+        let key_size = mem::size_of::<Wide>();
+        let mut tree = BTree::new();
+
+        tree.insert(Wide([0; 8]));
+        assert_eq!(tree.estimated_heap_bytes(), 4 * key_size);
+
+        for i in 1..30 {
+            tree.insert(Wide([i; 8]));
+        }
+        // a full leaf, holding exactly room for the key which makes it split
+        assert_eq!(tree.estimated_heap_bytes(), BTREE_MAX_KEYS * key_size);
+    }
+
+    #[test]
+    fn fill_stats_single_leaf() {
+        let mut tree = BTree::<u32>::new();
+        for i in 0..10 {
+            tree.insert(i);
+        }
+
+        let stats = tree.fill_stats();
+        assert_eq!(stats.node_count, 1);
+        assert_eq!(stats.leaf_count, 1);
+        assert_eq!((stats.min_leaf_keys, stats.max_leaf_keys), (10, 10));
+        assert_eq!(stats.internal_count, 0);
+        assert_eq!(stats.avg_internal_keys, 0.0);
+    }
+
+    #[test]
+    fn fill_stats_sequential_inserts() {
+        let mut tree = BTree::<u64>::new();
+        for i in 0..100_000 {
+            tree.insert(i);
+        }
+
+        let stats = tree.fill_stats();
+        assert_eq!(stats.node_count, stats.leaf_count + stats.internal_count);
+        assert!(stats.min_leaf_keys >= BTREE_MIN_KEYS);
+        assert!(stats.max_leaf_keys < BTREE_MAX_KEYS);
+        assert!(stats.avg_leaf_keys >= BTREE_MIN_KEYS as f64);
+        assert!(stats.avg_leaf_keys < BTREE_MAX_KEYS as f64);
+
+        // sequential inserts leave nearly every leaf exactly half full
+        assert!(stats.avg_leaf_keys < (BTREE_MIN_KEYS + 1) as f64);
+
+        let total_internal = stats.avg_internal_keys * stats.internal_count as f64;
+        let total_leaf = stats.avg_leaf_keys * stats.leaf_count as f64;
+        assert_eq!((total_internal + total_leaf).round() as usize, tree.len());
+    }
+
+    #[test]
+    fn fill_stats_packed_tree() {
+        let stats = BTree::from_sorted(0..100_000_u64).fill_stats();
+
+        assert!(stats.min_leaf_keys >= BTREE_MAX_KEYS - 2);
+        assert!(stats.avg_leaf_keys > (BTREE_MAX_KEYS - 2) as f64);
+        assert!(stats.avg_internal_keys > (BTREE_MAX_KEYS - 3) as f64);
+    }
+}
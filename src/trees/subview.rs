@@ -0,0 +1,91 @@
+use std::ops::{Bound, RangeBounds};
+
+use super::{BTree, Global, Key, NodeAlloc, Range};
+
+/// A read-only window onto the keys of a `BTree` within a range. Keys outside the range are
+/// invisible through the view, even though they are still in the tree.
+pub struct SubView<'a, T: 'a + Key, A: 'a + NodeAlloc = Global> {
+    tree: &'a BTree<T, A>,
+    start: Bound<T>,
+    end: Bound<T>,
+}
+
+impl<T: Key + Clone, A: NodeAlloc> BTree<T, A> {
+    /// A view restricted to the keys within `range`, for handing to code which should see
+    /// nothing else. The bounds are cloned into the view.
+    pub fn subrange<R: RangeBounds<T>>(&self, range: R) -> SubView<'_, T, A> {
+        SubView {
+            tree: self,
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+        }
+    }
+}
+
+impl<'a, T: 'a + Key, A: 'a + NodeAlloc> SubView<'a, T, A> {
+    /// Whether `key` is within the view's range and in the tree.
+    pub fn find(&self, key: &T) -> bool {
+        self.bounds().contains(key) && self.tree.find(key)
+    }
+
+    /// The keys within the view's range, in ascending order (or descending via `rev`).
+    pub fn iter(&self) -> Range<'a, T, A> {
+        self.tree.range(self.bounds())
+    }
+
+    /// Number of keys within the view's range, worked out from the subtree counts.
+    pub fn count(&self) -> usize {
+        self.iter().len()
+    }
+
+    fn bounds(&self) -> (Bound<&T>, Bound<&T>) {
+        (self.start.as_ref(), self.end.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use super::super::BTree;
+
+    #[test]
+    fn subview_hides_keys_outside_the_range() {
+        let tree = BTree::from_sorted(0..10_000_u32);
+        let view = tree.subrange(2000..3000);
+
+        for key in [0, 1999, 3000, 3001, 9999].iter() {
+            assert!(tree.find(key));
+            assert!(!view.find(key), "{} is outside the view", key);
+        }
+        for key in [2000, 2500, 2999].iter() {
+            assert!(view.find(key));
+        }
+        assert!(!view.find(&20_000));
+    }
+
+    #[test]
+    fn subview_iterates_and_counts_within_the_range() {
+        let tree = BTree::from_sorted((0..5000_u64).map(|i| i * 2));
+
+        let view = tree.subrange(101..=201);
+        assert_eq!(view.count(), 50);
+        assert!(view.iter().cloned().eq((51..=100).map(|i| i * 2)));
+        assert_eq!(view.iter().next_back(), Some(&200));
+
+        let open = tree.subrange((Bound::Excluded(9990), Bound::Unbounded));
+        assert_eq!(
+            open.iter().cloned().collect::<Vec<_>>(),
+            vec![9992, 9994, 9996, 9998]
+        );
+        assert!(!open.find(&9990));
+
+        let everything = tree.subrange(..);
+        assert_eq!(everything.count(), tree.len());
+        assert!(everything.find(&0));
+
+        let backwards = tree.subrange((Bound::Included(300), Bound::Excluded(200)));
+        assert_eq!(backwards.count(), 0);
+        assert!(!backwards.find(&250));
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use super::persist::corrupt;
+use super::{BTree, Key, NodeAlloc};
+
+impl<T: Key + Display, A: NodeAlloc> BTree<T, A> {
+    /// Writes every key in ascending order, one per line, for eyeballing or feeding to shell
+    /// tools; `load_text` reads it back.
+    pub fn dump_text<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for key in self.iter() {
+            writeln!(w, "{}", key)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Key + FromStr> BTree<T>
+where
+    T::Err: Display,
+{
+    /// Reads a dump written by `dump_text`. Surrounding whitespace and blank lines are ignored.
+    ///
+    /// A line which doesn't parse, or a key which is not greater than the one before it, is an
+    /// `InvalidData` error naming the (1-based) line.
+    pub fn load_text<R: BufRead>(r: R) -> io::Result<BTree<T>> {
+        let mut keys: Vec<T> = Vec::new();
+
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+            let line_number = i + 1;
+            let field = line.trim();
+            if field.is_empty() {
+                continue;
+            }
+
+            let key = field.parse::<T>().map_err(|e| {
+                corrupt(format!(
+                    "line {}: {:?} is not a valid key ({})",
+                    line_number, field, e
+                ))
+            })?;
+            if keys.last().is_some_and(|previous| *previous >= key) {
+                return Err(corrupt(format!(
+                    "line {}: {:?} is not greater than the key before it",
+                    line_number, field
+                )));
+            }
+            keys.push(key);
+        }
+
+        Ok(BTree::from_sorted(keys))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::super::BTree;
+
+    #[test]
+    fn text_round_trip() {
+        let tree = BTree::from_sorted((-500..500_i64).map(|i| i * 7));
+
+        let mut dump = Vec::new();
+        tree.dump_text(&mut dump).unwrap();
+        assert!(dump.starts_with(b"-3500\n-3493\n"));
+
+        let loaded = BTree::<i64>::load_text(&dump[..]).unwrap();
+        assert_eq!(loaded, tree);
+        assert_eq!(loaded.check_invariants(), Ok(()));
+
+        let mut empty = Vec::new();
+        BTree::<u32>::new().dump_text(&mut empty).unwrap();
+        assert!(empty.is_empty());
+        assert!(BTree::<u32>::load_text(&empty[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn text_tolerates_whitespace() {
+        let tree = BTree::<u32>::load_text(" 1\n\n2 \n  3\n".as_bytes()).unwrap();
+        assert_eq!(tree, BTree::from_sorted(1..4));
+    }
+
+    #[test]
+    fn text_rejects_bad_line() {
+        let err = BTree::<u64>::load_text("1\n2\nthree\n4\n".as_bytes()).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("line 3"), "{}", err);
+        assert!(err.to_string().contains("three"), "{}", err);
+    }
+
+    #[test]
+    fn text_rejects_unsorted_lines() {
+        let err = BTree::<u64>::load_text("1\n5\n5\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 3"), "{}", err);
+
+        let err = BTree::<u64>::load_text("1\n5\n4\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("not greater"), "{}", err);
+    }
+}
@@ -0,0 +1,30 @@
+// The core tree as a `no_std` user gets it. The library's own unit tests always build it with
+// `std`, so run this with `cargo test --no-default-features --test no_std_core` to have the
+// library compiled against `core` and `alloc` alone; with the default features it checks the
+// same things against the ordinary build.
+extern crate catdb_lib;
+
+use catdb_lib::trees::BTree;
+
+#[test]
+fn insert_find_and_remove_under_alloc() {
+    let mut tree = BTree::new();
+    for i in 0..10_000_u64 {
+        assert!(tree.insert((i * 7919) % 10_000 * 2));
+    }
+    assert_eq!(tree.len(), 10_000);
+    assert!(!tree.insert(0));
+
+    for key in 0..20_000 {
+        assert_eq!(tree.find(&key), key % 2 == 0, "{}", key);
+    }
+    assert!(tree.iter().cloned().eq((0..10_000).map(|i| i * 2)));
+    assert!(tree.range(100..110).cloned().eq([100, 102, 104, 106, 108].iter().cloned()));
+
+    for key in (0..20_000).step_by(4) {
+        assert!(tree.remove(&key));
+    }
+    assert_eq!(tree.len(), 5_000);
+    assert!(!tree.find(&4) && tree.find(&6));
+    assert_eq!(tree.check_invariants(), Ok(()));
+}